@@ -0,0 +1,11 @@
+struct Gradient {
+    top: vec4<f32>,
+    bottom: vec4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> gradient: Gradient;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return mix(gradient.top, gradient.bottom, in.tex_coords.y);
+}
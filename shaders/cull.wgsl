@@ -0,0 +1,66 @@
+// Frustum-culls one object's instances by bounding sphere and compacts the
+// survivors, counting them into the instance_count of every mesh's indirect args
+
+struct Frustum {
+    // xyz is the inward normal, w the offset; normalized, so dot + w is a distance
+    planes: array<vec4<f32>, 6>,
+}
+@group(0) @binding(0)
+var<uniform> frustum: Frustum;
+
+struct Params {
+    // Bounding sphere of the model in its own space
+    center: vec3<f32>,
+    radius: f32,
+    instance_count: u32,
+    mesh_count: u32,
+}
+@group(0) @binding(1)
+var<uniform> params: Params;
+
+// InstanceRaw: model matrix columns, then the normal matrix's, tightly packed
+const INSTANCE_FLOATS: u32 = 25u;
+// DrawIndexedIndirectArgs is 5 words; instance_count is the second
+const ARGS_WORDS: u32 = 5u;
+const INSTANCE_COUNT_WORD: u32 = 1u;
+
+@group(0) @binding(2)
+var<storage, read> instances: array<f32>;
+@group(0) @binding(3)
+var<storage, read_write> culled: array<f32>;
+@group(0) @binding(4)
+var<storage, read_write> args: array<atomic<u32>>;
+
+fn column(base: u32) -> vec4<f32> {
+    return vec4<f32>(instances[base], instances[base + 1u], instances[base + 2u], instances[base + 3u]);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= params.instance_count {
+        return;
+    }
+    let base = i * INSTANCE_FLOATS;
+    let model = mat4x4<f32>(column(base), column(base + 4u), column(base + 8u), column(base + 12u));
+
+    let center = (model * vec4<f32>(params.center, 1.0)).xyz;
+    // The largest axis scale keeps the sphere conservative under non-uniform scaling
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = params.radius * scale;
+    for (var p = 0u; p < 6u; p++) {
+        let plane = frustum.planes[p];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return;
+        }
+    }
+
+    let slot = atomicAdd(&args[INSTANCE_COUNT_WORD], 1u);
+    for (var m = 1u; m < params.mesh_count; m++) {
+        atomicAdd(&args[m * ARGS_WORDS + INSTANCE_COUNT_WORD], 1u);
+    }
+    let out = slot * INSTANCE_FLOATS;
+    for (var f = 0u; f < INSTANCE_FLOATS; f++) {
+        culled[out + f] = instances[base + f];
+    }
+}
@@ -0,0 +1,36 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// One segment per instance
+struct SegmentInput {
+    @location(0) a_position: vec3<f32>,
+    @location(1) a_color: vec3<f32>,
+    @location(2) b_position: vec3<f32>,
+    @location(3) b_color: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+}
+
+// Line ends are already in world space; each segment is widened into a quad
+// facing the screen by line_quad_corner
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, in: SegmentInput) -> VertexOutput {
+    let a = camera.view_proj * vec4<f32>(in.a_position, 1.0);
+    let b = camera.view_proj * vec4<f32>(in.b_position, 1.0);
+    var out: VertexOutput;
+    out.clip_position = line_quad_corner(a, b, vertex_index);
+    out.color = mix(in.a_color, in.b_color, CORNERS[vertex_index].x);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
@@ -0,0 +1,25 @@
+// depth_texture is prepended by debug_view::DepthOverlay, multisampled or not to
+// match the scene's depth buffer
+
+struct DepthRange {
+    // View-space distance of the near and far planes
+    znear: f32,
+    zfar: f32,
+    // NDC depth the near and far planes end up at; swapped under reverse-Z
+    ndc_near: f32,
+    ndc_far: f32,
+}
+@group(0) @binding(1)
+var<uniform> range: DepthRange;
+
+// Stored depth turned back into view-space distance, as gray from white at the
+// near plane to black at the far plane and wherever nothing was drawn
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let depth = textureLoad(depth_texture, vec2<i32>(in.clip_position.xy), 0);
+    // 0 at near, 1 at far whichever way the depth buffer runs
+    let t = clamp((depth - range.ndc_near) / (range.ndc_far - range.ndc_near), 0.0, 1.0);
+    let distance = range.znear * range.zfar / (range.zfar - t * (range.zfar - range.znear));
+    let gray = 1.0 - (distance - range.znear) / (range.zfar - range.znear);
+    return vec4<f32>(vec3<f32>(gray), 1.0);
+}
@@ -0,0 +1,56 @@
+// Shared by every pipeline drawing screen-space-wide lines; prepended to their
+// own source, which binds the camera at group 0
+
+struct LineStyle {
+    // Of the view being drawn, in pixels
+    viewport_size: vec2<f32>,
+    // In pixels
+    width: f32,
+}
+@group(1) @binding(0)
+var<uniform> style: LineStyle;
+
+// Smallest w kept in front of the camera when an end is clipped
+const NEAR_W: f32 = 1e-4;
+
+// Two counter-clockwise triangles: x picks the end, y the side of the line
+const CORNERS = array<vec2<f32>, 6>(
+    vec2<f32>(0.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(0.0, 1.0),
+    vec2<f32>(0.0, 1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+);
+
+// Clip-space position of corner `corner_index` (below 6) of the quad widening
+// the segment from `a` to `b`, both in clip space, to `style.width` pixels
+fn line_quad_corner(a_clip: vec4<f32>, b_clip: vec4<f32>, corner_index: u32) -> vec4<f32> {
+    var a = a_clip;
+    var b = b_clip;
+    // Pull an end behind the camera forward along the segment, so the
+    // perspective divide below stays meaningful; fully hidden segments collapse
+    if a.w < NEAR_W && b.w < NEAR_W {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    if a.w < NEAR_W {
+        a = mix(a, b, (NEAR_W - a.w) / (b.w - a.w));
+    } else if b.w < NEAR_W {
+        b = mix(b, a, (NEAR_W - b.w) / (a.w - b.w));
+    }
+
+    let corner = CORNERS[corner_index];
+    let screen_dir = (b.xy / b.w - a.xy / a.w) * style.viewport_size;
+    // A segment seen end-on has no direction; any will do for its dot
+    var dir = vec2<f32>(1.0, 0.0);
+    if dot(screen_dir, screen_dir) > 0.0 {
+        dir = normalize(screen_dir);
+    }
+    let normal = vec2<f32>(-dir.y, dir.x);
+    // Pixels to NDC, where the viewport spans 2 units
+    let offset = normal * corner.y * style.width / style.viewport_size;
+
+    let position = mix(a, b, corner.x);
+    return position + vec4<f32>(offset * position.w, 0.0, 0.0);
+}
+
@@ -0,0 +1,50 @@
+// Writes (object index + 1, instance index) so 0 means nothing was hit
+struct PickCamera {
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> pick_camera: PickCamera;
+
+struct PickObject {
+    id: u32,
+}
+@group(1) @binding(0)
+var<uniform> pick_object: PickObject;
+
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) instance_index: u32,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = pick_camera.view_proj * model_matrix * vec4<f32>(model.position.xyz, 1.0);
+    out.instance_index = instance_index;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec2<u32> {
+    return vec2<u32>(pick_object.id, in.instance_index);
+}
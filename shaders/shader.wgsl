@@ -12,12 +12,41 @@ struct Light {
 @group(2) @binding(0)
 var<uniform> light: Light;
 
+struct Shadow {
+    view_proj: mat4x4<f32>,
+    bias: f32,
+    normal_bias: f32,
+    pcf_samples: u32,
+    enabled: u32,
+}
+@group(2) @binding(1)
+var t_shadow: texture_depth_2d;
+@group(2) @binding(2)
+var s_shadow: sampler_comparison;
+@group(2) @binding(3)
+var<uniform> shadow: Shadow;
+
+struct Fog {
+    color: vec3<f32>,
+    // 0 off, 1 linear, 2 exponential
+    mode: u32,
+    start: f32,
+    end: f32,
+    density: f32,
+}
+@group(2) @binding(4)
+var<uniform> fog: Fog;
+
 struct VertexInput {
-    @location(0) position: vec3<f32>,
+    // Four components so compact vertices (Float16x4, Snorm16x4) bind as well;
+    // Float32x3 data reads back with w = 1
+    @location(0) position: vec4<f32>,
     @location(1) tex_coords: vec2<f32>,
-    @location(2) normal: vec3<f32>,
-    @location(3) tangent: vec3<f32>,
-    @location(4) bitangent: vec3<f32>,
+    @location(2) normal: vec4<f32>,
+    @location(3) tangent: vec4<f32>,
+    @location(4) bitangent: vec4<f32>,
+    @location(12) color: vec4<f32>,
+    @location(13) tex_coords_1: vec2<f32>,
 }
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
@@ -35,6 +64,9 @@ struct VertexOutput {
     @location(1) tangent_position: vec3<f32>,
     @location(2) tangent_light_position: vec3<f32>,
     @location(3) tangent_view_position: vec3<f32>,
+    @location(4) color: vec3<f32>,
+    @location(5) world_position: vec3<f32>,
+    @location(6) world_normal: vec3<f32>,
 }
 
 @vertex
@@ -55,23 +87,26 @@ fn vs_main(
     );
 
     // Construct the tangent matrix
-    let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
+    let world_normal = normalize(normal_matrix * model.normal.xyz);
+    let world_tangent = normalize(normal_matrix * model.tangent.xyz);
+    let world_bitangent = normalize(normal_matrix * model.bitangent.xyz);
     let tangent_matrix = transpose(mat3x3<f32>(
         world_tangent,
         world_bitangent,
         world_normal,
     ));
 
-    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    let world_position = model_matrix * vec4<f32>(model.position.xyz, 1.0);
 
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
     out.tex_coords = model.tex_coords;
+    out.color = model.color.rgb;
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tangent_matrix * light.position;
+    out.world_position = world_position.xyz;
+    out.world_normal = world_normal;
     return out;
 }
 
@@ -84,9 +119,61 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
+struct Material {
+    emissive: vec3<f32>,
+    shininess: f32,
+    specular: vec3<f32>,
+    opacity: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: Material;
+
+// Fraction of `pcf_samples` x `pcf_samples` shadow map taps that see the light
+fn shadow_factor(world_position: vec3<f32>, world_normal: vec3<f32>) -> f32 {
+    if shadow.enabled == 0u {
+        return 1.0;
+    }
+    let clip = shadow.view_proj * vec4<f32>(world_position + world_normal * shadow.normal_bias, 1.0);
+    if clip.w <= 0.0 {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    // Outside the light's frustum counts as lit
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let depth = ndc.z - shadow.bias;
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+    let radius = i32(shadow.pcf_samples / 2u);
+    var lit = 0.0;
+    var taps = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, depth);
+            taps += 1.0;
+        }
+    }
+    return lit / taps;
+}
+
+// How much of the fog color replaces the surface at this distance from the camera
+fn fog_factor(world_position: vec3<f32>) -> f32 {
+    let distance = distance(world_position, camera.view_pos.xyz);
+    if fog.mode == 1u {
+        return clamp((distance - fog.start) / (fog.end - fog.start), 0.0, 1.0);
+    }
+    if fog.mode == 2u {
+        return 1.0 - exp(-fog.density * distance);
+    }
+    return 0.0;
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords) * vec4<f32>(in.color, 1.0);
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
     
     // We don't need (or want) much ambient light, so 0.1 is fine
@@ -102,10 +189,12 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
     let diffuse_color = light.color * diffuse_strength;
 
-    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), material.shininess);
+    let specular_color = specular_strength * light.color * material.specular;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    let lit = shadow_factor(in.world_position, normalize(in.world_normal));
+    let shaded = (ambient_color + (diffuse_color + specular_color) * lit) * object_color.xyz + material.emissive;
+    let result = mix(shaded, fog.color, fog_factor(in.world_position));
 
-    return vec4<f32>(result, object_color.a);
+    return vec4<f32>(result, object_color.a * material.opacity);
 }
\ No newline at end of file
@@ -0,0 +1,30 @@
+struct ShadowCamera {
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> shadow_camera: ShadowCamera;
+
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+// Depth only; there's no fragment stage
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return shadow_camera.view_proj * model_matrix * vec4<f32>(model.position.xyz, 1.0);
+}
@@ -0,0 +1,12 @@
+@group(0) @binding(0)
+var shadow_map: texture_depth_2d;
+
+// Raw depth as gray: black at the light's near plane, white at its far plane and
+// wherever nothing was drawn
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(shadow_map));
+    let texel = vec2<i32>(clamp(in.tex_coords * size, vec2<f32>(0.0), size - 1.0));
+    let depth = textureLoad(shadow_map, texel, 0);
+    return vec4<f32>(vec3<f32>(depth), 1.0);
+}
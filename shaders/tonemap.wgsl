@@ -0,0 +1,42 @@
+struct ToneMapParams {
+    exposure: f32,
+    // 0 = none (clamp), 1 = Reinhard, 2 = ACES
+    mode: u32,
+}
+@group(0) @binding(0)
+var hdr_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var hdr_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> params: ToneMapParams;
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(hdr_texture, hdr_sampler, in.tex_coords);
+    let color = hdr.rgb * params.exposure;
+
+    var mapped: vec3<f32>;
+    switch params.mode {
+        case 1u: {
+            mapped = color / (color + vec3<f32>(1.0));
+        }
+        case 2u: {
+            mapped = aces(color);
+        }
+        default: {
+            mapped = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+    // The sRGB swapchain does the gamma encoding
+    return vec4<f32>(mapped, hdr.a);
+}
@@ -0,0 +1,67 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// How the mesh's buffers are packed, see wireframe::MeshLayoutUniform
+struct MeshLayout {
+    // In 4-byte words
+    vertex_stride: u32,
+    // Positions are half floats (VertexFormatOption::Compact)
+    compact: u32,
+    // Two 16-bit indices per word
+    index_u16: u32,
+}
+@group(2) @binding(0)
+var<uniform> mesh: MeshLayout;
+@group(2) @binding(1)
+var<storage, read> vertices: array<u32>;
+@group(2) @binding(2)
+var<storage, read> indices: array<u32>;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+fn index_at(i: u32) -> u32 {
+    if mesh.index_u16 != 0u {
+        return (indices[i / 2u] >> ((i % 2u) * 16u)) & 0xffffu;
+    }
+    return indices[i];
+}
+
+fn position_of(vertex: u32) -> vec3<f32> {
+    let base = vertex * mesh.vertex_stride;
+    if mesh.compact != 0u {
+        return vec3<f32>(unpack2x16float(vertices[base]), unpack2x16float(vertices[base + 1u]).x);
+    }
+    return bitcast<vec3<f32>>(vec3<u32>(vertices[base], vertices[base + 1u], vertices[base + 2u]));
+}
+
+// Drawn without vertex buffers: every triangle gets three edges of six
+// vertices each, whose ends are read straight from the mesh's buffers
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let triangle = vertex_index / 18u;
+    let edge = (vertex_index / 6u) % 3u;
+    let a = position_of(index_at(triangle * 3u + edge));
+    let b = position_of(index_at(triangle * 3u + (edge + 1u) % 3u));
+    let transform = camera.view_proj * model_matrix;
+    return line_quad_corner(transform * vec4<f32>(a, 1.0), transform * vec4<f32>(b, 1.0), vertex_index % 6u);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.05, 0.05, 0.05, 1.0);
+}
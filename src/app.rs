@@ -3,33 +3,54 @@ use winit::{
   application::ApplicationHandler,
   event::*,
   event_loop::ActiveEventLoop,
-  keyboard::PhysicalKey,
+  keyboard::{KeyCode, PhysicalKey},
   window::Window,
 };
 
 #[cfg(target_arch = "wasm32")]
 use winit::event_loop::EventLoop;
 
+use crate::config::RendererConfig;
+use crate::input::InputAction;
 use crate::state::State;
 
 pub struct App {
   #[cfg(target_arch = "wasm32")]
   proxy: Option<winit::event_loop::EventLoopProxy<State>>,
+  // Hands the state back after `try_recreate_device` ran on the browser's executor
+  #[cfg(target_arch = "wasm32")]
+  recreate_proxy: winit::event_loop::EventLoopProxy<State>,
   state: Option<State>,
+  config: RendererConfig,
   last_time: instant::Instant,
 }
 
 impl App {
   pub fn new(#[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>) -> Self {
     #[cfg(target_arch = "wasm32")]
-    let proxy = Some(event_loop.create_proxy());
+    let proxy = event_loop.create_proxy();
     Self {
       state: None,
       #[cfg(target_arch = "wasm32")]
-      proxy,
+      proxy: Some(proxy.clone()),
+      #[cfg(target_arch = "wasm32")]
+      recreate_proxy: proxy,
+      config: RendererConfig::default(),
       last_time: instant::Instant::now(),
     }
   }
+
+  pub fn with_config(mut self, config: RendererConfig) -> Self {
+    self.config = config;
+    self
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for App {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl ApplicationHandler<State> for App {
@@ -55,16 +76,17 @@ impl ApplicationHandler<State> for App {
 
     #[cfg(not(target_arch = "wasm32"))]
     {
-      self.state = Some(pollster::block_on(State::new(window)).unwrap());
+      self.state = Some(pollster::block_on(State::new(window, self.config.clone())).unwrap());
     }
 
     #[cfg(target_arch = "wasm32")]
     {
       if let Some(proxy) = self.proxy.take() {
+        let config = self.config.clone();
         wasm_bindgen_futures::spawn_local(async move {
           assert!(proxy
             .send_event(
-              State::new(window)
+              State::new(window, config)
                 .await
                 .expect("Unable to create canvas!!!")
             )
@@ -93,13 +115,10 @@ impl ApplicationHandler<State> for App {
     } else {
       return;
     };
-    match event {
-      DeviceEvent::MouseMotion { delta: (dx, dy) } => {
-        if state.mouse_pressed {
-          state.camera_controller.handle_mouse(dx, dy);
-        }
+    if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+      if state.mouse_pressed {
+        state.camera_controller.handle_mouse(dx, dy);
       }
-      _ => {}
     }
   }
 
@@ -109,15 +128,47 @@ impl ApplicationHandler<State> for App {
     _window_id: winit::window::WindowId,
     event: WindowEvent,
   ) {
+    // Recreation can't block here, so the state is away until it finishes and
+    // comes back through `user_event`, which requests the next redraw
+    #[cfg(target_arch = "wasm32")]
+    if event == WindowEvent::RedrawRequested && self.state.as_ref().is_some_and(State::is_device_lost) {
+      if let Some(mut state) = self.state.take() {
+        let proxy = self.recreate_proxy.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+          if let Err(e) = state.try_recreate_device().await {
+            log::error!("Unable to recreate the GPU device {}", e);
+          }
+          // Still lost, it's tried again on that redraw
+          let _ = proxy.send_event(state);
+        });
+      }
+      return;
+    }
+
     let state = match &mut self.state {
       Some(canvas) => canvas,
       None => return,
     };
 
+    if state.handle_window_event(&event) {
+      return;
+    }
+
     match event {
       WindowEvent::CloseRequested => event_loop.exit(),
       WindowEvent::Resized(size) => state.resize(size.width, size.height),
       WindowEvent::RedrawRequested => {
+        if state.is_device_lost() {
+          #[cfg(not(target_arch = "wasm32"))]
+          if let Err(e) = pollster::block_on(state.try_recreate_device()) {
+            log::error!("Unable to recreate the GPU device {}", e);
+            // Try again next frame
+            state.request_redraw();
+          }
+          if state.is_device_lost() {
+            return;
+          }
+        }
         let dt = self.last_time.elapsed();
         self.last_time = instant::Instant::now();
         state.update(dt);
@@ -125,16 +176,23 @@ impl ApplicationHandler<State> for App {
           Ok(_) => {}
           // Reconfigure the surface if it's lost or outdated
           Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-              let size = state.window.inner_size();
-              state.resize(size.width, size.height);
+              state.reconfigure_surface();
           }
           Err(e) => {
               log::error!("Unable to render {}", e);
           }
         }
+        // Redraw continuously until paused; `set_paused(false)` restarts the loop
+        if !state.is_paused() {
+          state.request_redraw();
+        }
+      }
+      WindowEvent::MouseInput { state: btn_state, button, .. } => {
+        state.handle_mouse_button(button, btn_state == ElementState::Pressed);
+      }
+      WindowEvent::MouseWheel { delta, .. } => {
+        state.handle_mouse_scroll(&delta);
       }
-      WindowEvent::MouseInput { state: btn_state, button, .. } => state.handle_mouse_button(button, btn_state == ElementState::Pressed),
-      WindowEvent::MouseWheel { delta, .. } => state.handle_mouse_scroll(&delta),
       WindowEvent::KeyboardInput {
         event:
           KeyEvent {
@@ -143,7 +201,12 @@ impl ApplicationHandler<State> for App {
             ..
           },
         ..
-      } => state.handle_key(event_loop, code, key_state.is_pressed()),
+      } => {
+        let action = state.handle_key(code, key_state.is_pressed());
+        if action == InputAction::Unhandled && code == KeyCode::Escape && key_state.is_pressed() {
+          event_loop.exit();
+        }
+      }
       _ => {}
     }
   }
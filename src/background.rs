@@ -0,0 +1,198 @@
+use wgpu::util::DeviceExt;
+
+use crate::tonemap;
+
+// What the scene is drawn over. Colors are linear, like everything else rendered
+// before tone mapping
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+  SolidColor(wgpu::Color),
+  // Top and bottom colors, blended down the height of the window
+  VerticalGradient(wgpu::Color, wgpu::Color),
+}
+
+impl Default for Background {
+  fn default() -> Self {
+    Background::SolidColor(wgpu::Color {
+      r: 0.1,
+      g: 0.2,
+      b: 0.3,
+      a: 1.0,
+    })
+  }
+}
+
+fn to_array(color: wgpu::Color) -> [f32; 4] {
+  [color.r as f32, color.g as f32, color.b as f32, color.a as f32]
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GradientUniform {
+  top: [f32; 4],
+  bottom: [f32; 4],
+}
+
+// Solid colors are just the scene pass's clear; gradients are drawn by a
+// fullscreen triangle before it, which the scene pass then loads
+pub struct BackgroundPass {
+  background: Background,
+  pipeline: wgpu::RenderPipeline,
+  // Kept to rebuild the pipeline when the scene's sample count changes
+  layout: wgpu::PipelineLayout,
+  shader: wgpu::ShaderModule,
+  sample_count: u32,
+  uniform_buffer: wgpu::Buffer,
+  bind_group: wgpu::BindGroup,
+}
+
+impl BackgroundPass {
+  pub fn new(device: &wgpu::Device) -> Self {
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      entries: &[wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      }],
+      label: Some("background_bind_group_layout"),
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("Background Pipeline Layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some("Background Shader"),
+      source: wgpu::ShaderSource::Wgsl(
+        concat!(include_str!("../shaders/fullscreen.wgsl"), include_str!("../shaders/background.wgsl")).into(),
+      ),
+    });
+    let pipeline = Self::create_pipeline(device, &layout, &shader, 1);
+
+    let background = Background::default();
+    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Background Buffer"),
+      contents: bytemuck::cast_slice(&[Self::uniform(background)]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &bind_group_layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: uniform_buffer.as_entire_binding(),
+      }],
+      label: Some("background_bind_group"),
+    });
+
+    Self {
+      background,
+      pipeline,
+      layout,
+      shader,
+      sample_count: 1,
+      uniform_buffer,
+      bind_group,
+    }
+  }
+
+  fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    sample_count: u32,
+  ) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Background Pipeline"),
+      layout: Some(layout),
+      vertex: wgpu::VertexState {
+        module: shader,
+        entry_point: Some("vs_main"),
+        buffers: &[],
+        compilation_options: Default::default(),
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: shader,
+        entry_point: Some("fs_main"),
+        targets: &[Some(wgpu::ColorTargetState {
+          format: tonemap::HDR_FORMAT,
+          blend: Some(wgpu::BlendState::REPLACE),
+          write_mask: wgpu::ColorWrites::ALL,
+        })],
+        compilation_options: Default::default(),
+      }),
+      primitive: wgpu::PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: wgpu::MultisampleState {
+        count: sample_count,
+        ..Default::default()
+      },
+      multiview: None,
+      cache: None,
+    })
+  }
+
+  fn uniform(background: Background) -> GradientUniform {
+    let (top, bottom) = match background {
+      Background::SolidColor(color) => (color, color),
+      Background::VerticalGradient(top, bottom) => (top, bottom),
+    };
+    GradientUniform {
+      top: to_array(top),
+      bottom: to_array(bottom),
+    }
+  }
+
+  pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+    if self.sample_count != sample_count {
+      self.sample_count = sample_count;
+      self.pipeline = Self::create_pipeline(device, &self.layout, &self.shader, sample_count);
+    }
+  }
+
+  pub fn background(&self) -> Background {
+    self.background
+  }
+
+  pub fn set_background(&mut self, queue: &wgpu::Queue, background: Background) {
+    self.background = background;
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[Self::uniform(background)]));
+  }
+
+  // What the scene pass should clear to, or `None` if `run` already filled the target
+  pub fn clear_color(&self) -> Option<wgpu::Color> {
+    match self.background {
+      Background::SolidColor(color) => Some(color),
+      Background::VerticalGradient(..) => None,
+    }
+  }
+
+  pub fn run(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+    if self.clear_color().is_some() {
+      return;
+    }
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("Background Pass"),
+      color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+        view: target,
+        resolve_target: None,
+        ops: wgpu::Operations {
+          load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+          store: wgpu::StoreOp::Store,
+        },
+        depth_slice: None,
+      })],
+      depth_stencil_attachment: None,
+      occlusion_query_set: None,
+      timestamp_writes: None,
+    });
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, &self.bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+  }
+}
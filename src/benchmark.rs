@@ -0,0 +1,71 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Rad};
+
+use crate::bounds::Aabb;
+use crate::camera::Camera;
+
+// Distribution of per-frame times in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameTimes {
+  pub mean_ms: f32,
+  pub median_ms: f32,
+  pub p99_ms: f32,
+}
+
+impl FrameTimes {
+  fn from_samples(mut samples: Vec<f32>) -> Option<Self> {
+    if samples.is_empty() {
+      return None;
+    }
+    samples.sort_by(f32::total_cmp);
+    let n = samples.len();
+    let median_ms = if n.is_multiple_of(2) {
+      (samples[n / 2 - 1] + samples[n / 2]) / 2.0
+    } else {
+      samples[n / 2]
+    };
+    // Nearest-rank percentile
+    let p99_ms = samples[(n * 99).div_ceil(100) - 1];
+    Some(Self {
+      mean_ms: samples.iter().sum::<f32>() / n as f32,
+      median_ms,
+      p99_ms,
+    })
+  }
+}
+
+// Result of `State::run_benchmark`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BenchmarkReport {
+  pub frames: u32,
+  // Wall time from encoding a frame to the GPU finishing it
+  pub cpu: FrameTimes,
+  // Shadow, scene and tone map passes, measured with timestamp queries; `None`
+  // without Features::TIMESTAMP_QUERY
+  pub gpu: Option<FrameTimes>,
+}
+
+impl BenchmarkReport {
+  pub(crate) fn new(frames: u32, cpu_samples: Vec<f32>, gpu_samples: Vec<f32>) -> Self {
+    Self {
+      frames,
+      cpu: FrameTimes::from_samples(cpu_samples).unwrap_or_default(),
+      gpu: FrameTimes::from_samples(gpu_samples),
+    }
+  }
+}
+
+// Height of the orbit above the scene's center, as a fraction of its distance
+const ORBIT_ELEVATION: f32 = 0.3;
+
+// The benchmark's fixed camera path: one full orbit around `bounds` over `frames`,
+// far enough out for the whole scene to stay in view
+pub(crate) fn orbit_camera(bounds: &Aabb, frame: u32, frames: u32, min_half_fov: Rad<f32>) -> Camera {
+  let center = bounds.center();
+  let distance = bounds.radius() / min_half_fov.0.sin();
+  let angle = std::f32::consts::TAU * frame as f32 / frames as f32;
+  let offset = cgmath::Vector3::new(angle.cos(), ORBIT_ELEVATION, angle.sin()).normalize() * distance;
+  let position: Point3<f32> = center + offset;
+  let forward = -offset.normalize();
+  Camera::new(position, Rad(forward.z.atan2(forward.x)), Rad(forward.y.asin()))
+}
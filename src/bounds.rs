@@ -0,0 +1,104 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector3};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+  pub min: Point3<f32>,
+  pub max: Point3<f32>,
+}
+
+impl Aabb {
+  pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+    Self { min, max }
+  }
+
+  pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Option<Self> {
+    let mut points = points.into_iter();
+    let first = points.next()?;
+    Some(points.fold(Self::new(first, first), |aabb, p| aabb.including(p)))
+  }
+
+  pub fn including(&self, p: Point3<f32>) -> Self {
+    Self {
+      min: Point3::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z)),
+      max: Point3::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z)),
+    }
+  }
+
+  pub fn union(&self, other: &Aabb) -> Self {
+    self.including(other.min).including(other.max)
+  }
+
+  pub fn center(&self) -> Point3<f32> {
+    self.min.midpoint(self.max)
+  }
+
+  pub fn extents(&self) -> Vector3<f32> {
+    self.max - self.min
+  }
+
+  // Radius of the sphere through the corners, centered on `center()`
+  pub fn radius(&self) -> f32 {
+    self.extents().magnitude() * 0.5
+  }
+
+  pub fn corners(&self) -> [Point3<f32>; 8] {
+    let (a, b) = (self.min, self.max);
+    [
+      Point3::new(a.x, a.y, a.z),
+      Point3::new(b.x, a.y, a.z),
+      Point3::new(a.x, b.y, a.z),
+      Point3::new(b.x, b.y, a.z),
+      Point3::new(a.x, a.y, b.z),
+      Point3::new(b.x, a.y, b.z),
+      Point3::new(a.x, b.y, b.z),
+      Point3::new(b.x, b.y, b.z),
+    ]
+  }
+
+  // Box around the transformed corners, so it stays conservative under rotation
+  pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+    Self::from_points(self.corners().map(|c| matrix.transform_point(c)))
+      .expect("an AABB always has corners")
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+  pub center: Point3<f32>,
+  pub radius: f32,
+}
+
+impl Sphere {
+  pub fn new(center: Point3<f32>, radius: f32) -> Self {
+    Self { center, radius }
+  }
+
+  // Centered on the points' box, reaching the farthest point; tighter than the
+  // box's own corner sphere unless the points fill the corners
+  pub fn from_points(points: impl IntoIterator<Item = Point3<f32>> + Clone) -> Option<Self> {
+    let center = Aabb::from_points(points.clone())?.center();
+    let radius = points
+      .into_iter()
+      .map(|p| p.distance2(center))
+      .fold(0.0, f32::max)
+      .sqrt();
+    Some(Self::new(center, radius))
+  }
+
+  // Smallest sphere around both
+  pub fn union(&self, other: &Sphere) -> Self {
+    let offset = other.center - self.center;
+    let distance = offset.magnitude();
+    if distance + other.radius <= self.radius {
+      return *self;
+    }
+    if distance + self.radius <= other.radius {
+      return *other;
+    }
+    let radius = (distance + self.radius + other.radius) * 0.5;
+    // distance > 0 here, or one sphere would contain the other
+    let center = self.center + offset * ((radius - self.radius) / distance);
+    Self::new(center, radius)
+  }
+}
@@ -5,6 +5,11 @@ use winit::keyboard::KeyCode;
 use instant::Duration;
 use std::f32::consts::FRAC_PI_2;
 
+// Coordinates are right-handed throughout: +X right, +Y up, and the view looks down
+// -Z (`look_to_rh`). cgmath's `perspective` targets OpenGL's -1..1 clip depth, which
+// this matrix remaps to wgpu's 0..1. Nothing mirrors the image, so a triangle whose
+// vertices run counter-clockwise when viewed from outside the mesh is a front face,
+// matching the default FrontFace::Ccw
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
     cgmath::Vector4::new(1.0, 0.0, 0.0, 0.0),
@@ -13,9 +18,18 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_co
     cgmath::Vector4::new(0.0, 0.0, 0.5, 1.0),
 );
 
+// Maps NDC depth z to 1 - z so the near plane lands at 1.0 and the far plane at 0.0
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
+    cgmath::Vector4::new(1.0, 0.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 1.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, -1.0, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, 1.0, 1.0),
+);
+
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
   pub position: Point3<f32>,
   yaw: Rad<f32>,
@@ -33,36 +47,90 @@ impl Camera {
     }
   }
 
-  pub fn calc_matrix(&self) -> Matrix4<f32> {
+  pub fn yaw(&self) -> Rad<f32> {
+    self.yaw
+  }
+
+  pub fn pitch(&self) -> Rad<f32> {
+    self.pitch
+  }
+
+  pub fn forward(&self) -> Vector3<f32> {
     let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
     let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
 
+    Vector3::new(
+      cos_pitch * cos_yaw,
+      sin_pitch,
+      cos_pitch * sin_yaw,
+    ).normalize()
+  }
+
+  pub fn calc_matrix(&self) -> Matrix4<f32> {
     Matrix4::look_to_rh(
       self.position,
-      Vector3::new(
-        cos_pitch * cos_yaw,
-        sin_pitch,
-        cos_pitch * sin_yaw,
-      ).normalize(),
+      self.forward(),
       Vector3::unit_y(),
     )
   }
 }
 
+#[derive(Debug, Clone)]
 pub struct Projection {
   aspect: f32,
+  // Used instead of `aspect` when set, which keeps following resizes underneath
+  aspect_override: Option<f32>,
   fovy: Rad<f32>,
   znear: f32,
   zfar: f32,
+  reverse_z: bool,
 }
 
 impl Projection {
   pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
     Self {
       aspect: width as f32 / height as f32,
+      aspect_override: None,
       fovy: fovy.into(),
       znear,
       zfar,
+      reverse_z: false,
+    }
+  }
+
+  pub fn fovy(&self) -> Rad<f32> {
+    self.fovy
+  }
+
+  pub fn znear(&self) -> f32 {
+    self.znear
+  }
+
+  pub fn zfar(&self) -> f32 {
+    self.zfar
+  }
+
+  // Returns false, leaving the planes as they were, unless 0 < near < far
+  pub fn set_clip_planes(&mut self, znear: f32, zfar: f32) -> bool {
+    if !(znear > 0.0 && zfar > znear) {
+      log::warn!("Ignoring clip planes {} and {}, they need 0 < near < far", znear, zfar);
+      return false;
+    }
+    self.znear = znear;
+    self.zfar = zfar;
+    true
+  }
+
+  pub fn set_reverse_z(&mut self, reverse_z: bool) {
+    self.reverse_z = reverse_z;
+  }
+
+  // NDC depth of the (near, far) planes
+  pub fn ndc_depth_range(&self) -> (f32, f32) {
+    if self.reverse_z {
+      (1.0, 0.0)
+    } else {
+      (0.0, 1.0)
     }
   }
 
@@ -70,9 +138,88 @@ impl Projection {
     self.aspect = width as f32 / height as f32;
   }
 
+  // Width over height the matrix is built for: the override if there is one,
+  // otherwise the last resize's
+  pub fn aspect(&self) -> f32 {
+    self.aspect_override.unwrap_or(self.aspect)
+  }
+
+  pub fn aspect_override(&self) -> Option<f32> {
+    self.aspect_override
+  }
+
+  // Fixes the aspect regardless of the target's size, e.g. for non-square pixels
+  // or a fixed-aspect image stretched over a differently shaped window; `None`
+  // goes back to following resizes. Returns false, keeping the current setting,
+  // for an aspect that isn't positive and finite
+  pub fn set_aspect_override(&mut self, aspect: Option<f32>) -> bool {
+    if aspect.is_some_and(|aspect| !aspect.is_finite() || aspect <= 0.0) {
+      log::warn!("Ignoring aspect ratio {:?}, it needs to be positive", aspect);
+      return false;
+    }
+    self.aspect_override = aspect;
+    true
+  }
+
+  // Half of the narrower of the vertical and horizontal fields of view
+  pub fn min_half_fov(&self) -> Rad<f32> {
+    let half_fovy = self.fovy / 2.0;
+    let half_fovx = Rad((half_fovy.0.tan() * self.aspect()).atan());
+    if half_fovx < half_fovy { half_fovx } else { half_fovy }
+  }
+
   pub fn calc_matrix(&self) -> Matrix4<f32> {
-    OPENGL_TO_WGPU_MATRIX * cgmath::perspective(self.fovy, self.aspect, self.znear, self.zfar)
+    let proj = OPENGL_TO_WGPU_MATRIX * cgmath::perspective(self.fovy, self.aspect(), self.znear, self.zfar);
+    if self.reverse_z {
+      REVERSE_Z_MATRIX * proj
+    } else {
+      proj
+    }
+  }
+}
+
+pub fn view_proj_matrix(camera: &Camera, projection: &Projection) -> Matrix4<f32> {
+  projection.calc_matrix() * camera.calc_matrix()
+}
+
+// What `screen_to_world_ray` returns in place of a (Point3, Vector3) tuple, so
+// origin and direction are named and can't be mixed up. Starts on the near
+// plane; `direction` is unit length
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+  pub origin: Point3<f32>,
+  pub direction: Vector3<f32>,
+}
+
+// Turns a pixel into a world-space ray by unprojecting it onto the near and far
+// planes. wgpu's NDC has y pointing up, while pixels have y down; `depth_range`
+// is the projection's (near, far) NDC depth so reverse-Z works too. None for an
+// empty view or a view-projection matrix that can't be inverted
+pub fn screen_to_world_ray(
+  view_proj: Matrix4<f32>,
+  depth_range: (f32, f32),
+  x: f32,
+  y: f32,
+  width: u32,
+  height: u32,
+) -> Option<Ray> {
+  if width == 0 || height == 0 {
+    return None;
   }
+  let inv_view_proj = view_proj.invert()?;
+  let ndc_x = 2.0 * x / width as f32 - 1.0;
+  let ndc_y = 1.0 - 2.0 * y / height as f32;
+
+  let (near_depth, far_depth) = depth_range;
+  let near = inv_view_proj * Vector4::new(ndc_x, ndc_y, near_depth, 1.0);
+  let far = inv_view_proj * Vector4::new(ndc_x, ndc_y, far_depth, 1.0);
+  let near = Point3::from_homogeneous(near);
+  let far = Point3::from_homogeneous(far);
+
+  Some(Ray {
+    origin: near,
+    direction: (far - near).normalize(),
+  })
 }
 
 #[derive(Debug)]
@@ -88,6 +235,9 @@ pub struct CameraController {
   scroll: f32,
   speed: f32,
   sensitivity: f32,
+  // Time constant in seconds for easing toward the keys' velocity; 0 is instant
+  damping: f32,
+  velocity: Vector3<f32>,
 }
 
 impl CameraController {
@@ -104,9 +254,17 @@ impl CameraController {
       scroll: 0.0,
       speed,
       sensitivity,
+      damping: 0.0,
+      velocity: Vector3::zero(),
     }
   }
 
+  // Movement accelerates toward the held keys' velocity and coasts to a stop after
+  // release, reaching ~63% of the change after `damping` seconds
+  pub fn set_damping(&mut self, damping: f32) {
+    self.damping = damping.max(0.0);
+  }
+
   pub fn handle_key(&mut self, key: KeyCode, pressed: bool) -> bool {
     let amount = if pressed { 1.0 } else { 0.0 };
     match key {
@@ -138,9 +296,18 @@ impl CameraController {
     }
   }
 
+  // Sums the frame's mouse motion; `State::update` resets it every frame, after
+  // the first camera update of the frame has applied it
   pub fn handle_mouse(&mut self, delta_x: f64, delta_y: f64) {
-    self.rotate_horizontal = delta_x as f32;
-    self.rotate_vertical = delta_y as f32;
+    self.rotate_horizontal += delta_x as f32;
+    self.rotate_vertical += delta_y as f32;
+  }
+
+  // Drops mouse motion no update has applied, e.g. while paused or looking
+  // through a named camera, so it doesn't turn the camera later all at once
+  pub fn reset_mouse(&mut self) {
+    self.rotate_horizontal = 0.0;
+    self.rotate_vertical = 0.0;
   }
 
   pub fn handle_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
@@ -160,21 +327,22 @@ impl CameraController {
     let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
     let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
     let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-    camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-    camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
-    
+    let target_velocity = (forward * (self.amount_forward - self.amount_backward)
+      + right * (self.amount_right - self.amount_left)
+      + Vector3::unit_y() * (self.amount_up - self.amount_down))
+      * self.speed;
+    let blend = if self.damping > 0.0 { 1.0 - (-dt / self.damping).exp() } else { 1.0 };
+    self.velocity += (target_velocity - self.velocity) * blend;
+    camera.position += self.velocity * dt;
+
     let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
     let scrollward = Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
     camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
     self.scroll = 0.0;
 
-    camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
-    
     camera.yaw += Rad(self.rotate_horizontal * self.sensitivity * dt);
     camera.pitch += Rad(-self.rotate_vertical * self.sensitivity * dt);
-
-    self.rotate_horizontal = 0.0;
-    self.rotate_vertical = 0.0;
+    self.reset_mouse();
 
     if camera.pitch < -Rad(SAFE_FRAC_PI_2) {
       camera.pitch = -Rad(SAFE_FRAC_PI_2);
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+pub struct ComputePipelineManager {
+  pipelines: Vec<wgpu::ComputePipeline>,
+  pipeline_map: HashMap<String, usize>,
+}
+
+impl ComputePipelineManager {
+  pub fn new() -> Self {
+    Self {
+      pipelines: Vec::new(),
+      pipeline_map: HashMap::new(),
+    }
+  }
+
+  // The shader must have exactly one @compute entry point
+  pub fn add_compute_pipeline(
+    &mut self,
+    device: &wgpu::Device,
+    name: String,
+    shader_source: &str,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+  ) -> usize {
+    if let Some(&index) = self.pipeline_map.get(&name) {
+      return index;
+    }
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some(&format!("{} Pipeline Layout", name)),
+      bind_group_layouts,
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some(&format!("{} Shader", name)),
+      source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+      label: Some(&name),
+      layout: Some(&layout),
+      module: &shader,
+      entry_point: None,
+      compilation_options: Default::default(),
+      cache: None,
+    });
+
+    let index = self.pipelines.len();
+    self.pipelines.push(pipeline);
+    self.pipeline_map.insert(name, index);
+    index
+  }
+
+  pub fn get_by_name(&self, name: &str) -> Option<&wgpu::ComputePipeline> {
+    self.pipeline_map.get(name).and_then(|&i| self.pipelines.get(i))
+  }
+
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.pipeline_map.keys().map(String::as_str)
+  }
+}
+
+// A dispatch queued with `State::dispatch`, encoded ahead of the next frame's render passes
+pub struct Dispatch {
+  pub pipeline: String,
+  pub workgroups: (u32, u32, u32),
+  pub bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Dispatch {
+  pub fn encode(&self, compute_pass: &mut wgpu::ComputePass<'_>, pipeline: &wgpu::ComputePipeline) {
+    compute_pass.set_pipeline(pipeline);
+    for (i, bind_group) in self.bind_groups.iter().enumerate() {
+      compute_pass.set_bind_group(i as u32, bind_group, &[]);
+    }
+    let (x, y, z) = self.workgroups;
+    compute_pass.dispatch_workgroups(x, y, z);
+  }
+}
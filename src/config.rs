@@ -0,0 +1,69 @@
+use crate::{model, texture};
+
+// Choices that have to be made when the device and surface are created
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+  // Requested on top of the optional features the renderer enables by itself;
+  // `State::new` fails if the adapter is missing any of them
+  pub required_features: wgpu::Features,
+  // Frames the CPU may queue ahead of the GPU: 1 for the lowest input latency,
+  // 3 for smoother pacing under load. Can be changed later with
+  // `State::set_max_frame_latency`
+  pub max_frame_latency: u32,
+  // Winding that counts as front-facing (and survives back-face culling) for every
+  // pipeline that doesn't set its own. The camera math is right-handed, so Ccw
+  // suits assets exported from right-handed tools; use Cw for assets that render
+  // inside out
+  pub default_front_face: wgpu::FrontFace,
+  // How the window is composited with what's behind it. `None` takes the
+  // surface's first supported mode; `PreMultiplied` suits transparent overlays
+  // and `Opaque` avoids compositor bleed-through. Unsupported modes fall back
+  // to the default with a warning
+  pub alpha_mode: Option<wgpu::CompositeAlphaMode>,
+  // Vertex buffer layout every mesh pipeline is built for. `Compact` roughly
+  // trades a quarter of the vertex memory for half-float positions; models loaded
+  // through the resource cache follow it, others have to be loaded to match
+  pub vertex_format: model::VertexFormatOption,
+  // Models loaded through the resource cache get smooth normals computed from
+  // their faces even when the OBJ has its own. Off, only meshes without normals
+  // (which would otherwise light black) get them
+  pub recompute_normals: bool,
+  // Models loaded through the resource cache flip their V coordinates for OBJ's
+  // bottom-left texture origin (the default). Off, UVs are used as stored, for
+  // assets whose textures already come out the right way up
+  pub flip_v: bool,
+  // How the textures of models loaded through the resource cache are sampled
+  pub sampler_options: texture::SamplerOptions,
+  // Off, the scene passes run without a depth buffer and pipelines without depth
+  // testing, so everything draws in submission order, for 2D content that
+  // doesn't need one. Selection outlines need the depth buffer's stencil, so
+  // they're unavailable
+  pub use_depth: bool,
+  // Backends to request an adapter from, in order, falling back to the next when
+  // one has no adapter for the window. Defaults to PRIMARY natively, and to
+  // WebGPU then WebGL2 on wasm
+  pub backends: Vec<wgpu::Backends>,
+  // Passed with every adapter request along the `backends` chain
+  pub power_preference: wgpu::PowerPreference,
+}
+
+impl Default for RendererConfig {
+  fn default() -> Self {
+    Self {
+      required_features: wgpu::Features::empty(),
+      max_frame_latency: 2,
+      default_front_face: wgpu::FrontFace::Ccw,
+      alpha_mode: None,
+      vertex_format: model::VertexFormatOption::Float32,
+      recompute_normals: false,
+      flip_v: true,
+      sampler_options: texture::SamplerOptions::default(),
+      use_depth: true,
+      #[cfg(not(target_arch = "wasm32"))]
+      backends: vec![wgpu::Backends::PRIMARY],
+      #[cfg(target_arch = "wasm32")]
+      backends: vec![wgpu::Backends::BROWSER_WEBGPU, wgpu::Backends::GL],
+      power_preference: wgpu::PowerPreference::default(),
+    }
+  }
+}
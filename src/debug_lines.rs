@@ -0,0 +1,210 @@
+use std::mem;
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use crate::bounds::Aabb;
+use crate::model;
+use crate::uniforms::DynamicUniformSlots;
+
+pub const PIPELINE: &str = "debug_lines_pipeline";
+
+const INITIAL_CAPACITY: usize = 1024;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+  pub position: [f32; 3],
+  pub color: [f32; 3],
+}
+
+// A line's two ends, read once per instance; every segment is drawn as a quad
+// of six vertices expanded to the line width in the vertex shader
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineSegment {
+  pub a: LineVertex,
+  pub b: LineVertex,
+}
+
+impl LineSegment {
+  pub const VERTEX_COUNT: u32 = 6;
+}
+
+const SEGMENT_ATTRIBUTES: [wgpu::VertexAttribute; 4] = [
+  wgpu::VertexAttribute {
+    offset: mem::offset_of!(LineSegment, a) as wgpu::BufferAddress,
+    shader_location: 0,
+    format: wgpu::VertexFormat::Float32x3,
+  },
+  wgpu::VertexAttribute {
+    offset: (mem::offset_of!(LineSegment, a) + mem::offset_of!(LineVertex, color)) as wgpu::BufferAddress,
+    shader_location: 1,
+    format: wgpu::VertexFormat::Float32x3,
+  },
+  wgpu::VertexAttribute {
+    offset: mem::offset_of!(LineSegment, b) as wgpu::BufferAddress,
+    shader_location: 2,
+    format: wgpu::VertexFormat::Float32x3,
+  },
+  wgpu::VertexAttribute {
+    offset: (mem::offset_of!(LineSegment, b) + mem::offset_of!(LineVertex, color)) as wgpu::BufferAddress,
+    shader_location: 3,
+    format: wgpu::VertexFormat::Float32x3,
+  },
+];
+
+impl model::Vertex for LineSegment {
+  fn desc() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+      array_stride: mem::size_of::<LineSegment>() as wgpu::BufferAddress,
+      step_mode: wgpu::VertexStepMode::Instance,
+      attributes: &SEGMENT_ATTRIBUTES,
+    }
+  }
+}
+
+pub const DEFAULT_LINE_WIDTH: f32 = 1.0;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineStyleUniform {
+  viewport_size: [f32; 2],
+  width: f32,
+  _padding: u32,
+}
+
+// Width in pixels of every line drawn with the line pipeline, plus each view's
+// size in pixels to expand it against; one slot per view, indexed like the
+// scene passes (main view, then viewports)
+pub(crate) struct LineStyle {
+  slots: DynamicUniformSlots<LineStyleUniform>,
+  width: f32,
+}
+
+impl LineStyle {
+  pub fn new(device: &wgpu::Device) -> Self {
+    Self {
+      slots: DynamicUniformSlots::new(device, wgpu::ShaderStages::VERTEX, 1, "line_style"),
+      width: DEFAULT_LINE_WIDTH,
+    }
+  }
+
+  pub fn layout(&self) -> &wgpu::BindGroupLayout {
+    &self.slots.layout
+  }
+
+  pub fn width(&self) -> f32 {
+    self.width
+  }
+
+  pub fn set_width(&mut self, width: f32) {
+    self.width = width;
+  }
+
+  // Uploads the width with each view's size in pixels
+  pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, view_sizes: impl ExactSizeIterator<Item = (f32, f32)>) {
+    let width = self.width;
+    let styles = view_sizes.map(|(view_width, view_height)| LineStyleUniform {
+      viewport_size: [view_width.max(1.0), view_height.max(1.0)],
+      width,
+      _padding: 0,
+    });
+    self.slots.write(device, queue, styles);
+  }
+
+  pub fn binding(&self, view: usize) -> (&wgpu::BindGroup, u32) {
+    (self.slots.bind_group(), self.slots.offset(view))
+  }
+}
+
+// Immediate-mode world-space lines: queue them any time before `render`, which
+// draws everything queued and then clears the list
+pub struct DebugLines {
+  segments: Vec<LineSegment>,
+  buffer: wgpu::Buffer,
+  capacity: usize,
+  // Segments uploaded for the frame being rendered
+  uploaded: u32,
+}
+
+impl DebugLines {
+  pub fn new(device: &wgpu::Device) -> Self {
+    Self {
+      segments: Vec::new(),
+      buffer: Self::create_buffer(device, INITIAL_CAPACITY),
+      capacity: INITIAL_CAPACITY,
+      uploaded: 0,
+    }
+  }
+
+  fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Debug Line Buffer"),
+      size: (capacity * mem::size_of::<LineSegment>()) as wgpu::BufferAddress,
+      usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    })
+  }
+
+  pub fn draw_line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 3]) {
+    self.segments.push(LineSegment {
+      a: LineVertex { position: a.into(), color },
+      b: LineVertex { position: b.into(), color },
+    });
+  }
+
+  pub fn draw_aabb(&mut self, aabb: &Aabb, color: [f32; 3]) {
+    // Corners are ordered by (x, y, z) bits, so each edge joins corners one bit apart
+    const EDGES: [(usize, usize); 12] = [
+      (0, 1), (2, 3), (4, 5), (6, 7),
+      (0, 2), (1, 3), (4, 6), (5, 7),
+      (0, 4), (1, 5), (2, 6), (3, 7),
+    ];
+    let corners = aabb.corners();
+    for (a, b) in EDGES {
+      self.draw_line(corners[a], corners[b], color);
+    }
+  }
+
+  pub fn draw_ray(&mut self, origin: Point3<f32>, direction: Vector3<f32>, length: f32, color: [f32; 3]) {
+    if direction.is_zero() {
+      return;
+    }
+    self.draw_line(origin, origin + direction.normalize() * length, color);
+  }
+
+  pub fn clear(&mut self) {
+    self.segments.clear();
+  }
+
+  pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+    self.uploaded = self.segments.len() as u32;
+    if self.segments.is_empty() {
+      return;
+    }
+    if self.segments.len() > self.capacity {
+      self.capacity = self.segments.len().next_power_of_two();
+      self.buffer = Self::create_buffer(device, self.capacity);
+    }
+    queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.segments));
+  }
+
+  // Returns whether anything was drawn
+  pub fn draw<'a>(
+    &'a self,
+    render_pass: &mut wgpu::RenderPass<'a>,
+    pipeline: &'a wgpu::RenderPipeline,
+    camera_bind_group: &'a wgpu::BindGroup,
+    line_style: (&'a wgpu::BindGroup, u32),
+  ) -> bool {
+    if self.uploaded == 0 {
+      return false;
+    }
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, camera_bind_group, &[]);
+    render_pass.set_bind_group(1, line_style.0, &[line_style.1]);
+    render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+    render_pass.draw(0..LineSegment::VERTEX_COUNT, 0..self.uploaded);
+    true
+  }
+}
@@ -0,0 +1,344 @@
+// Replaces the shading of every instanced object with a visualization of one of
+// its inputs; objects without instances (the light) are still drawn normally.
+// Depth and ShadowMap instead leave the scene as is and draw over the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugView {
+  #[default]
+  None,
+  // World-space normals mapped from -1..1 to 0..1
+  Normals,
+  UVs,
+  // The depth buffer as view-space distance, white at the near plane and black
+  // at the far one. Needs a depth buffer; with extra viewports only the last
+  // one drawn is shown, the others' depth being cleared by then
+  Depth,
+  // The light's depth as gray, bottom-left, over the tone-mapped frame
+  ShadowMap,
+}
+
+impl DebugView {
+  // The views drawn through a pipeline of their own
+  pub const ALL: [DebugView; 2] = [DebugView::Normals, DebugView::UVs];
+
+  pub fn pipeline_name(self) -> Option<&'static str> {
+    match self {
+      DebugView::None | DebugView::Depth | DebugView::ShadowMap => None,
+      DebugView::Normals => Some("normals"),
+      DebugView::UVs => Some("uvs"),
+    }
+  }
+
+  fn shader_mode(self) -> u32 {
+    match self {
+      DebugView::None | DebugView::Depth | DebugView::ShadowMap => 0,
+      DebugView::Normals => 1,
+      DebugView::UVs => 2,
+    }
+  }
+
+  pub fn shader_source(self) -> String {
+    format!(
+      "const DEBUG_VIEW: u32 = {}u;\n{}",
+      self.shader_mode(),
+      include_str!("../shaders/debug.wgsl"),
+    )
+  }
+}
+
+// Fraction of the surface's shorter side the shadow map is shown at
+const SHADOW_MAP_SCALE: f32 = 0.3;
+
+// Draws DebugView::ShadowMap straight onto the surface after tone mapping
+pub(crate) struct ShadowMapOverlay {
+  layout: wgpu::BindGroupLayout,
+  bind_group: wgpu::BindGroup,
+  pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMapOverlay {
+  pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat, shadow_view: &wgpu::TextureView) -> Self {
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      entries: &[wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+          multisampled: false,
+          view_dimension: wgpu::TextureViewDimension::D2,
+          sample_type: wgpu::TextureSampleType::Depth,
+        },
+        count: None,
+      }],
+      label: Some("shadow_map_overlay_layout"),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("Shadow Map Overlay Pipeline Layout"),
+      bind_group_layouts: &[&layout],
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some("Shadow Map Overlay Shader"),
+      source: wgpu::ShaderSource::Wgsl(
+        concat!(include_str!("../shaders/fullscreen.wgsl"), include_str!("../shaders/shadow_debug.wgsl")).into(),
+      ),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Shadow Map Overlay Pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: wgpu::VertexState {
+        module: &shader,
+        entry_point: Some("vs_main"),
+        buffers: &[],
+        compilation_options: Default::default(),
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: &shader,
+        entry_point: Some("fs_main"),
+        targets: &[Some(wgpu::ColorTargetState {
+          format: output_format,
+          blend: Some(wgpu::BlendState::REPLACE),
+          write_mask: wgpu::ColorWrites::ALL,
+        })],
+        compilation_options: Default::default(),
+      }),
+      primitive: wgpu::PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: wgpu::MultisampleState::default(),
+      multiview: None,
+      cache: None,
+    });
+
+    let bind_group = Self::create_bind_group(device, &layout, shadow_view);
+    Self {
+      layout,
+      bind_group,
+      pipeline,
+    }
+  }
+
+  fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    shadow_view: &wgpu::TextureView,
+  ) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: wgpu::BindingResource::TextureView(shadow_view),
+      }],
+      label: Some("shadow_map_overlay_bind_group"),
+    })
+  }
+
+  // After the shadow map was recreated
+  pub fn rebind(&mut self, device: &wgpu::Device, shadow_view: &wgpu::TextureView) {
+    self.bind_group = Self::create_bind_group(device, &self.layout, shadow_view);
+  }
+
+  pub fn run(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView, (width, height): (u32, u32)) {
+    let size = (width.min(height) as f32 * SHADOW_MAP_SCALE).floor();
+    if size < 1.0 {
+      return;
+    }
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("Shadow Map Overlay Pass"),
+      color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+        view: output,
+        resolve_target: None,
+        ops: wgpu::Operations {
+          load: wgpu::LoadOp::Load,
+          store: wgpu::StoreOp::Store,
+        },
+        depth_slice: None,
+      })],
+      depth_stencil_attachment: None,
+      occlusion_query_set: None,
+      timestamp_writes: None,
+    });
+    render_pass.set_viewport(0.0, height as f32 - size, size, size, 0.0, 1.0);
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, &self.bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DepthRangeUniform {
+  znear: f32,
+  zfar: f32,
+  ndc_near: f32,
+  ndc_far: f32,
+}
+
+// Draws DebugView::Depth straight onto the surface after tone mapping, reading
+// the depth buffer the scene pass left behind
+pub(crate) struct DepthOverlay {
+  range_buffer: wgpu::Buffer,
+  // Indexed by whether the depth buffer is multisampled
+  layouts: [wgpu::BindGroupLayout; 2],
+  pipelines: [wgpu::RenderPipeline; 2],
+}
+
+impl DepthOverlay {
+  pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Self {
+    let range_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Depth Overlay Range Buffer"),
+      size: std::mem::size_of::<DepthRangeUniform>() as wgpu::BufferAddress,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let layouts = [false, true].map(|multisampled| {
+      device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+          wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+              multisampled,
+              view_dimension: wgpu::TextureViewDimension::D2,
+              sample_type: wgpu::TextureSampleType::Depth,
+            },
+            count: None,
+          },
+          wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: false,
+              min_binding_size: None,
+            },
+            count: None,
+          },
+        ],
+        label: Some("depth_overlay_layout"),
+      })
+    });
+    let pipelines = [false, true].map(|multisampled| {
+      Self::create_pipeline(device, output_format, &layouts[multisampled as usize], multisampled)
+    });
+    Self {
+      range_buffer,
+      layouts,
+      pipelines,
+    }
+  }
+
+  fn create_pipeline(
+    device: &wgpu::Device,
+    output_format: wgpu::TextureFormat,
+    layout: &wgpu::BindGroupLayout,
+    multisampled: bool,
+  ) -> wgpu::RenderPipeline {
+    let texture_type = if multisampled { "texture_depth_multisampled_2d" } else { "texture_depth_2d" };
+    let source = format!(
+      "@group(0) @binding(0)\nvar depth_texture: {};\n{}",
+      texture_type,
+      concat!(include_str!("../shaders/fullscreen.wgsl"), include_str!("../shaders/depth_debug.wgsl")),
+    );
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("Depth Overlay Pipeline Layout"),
+      bind_group_layouts: &[layout],
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some("Depth Overlay Shader"),
+      source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Depth Overlay Pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: wgpu::VertexState {
+        module: &shader,
+        entry_point: Some("vs_main"),
+        buffers: &[],
+        compilation_options: Default::default(),
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: &shader,
+        entry_point: Some("fs_main"),
+        targets: &[Some(wgpu::ColorTargetState {
+          format: output_format,
+          blend: Some(wgpu::BlendState::REPLACE),
+          write_mask: wgpu::ColorWrites::ALL,
+        })],
+        compilation_options: Default::default(),
+      }),
+      primitive: wgpu::PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: wgpu::MultisampleState::default(),
+      multiview: None,
+      cache: None,
+    })
+  }
+
+  // Covers `rect` (in pixels), mapping depth with the near and far planes of the
+  // `projection` it was drawn with. The bind group is made per frame, the depth
+  // buffer being recreated on resizes, sample count and format changes alike
+  #[allow(clippy::too_many_arguments)]
+  pub fn run(
+    &self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    encoder: &mut wgpu::CommandEncoder,
+    output: &wgpu::TextureView,
+    depth_texture: &wgpu::Texture,
+    (x, y, width, height): (f32, f32, f32, f32),
+    projection: &crate::camera::Projection,
+  ) {
+    if width <= 0.0 || height <= 0.0 {
+      return;
+    }
+    let (ndc_near, ndc_far) = projection.ndc_depth_range();
+    let range = DepthRangeUniform {
+      znear: projection.znear(),
+      zfar: projection.zfar(),
+      ndc_near,
+      ndc_far,
+    };
+    queue.write_buffer(&self.range_buffer, 0, bytemuck::cast_slice(&[range]));
+
+    // Stencil formats can only be sampled through a depth-only view
+    let depth_view = depth_texture.create_view(&wgpu::TextureViewDescriptor {
+      aspect: wgpu::TextureAspect::DepthOnly,
+      ..Default::default()
+    });
+    let multisampled = depth_texture.sample_count() > 1;
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &self.layouts[multisampled as usize],
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::TextureView(&depth_view),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: self.range_buffer.as_entire_binding(),
+        },
+      ],
+      label: Some("depth_overlay_bind_group"),
+    });
+
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("Depth Overlay Pass"),
+      color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+        view: output,
+        resolve_target: None,
+        ops: wgpu::Operations {
+          load: wgpu::LoadOp::Load,
+          store: wgpu::StoreOp::Store,
+        },
+        depth_slice: None,
+      })],
+      depth_stencil_attachment: None,
+      occlusion_query_set: None,
+      timestamp_writes: None,
+    });
+    render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+    render_pass.set_pipeline(&self.pipelines[multisampled as usize]);
+    render_pass.set_bind_group(0, &bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+  }
+}
@@ -0,0 +1,33 @@
+use std::fmt;
+
+// What `State::new` ended up choosing, for pasting into bug reports
+#[derive(Debug, Clone)]
+pub struct RendererDiagnostics {
+  pub adapter_name: String,
+  pub backend: wgpu::Backend,
+  pub device_type: wgpu::DeviceType,
+  pub driver: String,
+  pub driver_info: String,
+  pub surface_format: wgpu::TextureFormat,
+  // Format the scene is rendered in before tone mapping onto the surface
+  pub render_format: wgpu::TextureFormat,
+  pub present_mode: wgpu::PresentMode,
+  pub alpha_mode: wgpu::CompositeAlphaMode,
+  pub sample_count: u32,
+  pub surface_size: (u32, u32),
+  pub features: wgpu::Features,
+}
+
+impl fmt::Display for RendererDiagnostics {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "Adapter:        {} ({:?}, {:?})", self.adapter_name, self.backend, self.device_type)?;
+    writeln!(f, "Driver:         {} {}", self.driver, self.driver_info)?;
+    writeln!(f, "Surface format: {:?}", self.surface_format)?;
+    writeln!(f, "Render format:  {:?}", self.render_format)?;
+    writeln!(f, "Present mode:   {:?}", self.present_mode)?;
+    writeln!(f, "Alpha mode:     {:?}", self.alpha_mode)?;
+    writeln!(f, "Sample count:   {}", self.sample_count)?;
+    writeln!(f, "Surface size:   {}x{}", self.surface_size.0, self.surface_size.1)?;
+    write!(f, "Features:       {:?}", self.features)
+  }
+}
@@ -1,11 +1,25 @@
 use std::ops::Range;
+use wgpu::util::RenderEncoder;
 use crate::model::{Mesh, Material, Model};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum DrawMethod {
   WithMaterial,
   WithoutMaterial,
+  // Like WithMaterial, but the index and instance counts come from the object's
+  // indirect args buffer, one DrawIndexedIndirectArgs per mesh
+  Indirect,
+  // Like Indirect, but a compute pass first frustum-culls the instances against
+  // the main camera and compacts the survivors, so only visible instances are
+  // drawn. Secondary viewports aren't culled against their own cameras: they
+  // draw the main camera's survivors, so instances outside its frustum are
+  // missing from them. Shadows use every instance
+  GpuCulled,
 }
 
+pub const INDIRECT_ARGS_SIZE: wgpu::BufferAddress =
+  std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+
 pub trait DrawWithMaterial<'a> {
   #[allow(unused)]
   fn draw_mesh(
@@ -31,41 +45,105 @@ pub trait DrawWithMaterial<'a> {
     camera_bind_group: &'a wgpu::BindGroup, 
     light_bind_group: &'a wgpu::BindGroup,
   );
+  // `extra_bind_groups` are bound at their groups (3 onward) next to the
+  // standard ones, as RenderableObject::extra_bind_groups
   fn draw_model_instanced(
     &mut self, 
     model: &'a Model, 
     instances: Range<u32>, 
     camera_bind_group: &'a wgpu::BindGroup, 
     light_bind_group: &'a wgpu::BindGroup,
+    extra_bind_groups: &'a [(u32, wgpu::BindGroup)],
+  );
+
+  fn draw_mesh_indirect(
+    &mut self,
+    mesh: &'a Mesh,
+    material: &'a Material,
+    indirect_buffer: &'a wgpu::Buffer,
+    indirect_offset: wgpu::BufferAddress,
+    camera_bind_group: &'a wgpu::BindGroup,
+    light_bind_group: &'a wgpu::BindGroup,
+  );
+  fn draw_model_indirect(
+    &mut self,
+    model: &'a Model,
+    indirect_buffer: &'a wgpu::Buffer,
+    camera_bind_group: &'a wgpu::BindGroup,
+    light_bind_group: &'a wgpu::BindGroup,
+    extra_bind_groups: &'a [(u32, wgpu::BindGroup)],
   );
 }
 
-impl<'a, 'b> DrawWithMaterial<'b> for wgpu::RenderPass<'a>
-where
-  'b: 'a,
-{
+// Render passes and render bundle encoders record draws the same way
+impl<'b, E: RenderEncoder<'b>> DrawWithMaterial<'b> for E {
   fn draw_mesh(&mut self, mesh: &'b Mesh, material: &'b Material, camera_bind_group: &'b wgpu::BindGroup, light_bind_group: &'b wgpu::BindGroup) {
     DrawWithMaterial::draw_mesh_instanced(self, mesh, material, 0..1, camera_bind_group, light_bind_group);
   }
 
   fn draw_mesh_instanced(&mut self, mesh: &'b Mesh, material: &'b Material, instances: Range<u32>, camera_bind_group: &'b wgpu::BindGroup, light_bind_group: &'b wgpu::BindGroup) {
     self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-    self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-    self.set_bind_group(0, &material.bind_group, &[]);
-    self.set_bind_group(1, camera_bind_group, &[]);
-    self.set_bind_group(2, light_bind_group, &[]);
+    self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+    self.set_bind_group(0, Some(&material.bind_group), &[]);
+    self.set_bind_group(1, Some(camera_bind_group), &[]);
+    self.set_bind_group(2, Some(light_bind_group), &[]);
     self.draw_indexed(0..mesh.num_elements, 0, instances);
   }
 
   fn draw_model(&mut self, model: &'b Model, camera_bind_group: &'b wgpu::BindGroup, light_bind_group: &'b wgpu::BindGroup) {
-    DrawWithMaterial::draw_model_instanced(self, model, 0..1, camera_bind_group, light_bind_group);
+    DrawWithMaterial::draw_model_instanced(self, model, 0..1, camera_bind_group, light_bind_group, &[]);
+  }
+
+  fn draw_model_instanced(&mut self, model: &'b Model, instances: Range<u32>, camera_bind_group: &'b wgpu::BindGroup, light_bind_group: &'b wgpu::BindGroup, extra_bind_groups: &'b [(u32, wgpu::BindGroup)]) {
+    for_each_mesh(self, model, camera_bind_group, light_bind_group, extra_bind_groups, |encoder, _, mesh| {
+      encoder.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+    });
+  }
+
+  fn draw_mesh_indirect(&mut self, mesh: &'b Mesh, material: &'b Material, indirect_buffer: &'b wgpu::Buffer, indirect_offset: wgpu::BufferAddress, camera_bind_group: &'b wgpu::BindGroup, light_bind_group: &'b wgpu::BindGroup) {
+    self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+    self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+    self.set_bind_group(0, Some(&material.bind_group), &[]);
+    self.set_bind_group(1, Some(camera_bind_group), &[]);
+    self.set_bind_group(2, Some(light_bind_group), &[]);
+    self.draw_indexed_indirect(indirect_buffer, indirect_offset);
   }
 
-  fn draw_model_instanced(&mut self, model: &'b Model, instances: Range<u32>, camera_bind_group: &'b wgpu::BindGroup, light_bind_group: &'b wgpu::BindGroup) {
-    for mesh in &model.meshes {
-      let material = &model.materials[mesh.material];
-      DrawWithMaterial::draw_mesh_instanced(self, mesh, material, instances.clone(), camera_bind_group, light_bind_group);
+  fn draw_model_indirect(&mut self, model: &'b Model, indirect_buffer: &'b wgpu::Buffer, camera_bind_group: &'b wgpu::BindGroup, light_bind_group: &'b wgpu::BindGroup, extra_bind_groups: &'b [(u32, wgpu::BindGroup)]) {
+    for_each_mesh(self, model, camera_bind_group, light_bind_group, extra_bind_groups, |encoder, i, _| {
+      encoder.draw_indexed_indirect(indirect_buffer, i as wgpu::BufferAddress * INDIRECT_ARGS_SIZE);
+    });
+  }
+}
+
+// Binds the camera, light and extra groups once per model, and a material only
+// when it differs from the previous mesh's, before `draw` records mesh `i` with
+// its buffers set
+fn for_each_mesh<'b, E: RenderEncoder<'b>>(
+  encoder: &mut E,
+  model: &'b Model,
+  camera_bind_group: &'b wgpu::BindGroup,
+  light_bind_group: &'b wgpu::BindGroup,
+  extra_bind_groups: &'b [(u32, wgpu::BindGroup)],
+  mut draw: impl FnMut(&mut E, usize, &'b Mesh),
+) {
+  encoder.set_bind_group(1, Some(camera_bind_group), &[]);
+  encoder.set_bind_group(2, Some(light_bind_group), &[]);
+  for (group, bind_group) in extra_bind_groups {
+    encoder.set_bind_group(*group, Some(bind_group), &[]);
+  }
+  let mut bound_material: Option<&Material> = None;
+  for (i, mesh) in model.meshes.iter().enumerate() {
+    // Out-of-range indices draw with the default material. Only a model that was
+    // never validated can lack one, and its mesh has nothing to bind
+    let Some(material) = model.mesh_material(mesh) else { continue };
+    if !bound_material.is_some_and(|bound| std::ptr::eq(bound, material)) {
+      encoder.set_bind_group(0, Some(&material.bind_group), &[]);
+      bound_material = Some(material);
     }
+    encoder.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+    encoder.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+    draw(encoder, i, mesh);
   }
 }
 
@@ -100,10 +178,7 @@ pub trait DrawWithoutMaterial<'a> {
   );
 }
 
-impl<'a, 'b> DrawWithoutMaterial<'b> for wgpu::RenderPass<'a>
-where
-  'b: 'a,
-{
+impl<'b, E: RenderEncoder<'b>> DrawWithoutMaterial<'b> for E {
   fn draw_mesh(
       &mut self,
       mesh: &'b Mesh,
@@ -121,9 +196,9 @@ where
       light_bind_group: &'b wgpu::BindGroup,
     ) {
       self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-      self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-      self.set_bind_group(0, camera_bind_group, &[]);
-      self.set_bind_group(1, light_bind_group, &[]);
+      self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+      self.set_bind_group(0, Some(camera_bind_group), &[]);
+      self.set_bind_group(1, Some(light_bind_group), &[]);
       self.draw_indexed(0..mesh.num_elements, 0, instances);
   }
 
@@ -143,8 +218,12 @@ where
       camera_bind_group: &'b wgpu::BindGroup,
       light_bind_group: &'b wgpu::BindGroup,
     ) {
+      self.set_bind_group(0, Some(camera_bind_group), &[]);
+      self.set_bind_group(1, Some(light_bind_group), &[]);
       for mesh in &model.meshes {
-        DrawWithoutMaterial::draw_mesh_instanced(self, mesh, instances.clone(), camera_bind_group, light_bind_group);
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+        self.draw_indexed(0..mesh.num_elements, 0, instances.clone());
       }
   }
 }
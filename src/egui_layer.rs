@@ -0,0 +1,98 @@
+use winit::{event::WindowEvent, window::Window};
+
+// Built each frame by the closure passed to `State::set_ui`
+pub(crate) type UiCallback = Box<dyn FnMut(&egui::Context)>;
+
+// egui painted straight onto the surface after tone mapping, so the UI is
+// neither lit, fogged nor exposure-adjusted
+pub(crate) struct EguiLayer {
+  context: egui::Context,
+  winit_state: egui_winit::State,
+  renderer: egui_wgpu::Renderer,
+}
+
+impl EguiLayer {
+  pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat, window: &Window) -> Self {
+    let context = egui::Context::default();
+    let winit_state = egui_winit::State::new(
+      context.clone(),
+      egui::ViewportId::ROOT,
+      window,
+      Some(window.scale_factor() as f32),
+      window.theme(),
+      Some(device.limits().max_texture_dimension_2d as usize),
+    );
+    let renderer = egui_wgpu::Renderer::new(device, surface_format, egui_wgpu::RendererOptions::default());
+    Self {
+      context,
+      winit_state,
+      renderer,
+    }
+  }
+
+  // Returns true if egui used the event, e.g. a click on one of its windows
+  pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+    self.winit_state.on_window_event(window, event).consumed
+  }
+
+  pub fn wants_pointer_input(&self) -> bool {
+    self.context.wants_pointer_input()
+  }
+
+  pub fn wants_keyboard_input(&self) -> bool {
+    self.context.wants_keyboard_input()
+  }
+
+  // Runs `build_ui` and records the result over `view`. Command buffers of any
+  // paint callbacks the UI added are returned, to be submitted before `encoder`
+  #[allow(clippy::too_many_arguments)]
+  pub fn paint(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    encoder: &mut wgpu::CommandEncoder,
+    view: &wgpu::TextureView,
+    window: &Window,
+    (width, height): (u32, u32),
+    build_ui: &mut dyn FnMut(&egui::Context),
+  ) -> Vec<wgpu::CommandBuffer> {
+    let input = self.winit_state.take_egui_input(window);
+    let output = self.context.run(input, |ctx| build_ui(ctx));
+    self.winit_state.handle_platform_output(window, output.platform_output);
+
+    let primitives = self.context.tessellate(output.shapes, output.pixels_per_point);
+    for (id, delta) in &output.textures_delta.set {
+      self.renderer.update_texture(device, queue, *id, delta);
+    }
+    let screen = egui_wgpu::ScreenDescriptor {
+      size_in_pixels: [width, height],
+      pixels_per_point: output.pixels_per_point,
+    };
+    let callbacks = self.renderer.update_buffers(device, queue, encoder, &primitives, &screen);
+
+    let mut render_pass = encoder
+      .begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("UI Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+          view,
+          resolve_target: None,
+          ops: wgpu::Operations {
+            load: wgpu::LoadOp::Load,
+            store: wgpu::StoreOp::Store,
+          },
+          depth_slice: None,
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+      })
+      .forget_lifetime();
+    self.renderer.render(&mut render_pass, &primitives, &screen);
+    drop(render_pass);
+
+    for id in &output.textures_delta.free {
+      self.renderer.free_texture(id);
+    }
+    callbacks
+  }
+}
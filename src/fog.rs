@@ -0,0 +1,79 @@
+use wgpu::util::DeviceExt;
+
+// Blends lit surfaces toward `color` with their distance from the camera. Colors
+// are linear, like the background; matching the two hides the far clip
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fog {
+  // No fog before `start`, only fog from `end` on, in world units
+  Linear { color: [f32; 3], start: f32, end: f32 },
+  // Fog fraction 1 - e^(-density * distance)
+  Exponential { color: [f32; 3], density: f32 },
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FogUniform {
+  color: [f32; 3],
+  // 0 off, 1 linear, 2 exponential
+  mode: u32,
+  start: f32,
+  end: f32,
+  density: f32,
+  _padding: u32,
+}
+
+impl FogUniform {
+  fn new(fog: Option<Fog>) -> Self {
+    let mut uniform = Self {
+      color: [0.0; 3],
+      mode: 0,
+      start: 0.0,
+      end: 0.0,
+      density: 0.0,
+      _padding: 0,
+    };
+    match fog {
+      None => {}
+      Some(Fog::Linear { color, start, end }) => {
+        uniform.color = color;
+        uniform.mode = 1;
+        uniform.start = start;
+        uniform.end = end;
+      }
+      Some(Fog::Exponential { color, density }) => {
+        uniform.color = color;
+        uniform.mode = 2;
+        uniform.density = density;
+      }
+    }
+    uniform
+  }
+}
+
+// Bound next to the light, since both apply to every view
+pub struct FogBuffer {
+  fog: Option<Fog>,
+  pub buffer: wgpu::Buffer,
+}
+
+impl FogBuffer {
+  pub fn new(device: &wgpu::Device) -> Self {
+    Self {
+      fog: None,
+      buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Fog Buffer"),
+        contents: bytemuck::cast_slice(&[FogUniform::new(None)]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      }),
+    }
+  }
+
+  pub fn fog(&self) -> Option<Fog> {
+    self.fog
+  }
+
+  pub fn set_fog(&mut self, queue: &wgpu::Queue, fog: Option<Fog>) {
+    self.fog = fog;
+    queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[FogUniform::new(fog)]));
+  }
+}
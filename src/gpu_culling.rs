@@ -0,0 +1,244 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Vector4};
+use wgpu::util::DeviceExt;
+
+use crate::draw_traits::INDIRECT_ARGS_SIZE;
+use crate::{instance, model};
+
+const WORKGROUP_SIZE: u32 = 64;
+// Offset of instance_count inside DrawIndexedIndirectArgs
+const INSTANCE_COUNT_OFFSET: wgpu::BufferAddress = 4;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FrustumUniform {
+  planes: [[f32; 4]; 6],
+}
+
+impl FrustumUniform {
+  // Gribb-Hartmann: each plane is a sum or difference of the matrix's rows, for
+  // clip space with 0 <= z <= w. The z planes hold for reverse-Z as well
+  fn new(view_proj: Matrix4<f32>) -> Self {
+    let m = view_proj.transpose();
+    let planes = [m.w + m.x, m.w - m.x, m.w + m.y, m.w - m.y, m.z, m.w - m.z].map(|plane: Vector4<f32>| {
+      let length = plane.truncate().magnitude();
+      // An infinite far plane comes out as 0 = 0, which culls nothing as is
+      if length > 0.0 { (plane / length).into() } else { plane.into() }
+    });
+    Self { planes }
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+  center: [f32; 3],
+  radius: f32,
+  instance_count: u32,
+  mesh_count: u32,
+  _padding: [u32; 2],
+}
+
+impl CullParams {
+  fn new(model: &model::Model, instance_count: u32) -> Self {
+    // Models without vertices have no bounds; an infinite sphere is never culled
+    let (center, radius) = match model.bounding_sphere() {
+      Some(sphere) => (sphere.center.into(), sphere.radius),
+      None => ([0.0; 3], f32::INFINITY),
+    };
+    Self {
+      center,
+      radius,
+      instance_count,
+      mesh_count: model.meshes.len() as u32,
+      _padding: [0; 2],
+    }
+  }
+}
+
+// Per-object buffers of a DrawMethod::GpuCulled object: the compacted instances it
+// draws from and what the cull pass needs to know about it
+pub struct CullTarget {
+  pub culled_buffer: wgpu::Buffer,
+  params_buffer: wgpu::Buffer,
+  // Built against the culler's layout the first time the object is culled
+  bind_group: Option<wgpu::BindGroup>,
+  instance_count: u32,
+  // Instances `culled_buffer` has room for
+  capacity: u32,
+}
+
+impl CullTarget {
+  pub fn new(device: &wgpu::Device, model: &model::Model, instance_count: u32) -> Self {
+    let size = instance_count.max(1) as wgpu::BufferAddress * std::mem::size_of::<instance::InstanceRaw>() as wgpu::BufferAddress;
+    Self {
+      culled_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Culled Instance Buffer"),
+        size,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+      }),
+      params_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Cull Params Buffer"),
+        contents: bytemuck::cast_slice(&[CullParams::new(model, instance_count)]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      }),
+      bind_group: None,
+      instance_count,
+      capacity: instance_count,
+    }
+  }
+
+  // After the object's instances were rewritten. The culled buffer and the
+  // cached bind group are sized for the instances the object was created with,
+  // so the count can only shrink; returns false, leaving the old count, if it grew
+  pub fn update(&mut self, queue: &wgpu::Queue, model: &model::Model, instance_count: u32) -> bool {
+    if instance_count > self.capacity {
+      log::warn!(
+        "GpuCulled object grew from {} to {} instances; recreate it instead",
+        self.capacity,
+        instance_count,
+      );
+      return false;
+    }
+    self.instance_count = instance_count;
+    queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[CullParams::new(model, instance_count)]));
+    true
+  }
+}
+
+// Compute pass run before the frame's render passes that frustum-culls every
+// GpuCulled object against the main camera, leaving the survivors and their
+// count in the object's culled buffer and indirect args. Every viewport draws
+// those same survivors, so instances only a secondary viewport's camera sees
+// are missing from it
+pub(crate) struct GpuCuller {
+  pipeline: wgpu::ComputePipeline,
+  layout: wgpu::BindGroupLayout,
+  frustum_buffer: wgpu::Buffer,
+}
+
+impl GpuCuller {
+  pub fn new(device: &wgpu::Device) -> Self {
+    let uniform = |binding| wgpu::BindGroupLayoutEntry {
+      binding,
+      visibility: wgpu::ShaderStages::COMPUTE,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    };
+    let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+      binding,
+      visibility: wgpu::ShaderStages::COMPUTE,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Storage { read_only },
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    };
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      entries: &[uniform(0), uniform(1), storage(2, true), storage(3, false), storage(4, false)],
+      label: Some("cull_bind_group_layout"),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("Cull Pipeline Layout"),
+      bind_group_layouts: &[&layout],
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some("Cull Shader"),
+      source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/cull.wgsl").into()),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+      label: Some("Cull Pipeline"),
+      layout: Some(&pipeline_layout),
+      module: &shader,
+      entry_point: Some("cs_main"),
+      compilation_options: Default::default(),
+      cache: None,
+    });
+
+    let frustum_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Cull Frustum Buffer"),
+      contents: bytemuck::cast_slice(&[FrustumUniform::new(Matrix4::identity())]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    Self {
+      pipeline,
+      layout,
+      frustum_buffer,
+    }
+  }
+
+  // `objects` are (instance buffer, indirect args, mesh count, target) for each
+  // object to cull this frame
+  pub fn encode<'a>(
+    &self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    encoder: &mut wgpu::CommandEncoder,
+    view_proj: Matrix4<f32>,
+    objects: impl Iterator<Item = (&'a wgpu::Buffer, &'a wgpu::Buffer, usize, &'a mut CullTarget)>,
+  ) {
+    let mut dispatches = Vec::new();
+    for (instance_buffer, indirect_buffer, mesh_count, target) in objects {
+      // The pass counts up from zero
+      for mesh in 0..mesh_count as wgpu::BufferAddress {
+        encoder.clear_buffer(indirect_buffer, mesh * INDIRECT_ARGS_SIZE + INSTANCE_COUNT_OFFSET, Some(4));
+      }
+      if target.instance_count == 0 {
+        continue;
+      }
+      if target.bind_group.is_none() {
+        target.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+          layout: &self.layout,
+          entries: &[
+            wgpu::BindGroupEntry {
+              binding: 0,
+              resource: self.frustum_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+              binding: 1,
+              resource: target.params_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+              binding: 2,
+              resource: instance_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+              binding: 3,
+              resource: target.culled_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+              binding: 4,
+              resource: indirect_buffer.as_entire_binding(),
+            },
+          ],
+          label: Some("cull_bind_group"),
+        }));
+      }
+      let bind_group = target.bind_group.clone().expect("created above");
+      dispatches.push((bind_group, target.instance_count.div_ceil(WORKGROUP_SIZE)));
+    }
+    if dispatches.is_empty() {
+      return;
+    }
+
+    queue.write_buffer(&self.frustum_buffer, 0, bytemuck::cast_slice(&[FrustumUniform::new(view_proj)]));
+    let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+      label: Some("Cull Pass"),
+      timestamp_writes: None,
+    });
+    compute_pass.set_pipeline(&self.pipeline);
+    for (bind_group, workgroups) in &dispatches {
+      compute_pass.set_bind_group(0, bind_group, &[]);
+      compute_pass.dispatch_workgroups(*workgroups, 1, 1);
+    }
+  }
+}
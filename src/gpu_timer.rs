@@ -0,0 +1,198 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use crate::render_graph;
+
+// Built-in passes bracketed by a pair of timestamps every frame, in query order
+pub(crate) const TIMED_PASSES: [&str; 3] = [
+  render_graph::SHADOW_PASS,
+  render_graph::SCENE_PASS,
+  render_graph::TONEMAP_PASS,
+];
+pub(crate) const SHADOW_SPAN: usize = 0;
+pub(crate) const SCENE_SPAN: usize = 1;
+pub(crate) const TONEMAP_SPAN: usize = 2;
+
+const QUERY_COUNT: u32 = 2 * TIMED_PASSES.len() as u32;
+const QUERIES_SIZE: wgpu::BufferAddress = QUERY_COUNT as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+// Frames whose results can be in flight at once; a frame finding them all busy
+// goes untimed rather than waiting
+const READBACK_COUNT: usize = 3;
+
+// Set by the map callback
+const MAP_PENDING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+struct Readback {
+  buffer: wgpu::Buffer,
+  // Spans the frame copied into this buffer actually wrote
+  written: u32,
+  // Which resolve filled it, so the newest result wins when several finish together
+  frame: u64,
+  in_flight: bool,
+  status: Arc<AtomicU8>,
+}
+
+// Pass durations from timestamp queries, read back a few frames late so the CPU
+// never waits on them. Needs Features::TIMESTAMP_QUERY
+pub(crate) struct GpuTimer {
+  query_set: wgpu::QuerySet,
+  resolve_buffer: wgpu::Buffer,
+  readbacks: Vec<Readback>,
+  // Nanoseconds per timestamp tick
+  period: f32,
+  // Spans written so far in the frame being encoded
+  written: Cell<u32>,
+  // Slot the current frame resolved into, mapped once it's submitted
+  pending: Option<usize>,
+  frames_resolved: u64,
+  // Frame `last` was read from
+  last_frame: u64,
+  last: [Option<f32>; TIMED_PASSES.len()],
+}
+
+impl GpuTimer {
+  pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+    if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+      return None;
+    }
+    let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+      label: Some("GPU Timer Queries"),
+      ty: wgpu::QueryType::Timestamp,
+      count: QUERY_COUNT,
+    });
+    let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("GPU Timer Resolve Buffer"),
+      size: QUERIES_SIZE,
+      usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+      mapped_at_creation: false,
+    });
+    let readbacks = (0..READBACK_COUNT)
+      .map(|_| Readback {
+        buffer: device.create_buffer(&wgpu::BufferDescriptor {
+          label: Some("GPU Timer Readback Buffer"),
+          size: QUERIES_SIZE,
+          usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+          mapped_at_creation: false,
+        }),
+        written: 0,
+        frame: 0,
+        in_flight: false,
+        status: Arc::new(AtomicU8::new(MAP_PENDING)),
+      })
+      .collect();
+
+    Some(Self {
+      query_set,
+      resolve_buffer,
+      readbacks,
+      period: queue.get_timestamp_period(),
+      written: Cell::new(0),
+      pending: None,
+      frames_resolved: 0,
+      last_frame: 0,
+      last: [None; TIMED_PASSES.len()],
+    })
+  }
+
+  // Picks up whichever earlier frames have finished; call before encoding a new one
+  pub fn begin_frame(&mut self, device: &wgpu::Device) {
+    // Runs the map callbacks of finished frames without blocking
+    let _ = device.poll(wgpu::PollType::Poll);
+    for readback in self.readbacks.iter_mut().filter(|readback| readback.in_flight) {
+      match readback.status.load(Ordering::Acquire) {
+        MAP_DONE => {
+          if readback.frame > self.last_frame {
+            let data = readback.buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+            for (span, last) in self.last.iter_mut().enumerate() {
+              *last = (readback.written & (1 << span) != 0).then(|| {
+                let ticks = timestamps[2 * span + 1].saturating_sub(timestamps[2 * span]);
+                ticks as f32 * self.period / 1_000_000.0
+              });
+            }
+            self.last_frame = readback.frame;
+          }
+          readback.buffer.unmap();
+        }
+        MAP_FAILED => {}
+        _ => continue,
+      }
+      readback.in_flight = false;
+      readback.status.store(MAP_PENDING, Ordering::Release);
+    }
+    self.written.set(0);
+  }
+
+  // For a span drawn as several passes, the first gets `begin` and the last `end`
+  pub fn render_pass_writes(&self, span: usize, begin: bool, end: bool) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+    if !(begin || end) {
+      return None;
+    }
+    if end {
+      self.written.set(self.written.get() | 1 << span);
+    }
+    let first = 2 * span as u32;
+    Some(wgpu::RenderPassTimestampWrites {
+      query_set: &self.query_set,
+      beginning_of_pass_write_index: begin.then_some(first),
+      end_of_pass_write_index: end.then_some(first + 1),
+    })
+  }
+
+  // After the timed passes, in the same encoder
+  pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+    let written = self.written.get();
+    let Some(slot) = self.readbacks.iter().position(|readback| !readback.in_flight) else {
+      return;
+    };
+    if written == 0 {
+      return;
+    }
+    encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.resolve_buffer, 0);
+    encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readbacks[slot].buffer, 0, QUERIES_SIZE);
+    self.frames_resolved += 1;
+    self.readbacks[slot].written = written;
+    self.readbacks[slot].frame = self.frames_resolved;
+    self.readbacks[slot].in_flight = true;
+    self.pending = Some(slot);
+  }
+
+  // Once the encoder passed to `resolve` has been submitted
+  pub fn after_submit(&mut self) {
+    let Some(slot) = self.pending.take() else {
+      return;
+    };
+    let status = self.readbacks[slot].status.clone();
+    self.readbacks[slot].buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+      match result {
+        Ok(()) => status.store(MAP_DONE, Ordering::Release),
+        Err(e) => {
+          log::warn!("GPU timer readback buffer couldn't be mapped: {}", e);
+          status.store(MAP_FAILED, Ordering::Release);
+        }
+      }
+    });
+  }
+
+  // Waits for every submitted frame, so `last_*` reflect the most recent one.
+  // Polling never blocks on wasm, hence native only
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn wait(&mut self, device: &wgpu::Device) {
+    if let Err(e) = device.poll(wgpu::PollType::wait_indefinitely()) {
+      log::warn!("GPU timer readback failed: {}", e);
+    }
+    self.begin_frame(device);
+  }
+
+  pub fn last_pass_ms(&self, span: usize) -> Option<f32> {
+    self.last[span]
+  }
+
+  // Sum over the passes that ran in the last timed frame
+  pub fn last_frame_ms(&self) -> Option<f32> {
+    self.last.iter().flatten().copied().reduce(|a, b| a + b)
+  }
+}
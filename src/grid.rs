@@ -0,0 +1,74 @@
+use wgpu::util::DeviceExt;
+
+use crate::debug_lines::{LineSegment, LineVertex};
+
+// Square line grid on the XZ plane centered on the origin, drawn with the debug
+// line pipeline (camera only, no lighting)
+pub struct Grid {
+  buffer: wgpu::Buffer,
+  segment_count: u32,
+  size: f32,
+  divisions: u32,
+  color: [f32; 3],
+  pub visible: bool,
+}
+
+impl Grid {
+  pub fn new(device: &wgpu::Device, size: f32, divisions: u32, color: [f32; 3]) -> Self {
+    let cells = divisions.max(1);
+    let half = size * 0.5;
+    let step = size / cells as f32;
+    let segment = |a, b| LineSegment {
+      a: LineVertex { position: a, color },
+      b: LineVertex { position: b, color },
+    };
+    let mut segments = Vec::with_capacity((cells as usize + 1) * 2);
+    for i in 0..=cells {
+      let offset = -half + step * i as f32;
+      segments.push(segment([offset, 0.0, -half], [offset, 0.0, half]));
+      segments.push(segment([-half, 0.0, offset], [half, 0.0, offset]));
+    }
+
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Grid Buffer"),
+      contents: bytemuck::cast_slice(&segments),
+      usage: wgpu::BufferUsages::VERTEX,
+    });
+
+    Self {
+      buffer,
+      segment_count: segments.len() as u32,
+      size,
+      divisions,
+      color,
+      visible: true,
+    }
+  }
+
+  // Same grid on another device
+  pub fn recreate(&self, device: &wgpu::Device) -> Self {
+    Self {
+      visible: self.visible,
+      ..Self::new(device, self.size, self.divisions, self.color)
+    }
+  }
+
+  // Returns whether anything was drawn
+  pub fn draw<'a>(
+    &'a self,
+    render_pass: &mut wgpu::RenderPass<'a>,
+    pipeline: &'a wgpu::RenderPipeline,
+    camera_bind_group: &'a wgpu::BindGroup,
+    line_style: (&'a wgpu::BindGroup, u32),
+  ) -> bool {
+    if !self.visible {
+      return false;
+    }
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, camera_bind_group, &[]);
+    render_pass.set_bind_group(1, line_style.0, &[line_style.1]);
+    render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+    render_pass.draw(0..LineSegment::VERTEX_COUNT, 0..self.segment_count);
+    true
+  }
+}
@@ -0,0 +1,18 @@
+// What the renderer did with an input event. Anything `Unhandled` is the host
+// app's to act on, e.g. `App` exits on an unhandled Escape
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputAction {
+  // Used by the renderer, typically the camera controller
+  Handled,
+  Unhandled,
+}
+
+impl From<bool> for InputAction {
+  fn from(handled: bool) -> Self {
+    if handled {
+      InputAction::Handled
+    } else {
+      InputAction::Unhandled
+    }
+  }
+}
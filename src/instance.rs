@@ -6,17 +6,57 @@ use crate::model;
 pub struct Instance {
   pub position: cgmath::Vector3<f32>,
   pub rotation: cgmath::Quaternion<f32>,
+  pub scale: cgmath::Vector3<f32>,
 }
 
 impl Instance {
-  pub fn to_raw(&self) -> InstanceRaw {
-    InstanceRaw {
-      model: (cgmath::Matrix4::from_translation(self.position)
-        * cgmath::Matrix4::from(self.rotation))
-      .into(),
-      normal: cgmath::Matrix3::from(self.rotation).into(),
+  // Unit-scale instance rotated by yaw about +Y, then pitch about the turned +X,
+  // then roll about the resulting +Z; pass `cgmath::Deg` for degrees
+  pub fn from_euler<Y: Into<cgmath::Rad<f32>>, P: Into<cgmath::Rad<f32>>, R: Into<cgmath::Rad<f32>>>(
+    position: cgmath::Vector3<f32>,
+    yaw: Y,
+    pitch: P,
+    roll: R,
+  ) -> Self {
+    Self {
+      position,
+      rotation: euler_rotation(yaw.into(), pitch.into(), roll.into()),
+      scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
     }
   }
+
+  // Replaces `rotation`, which stays the stored orientation; angles aren't kept
+  pub fn set_euler<Y: Into<cgmath::Rad<f32>>, P: Into<cgmath::Rad<f32>>, R: Into<cgmath::Rad<f32>>>(
+    &mut self,
+    yaw: Y,
+    pitch: P,
+    roll: R,
+  ) {
+    self.rotation = euler_rotation(yaw.into(), pitch.into(), roll.into());
+  }
+
+  pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+    cgmath::Matrix4::from_translation(self.position)
+      * cgmath::Matrix4::from(self.rotation)
+      * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+  }
+
+  pub fn to_raw(&self) -> InstanceRaw {
+    InstanceRaw::from_matrix(&self.model_matrix())
+  }
+}
+
+fn euler_rotation(yaw: cgmath::Rad<f32>, pitch: cgmath::Rad<f32>, roll: cgmath::Rad<f32>) -> cgmath::Quaternion<f32> {
+  cgmath::Quaternion::from_angle_y(yaw)
+    * cgmath::Quaternion::from_angle_x(pitch)
+    * cgmath::Quaternion::from_angle_z(roll)
+}
+
+// Inverse-transpose of the upper-left 3x3, which keeps normals perpendicular to
+// surfaces under non-uniform scale and shear (it reduces to the rotation otherwise)
+pub fn normal_matrix(model: &cgmath::Matrix4<f32>) -> cgmath::Matrix3<f32> {
+  let linear = cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+  linear.invert().map(|m| m.transpose()).unwrap_or(linear)
 }
 
 #[repr(C)]
@@ -26,48 +66,71 @@ pub struct InstanceRaw {
   normal: [[f32; 3]; 3],
 }
 
+impl InstanceRaw {
+  // Any affine model matrix, shear included
+  pub fn from_matrix(model: &cgmath::Matrix4<f32>) -> Self {
+    Self {
+      model: (*model).into(),
+      normal: normal_matrix(model).into(),
+    }
+  }
+}
+
+const MODEL_OFFSET: wgpu::BufferAddress = mem::offset_of!(InstanceRaw, model) as wgpu::BufferAddress;
+const NORMAL_OFFSET: wgpu::BufferAddress = mem::offset_of!(InstanceRaw, normal) as wgpu::BufferAddress;
+const VEC4: wgpu::BufferAddress = mem::size_of::<[f32; 4]>() as wgpu::BufferAddress;
+const VEC3: wgpu::BufferAddress = mem::size_of::<[f32; 3]>() as wgpu::BufferAddress;
+
+// Offsets come from the struct itself so reordering or adding fields can't silently misalign them
+const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 7] = [
+  wgpu::VertexAttribute {
+    offset: MODEL_OFFSET,
+    shader_location: 5,
+    format: wgpu::VertexFormat::Float32x4,
+  },
+  wgpu::VertexAttribute {
+    offset: MODEL_OFFSET + VEC4,
+    shader_location: 6,
+    format: wgpu::VertexFormat::Float32x4,
+  },
+  wgpu::VertexAttribute {
+    offset: MODEL_OFFSET + VEC4 * 2,
+    shader_location: 7,
+    format: wgpu::VertexFormat::Float32x4,
+  },
+  wgpu::VertexAttribute {
+    offset: MODEL_OFFSET + VEC4 * 3,
+    shader_location: 8,
+    format: wgpu::VertexFormat::Float32x4,
+  },
+  wgpu::VertexAttribute {
+    offset: NORMAL_OFFSET,
+    shader_location: 9,
+    format: wgpu::VertexFormat::Float32x3,
+  },
+  wgpu::VertexAttribute {
+    offset: NORMAL_OFFSET + VEC3,
+    shader_location: 10,
+    format: wgpu::VertexFormat::Float32x3,
+  },
+  wgpu::VertexAttribute {
+    offset: NORMAL_OFFSET + VEC3 * 2,
+    shader_location: 11,
+    format: wgpu::VertexFormat::Float32x3,
+  },
+];
+
+const _: () = assert!(
+  model::attribute_span(&INSTANCE_ATTRIBUTES) == mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+  "InstanceRaw has bytes not covered by a vertex attribute",
+);
+
 impl model::Vertex for InstanceRaw {
   fn desc() -> wgpu::VertexBufferLayout<'static> {
     wgpu::VertexBufferLayout {
       array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
       step_mode: wgpu::VertexStepMode::Instance,
-      attributes: &[
-        wgpu::VertexAttribute {
-          offset: 0,
-          shader_location: 5,
-          format: wgpu::VertexFormat::Float32x4,
-        },
-        wgpu::VertexAttribute {
-          offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-          shader_location: 6,
-          format: wgpu::VertexFormat::Float32x4,
-        },
-        wgpu::VertexAttribute {
-          offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-          shader_location: 7,
-          format: wgpu::VertexFormat::Float32x4,
-        },
-        wgpu::VertexAttribute {
-          offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-          shader_location: 8,
-          format: wgpu::VertexFormat::Float32x4,
-        },
-        wgpu::VertexAttribute {
-          offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
-          shader_location: 9,
-          format: wgpu::VertexFormat::Float32x3,
-        },
-        wgpu::VertexAttribute {
-          offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
-          shader_location: 10,
-          format: wgpu::VertexFormat::Float32x3,
-        },
-        wgpu::VertexAttribute {
-          offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
-          shader_location: 11,
-          format: wgpu::VertexFormat::Float32x3,
-        },
-      ],
+      attributes: &INSTANCE_ATTRIBUTES,
     }
   }
 }
@@ -92,7 +155,29 @@ pub fn create_instances() -> Vec<Instance> {
       Instance {
         position,
         rotation,
+        scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
       }
     })
   }).collect::<Vec<_>>()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn normal_matrix_keeps_normals_perpendicular_under_non_uniform_scale() {
+    let model = cgmath::Matrix4::from_translation(cgmath::Vector3::new(3.0, -1.0, 2.0))
+      * cgmath::Matrix4::from_angle_y(cgmath::Deg(30.0))
+      * cgmath::Matrix4::from_nonuniform_scale(2.0, 1.0, 0.5);
+    // A surface tilted between x and y, so the scale skews it
+    let normal = cgmath::Vector3::new(1.0, 1.0, 0.0).normalize();
+    let tangent = cgmath::Vector3::new(1.0, -1.0, 0.0);
+
+    let transformed_normal = normal_matrix(&model) * normal;
+    let transformed_tangent = (model * tangent.extend(0.0)).truncate();
+    assert!(transformed_normal.dot(transformed_tangent).abs() < 1e-5);
+    // Transformed by the model matrix itself, the normal tilts off the surface
+    let naive_normal = (model * normal.extend(0.0)).truncate();
+    assert!(naive_normal.dot(transformed_tangent).abs() > 0.1);
+  }
+}
@@ -0,0 +1,155 @@
+use anyhow::*;
+
+const IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+// The parts of a KTX2 file needed to upload it as-is: a single 2D image with
+// its mip chain, stored without supercompression
+pub struct Ktx2 {
+  pub format: wgpu::TextureFormat,
+  pub width: u32,
+  pub height: u32,
+  // Largest level first
+  pub levels: Vec<Vec<u8>>,
+}
+
+impl Ktx2 {
+  pub fn parse(bytes: &[u8]) -> Result<Self> {
+    if bytes.len() < HEADER_SIZE || bytes[..12] != IDENTIFIER {
+      bail!("not a KTX2 file");
+    }
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+    let vk_format = u32_at(12);
+    let width = u32_at(20);
+    let height = u32_at(24);
+    let depth = u32_at(28);
+    let layers = u32_at(32);
+    let faces = u32_at(36);
+    let level_count = u32_at(40).max(1);
+    let supercompression = u32_at(44);
+
+    let format = vk_format_to_wgpu(vk_format)
+      .with_context(|| format!("unsupported KTX2 vkFormat {}", vk_format))?;
+    if width == 0 || height == 0 || depth > 1 || layers > 1 || faces != 1 {
+      bail!("only single 2D KTX2 textures are supported");
+    }
+    // Each level at least halves the larger side, down to 1x1
+    let max_levels = 32 - width.max(height).leading_zeros();
+    if level_count > max_levels {
+      bail!("KTX2 file has {} levels, more than the {} a {}x{} texture can have", level_count, max_levels, width, height);
+    }
+    if supercompression != 0 {
+      bail!("supercompressed KTX2 files (scheme {}) aren't supported", supercompression);
+    }
+
+    let mut levels = Vec::with_capacity(level_count as usize);
+    for level in 0..level_count as usize {
+      let entry = HEADER_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+      let u64_at = |offset: usize| -> Result<usize> {
+        let field = bytes.get(offset..offset + 8).context("truncated KTX2 level index")?;
+        Ok(u64::from_le_bytes(field.try_into().unwrap()) as usize)
+      };
+      let offset = u64_at(entry)?;
+      let length = u64_at(entry + 8)?;
+      let data = offset
+        .checked_add(length)
+        .and_then(|end| bytes.get(offset..end))
+        .with_context(|| format!("KTX2 level {} is out of bounds", level))?;
+      let expected = level_size(format, width, height, level as u32);
+      if data.len() as u64 != expected {
+        bail!("KTX2 level {} is {} bytes, expected {}", level, data.len(), expected);
+      }
+      levels.push(data.to_vec());
+    }
+
+    Ok(Self { format, width, height, levels })
+  }
+}
+
+// Bytes in mip `level` of a tightly packed `format` image, as KTX2 stores them
+fn level_size(format: wgpu::TextureFormat, width: u32, height: u32, level: u32) -> u64 {
+  let (block_width, block_height) = format.block_dimensions();
+  let block_size = format.block_copy_size(None).unwrap_or(0);
+  let blocks_wide = (width >> level).max(1).div_ceil(block_width) as u64;
+  let blocks_high = (height >> level).max(1).div_ceil(block_height) as u64;
+  blocks_wide * blocks_high * block_size as u64
+}
+
+// VkFormat values from the Vulkan spec, for the formats wgpu can sample. wgpu has
+// no BC1 RGB format, so BC1 RGB files (131 and 133) load as BC1 RGBA: the blocks
+// decode the same, except that texels an encoder put in the punch-through slot
+// of a 3-color block come out transparent black rather than opaque black
+fn vk_format_to_wgpu(vk_format: u32) -> Option<wgpu::TextureFormat> {
+  use wgpu::{AstcBlock, AstcChannel, TextureFormat as F};
+
+  Some(match vk_format {
+    37 => F::Rgba8Unorm,
+    43 => F::Rgba8UnormSrgb,
+    131 | 133 => F::Bc1RgbaUnorm,
+    132 | 134 => F::Bc1RgbaUnormSrgb,
+    135 => F::Bc2RgbaUnorm,
+    136 => F::Bc2RgbaUnormSrgb,
+    137 => F::Bc3RgbaUnorm,
+    138 => F::Bc3RgbaUnormSrgb,
+    139 => F::Bc4RUnorm,
+    140 => F::Bc4RSnorm,
+    141 => F::Bc5RgUnorm,
+    142 => F::Bc5RgSnorm,
+    143 => F::Bc6hRgbUfloat,
+    144 => F::Bc6hRgbFloat,
+    145 => F::Bc7RgbaUnorm,
+    146 => F::Bc7RgbaUnormSrgb,
+    147 => F::Etc2Rgb8Unorm,
+    148 => F::Etc2Rgb8UnormSrgb,
+    149 => F::Etc2Rgb8A1Unorm,
+    150 => F::Etc2Rgb8A1UnormSrgb,
+    151 => F::Etc2Rgba8Unorm,
+    152 => F::Etc2Rgba8UnormSrgb,
+    153 => F::EacR11Unorm,
+    154 => F::EacR11Snorm,
+    155 => F::EacRg11Unorm,
+    156 => F::EacRg11Snorm,
+    157 => F::Astc { block: AstcBlock::B4x4, channel: AstcChannel::Unorm },
+    158 => F::Astc { block: AstcBlock::B4x4, channel: AstcChannel::UnormSrgb },
+    _ => return None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A one-level RGBA8 file of `width`x`height` whose level holds `data_len` bytes
+  fn rgba8_file(width: u32, height: u32, level_count: u32, data_len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; HEADER_SIZE + LEVEL_INDEX_ENTRY_SIZE];
+    bytes[..12].copy_from_slice(&IDENTIFIER);
+    for (offset, value) in [(12, 37), (20, width), (24, height), (36, 1), (40, level_count)] {
+      bytes[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(value));
+    }
+    let (entry, data_offset) = (HEADER_SIZE, bytes.len() as u64);
+    bytes[entry..entry + 8].copy_from_slice(&data_offset.to_le_bytes());
+    bytes[entry + 8..entry + 16].copy_from_slice(&(data_len as u64).to_le_bytes());
+    bytes.resize(bytes.len() + data_len, 0xff);
+    bytes
+  }
+
+  #[test]
+  fn parses_a_matching_level() {
+    let ktx2 = Ktx2::parse(&rgba8_file(2, 2, 1, 16)).unwrap();
+    assert_eq!((ktx2.width, ktx2.height, ktx2.levels.len()), (2, 2, 1));
+  }
+
+  #[test]
+  fn rejects_zero_width() {
+    assert!(Ktx2::parse(&rgba8_file(0, 2, 1, 16)).is_err());
+  }
+
+  #[test]
+  fn rejects_levels_that_dont_match_the_size() {
+    assert!(Ktx2::parse(&rgba8_file(2, 2, 1, 12)).is_err());
+    // A 2x2 texture has two levels at most
+    assert!(Ktx2::parse(&rgba8_file(2, 2, 3, 16)).is_err());
+  }
+}
@@ -4,24 +4,95 @@ use wasm_bindgen::prelude::*;
 use winit::event_loop::EventLoop;
 
 mod app;
+mod background;
+// Waits on the device between frames, which wasm can't do
+#[cfg(not(target_arch = "wasm32"))]
+mod benchmark;
+mod bounds;
 mod camera;
+mod compute_pipeline_manager;
+mod config;
+mod debug_lines;
+mod debug_view;
+mod diagnostics;
+mod fog;
+mod gpu_culling;
+mod gpu_timer;
 mod draw_traits;
+#[cfg(feature = "egui")]
+mod egui_layer;
+mod grid;
+mod input;
 mod instance;
+mod ktx2;
 mod light;
 mod model;
+mod object_transforms;
+mod outline;
+#[cfg(not(target_arch = "wasm32"))]
+mod picking;
 mod pipeline;
 mod pipeline_manager;
+mod push_constants;
+mod render_context;
+mod render_graph;
+mod render_stats;
 mod renderable_object;
 mod resources;
+mod scene;
+mod shader_rewrite;
+mod shadow;
+mod skinning;
 mod state;
 mod texture;
+mod tonemap;
 mod uniforms;
+mod viewport;
+mod wireframe;
 
 pub use app::App;
+pub use background::Background;
+#[cfg(not(target_arch = "wasm32"))]
+pub use benchmark::{BenchmarkReport, FrameTimes};
+pub use bounds::{Aabb, Sphere};
+pub use camera::{Camera, CameraController, Projection, Ray};
+pub use config::RendererConfig;
+pub use debug_lines::DebugLines;
+pub use debug_view::DebugView;
+pub use diagnostics::RendererDiagnostics;
+pub use fog::Fog;
+pub use input::InputAction;
+pub use instance::{Instance, InstanceRaw};
+#[cfg(feature = "egui")]
+pub use egui;
 pub use draw_traits::{DrawWithMaterial, DrawWithoutMaterial, DrawMethod};
-pub use model::Vertex;
+pub use model::{primitives, Material, Mesh, Model, ModelVertex, Vertex, VertexFormatOption};
+pub use object_transforms::TransformBinding;
+pub use pipeline::{DepthConfig, PipelineOptions};
+pub use pipeline_manager::PipelineManager;
+pub use push_constants::PushConstantBinding;
+pub use render_context::RenderContext;
+pub use render_graph::{FrameResources, RenderGraph, BACKGROUND_PASS, COMPUTE_PASS, SCENE_PASS, SHADOW_PASS, TONEMAP_PASS};
+pub use render_stats::RenderStats;
+#[cfg(not(target_arch = "wasm32"))]
+pub use resources::{asset_root, set_asset_root};
+#[cfg(target_arch = "wasm32")]
+pub use resources::set_asset_base_url;
+pub use resources::{load_model, load_model_with_format, load_model_with_options, load_model_with_progress, load_texture, ModelLoadOptions};
+pub use renderable_object::{RenderableObject, VertexBufferBinding, DEFAULT_LAYER, OVERLAY_LAYER};
+pub use scene::{Scene, SceneCamera, SceneInstance, SceneLight, SceneObject};
+pub use shadow::ShadowConfig;
+pub use skinning::{AnimationClip, Channel, Joint, JointTransform, Keyframes, Skin};
+pub use state::{LostResources, State};
+pub use texture::{SamplerOptions, Texture};
+pub use tonemap::ToneMap;
+pub use viewport::ViewportRect;
 
 pub fn run() -> anyhow::Result<()> {
+  run_with_config(RendererConfig::default())
+}
+
+pub fn run_with_config(config: RendererConfig) -> anyhow::Result<()> {
   #[cfg(not(target_arch = "wasm32"))]
   {
       env_logger::init();
@@ -35,7 +106,7 @@ pub fn run() -> anyhow::Result<()> {
   let mut app = App::new(
       #[cfg(target_arch = "wasm32")]
       &event_loop,
-  );
+  ).with_config(config);
   event_loop.run_app(&mut app)?;
 
   Ok(())
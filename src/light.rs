@@ -1,3 +1,11 @@
+use std::sync::Arc;
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::{instance, model, shadow};
+use crate::draw_traits::DrawMethod;
+use crate::renderable_object::RenderableObject;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
@@ -16,4 +24,195 @@ impl LightUniform {
       _padding2: 0,
     }
   }
-}
\ No newline at end of file
+}
+pub const PIPELINE: &str = "light_pipeline";
+
+// Size of the debug mesh relative to the model it's drawn with
+const DEBUG_MESH_SCALE: f32 = 0.25;
+
+pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+  device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+    entries: &[
+      wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      },
+      // Shadow map, its comparison sampler and the light-space transform
+      wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+          multisampled: false,
+          view_dimension: wgpu::TextureViewDimension::D2,
+          sample_type: wgpu::TextureSampleType::Depth,
+        },
+        count: None,
+      },
+      wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+        count: None,
+      },
+      wgpu::BindGroupLayoutEntry {
+        binding: 3,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      },
+      // Fog, which applies everywhere the light does
+      wgpu::BindGroupLayoutEntry {
+        binding: 4,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      },
+    ],
+    label: Some("light_bind_group_layout"),
+  })
+}
+
+// The scene light: owns its uniform and, optionally, a mesh drawn at its position
+// with `PIPELINE`, so moving the light keeps both in sync
+pub struct LightObject {
+  uniform: LightUniform,
+  buffer: wgpu::Buffer,
+  pub bind_group: wgpu::BindGroup,
+  pub debug_mesh: Option<RenderableObject>,
+  // Hides `debug_mesh` without affecting the lighting
+  pub debug_mesh_visible: bool,
+  pub casts_shadows: bool,
+}
+
+impl LightObject {
+  pub fn new(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    shadow_map: &shadow::ShadowMap,
+    fog_buffer: &wgpu::Buffer,
+    position: [f32; 3],
+    color: [f32; 3],
+  ) -> Self {
+    let uniform = LightUniform::new(position, color);
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Light Buffer"),
+      contents: bytemuck::cast_slice(&[uniform]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let bind_group = Self::create_bind_group(device, layout, &buffer, shadow_map, fog_buffer);
+
+    Self {
+      uniform,
+      buffer,
+      bind_group,
+      debug_mesh: None,
+      debug_mesh_visible: true,
+      casts_shadows: true,
+    }
+  }
+
+  fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    shadow_map: &shadow::ShadowMap,
+    fog_buffer: &wgpu::Buffer,
+  ) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout,
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: wgpu::BindingResource::TextureView(shadow_map.view()),
+        },
+        wgpu::BindGroupEntry {
+          binding: 2,
+          resource: wgpu::BindingResource::Sampler(shadow_map.sampler()),
+        },
+        wgpu::BindGroupEntry {
+          binding: 3,
+          resource: shadow_map.uniform_buffer().as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 4,
+          resource: fog_buffer.as_entire_binding(),
+        },
+      ],
+      label: Some("light_bind_group"),
+    })
+  }
+
+  // After the shadow map was recreated
+  pub fn rebind(
+    &mut self,
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    shadow_map: &shadow::ShadowMap,
+    fog_buffer: &wgpu::Buffer,
+  ) {
+    self.bind_group = Self::create_bind_group(device, layout, &self.buffer, shadow_map, fog_buffer);
+  }
+
+  pub fn with_debug_mesh(mut self, device: &wgpu::Device, model: Arc<model::Model>) -> Self {
+    self.debug_mesh = Some(RenderableObject::new(
+      device,
+      model,
+      vec![Self::debug_instance(self.uniform.position)],
+      Some(PIPELINE.to_string()),
+      DrawMethod::WithoutMaterial,
+    ));
+    self
+  }
+
+  fn debug_instance(position: [f32; 3]) -> instance::Instance {
+    instance::Instance {
+      position: position.into(),
+      rotation: cgmath::Quaternion::one(),
+      scale: cgmath::Vector3::new(DEBUG_MESH_SCALE, DEBUG_MESH_SCALE, DEBUG_MESH_SCALE),
+    }
+  }
+
+  pub fn position(&self) -> [f32; 3] {
+    self.uniform.position
+  }
+
+  pub fn color(&self) -> [f32; 3] {
+    self.uniform.color
+  }
+
+  pub fn set_position(&mut self, queue: &wgpu::Queue, position: [f32; 3]) {
+    self.uniform.position = position;
+    self.write_uniform(queue);
+    if let Some(mesh) = &mut self.debug_mesh {
+      mesh.instances = vec![Self::debug_instance(position)];
+      mesh.update_instances(queue);
+    }
+  }
+
+  pub fn set_color(&mut self, queue: &wgpu::Queue, color: [f32; 3]) {
+    self.uniform.color = color;
+    self.write_uniform(queue);
+  }
+
+  fn write_uniform(&self, queue: &wgpu::Queue) {
+    queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+  }
+}
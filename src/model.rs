@@ -1,9 +1,31 @@
+use std::sync::Arc;
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::bounds::{Aabb, Sphere};
+use crate::skinning::Skin;
 use crate::texture;
 
+pub mod primitives;
+
 pub trait Vertex {
   fn desc() -> wgpu::VertexBufferLayout<'static>;
 }
 
+// End of the furthest attribute; equals the struct size when every byte is mapped
+pub const fn attribute_span(attributes: &[wgpu::VertexAttribute]) -> wgpu::BufferAddress {
+  let mut span = 0;
+  let mut i = 0;
+  while i < attributes.len() {
+    let end = attributes[i].offset + attributes[i].format.size();
+    if end > span {
+      span = end;
+    }
+    i += 1;
+  }
+  span
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertex {
@@ -12,6 +34,43 @@ pub struct ModelVertex {
   pub normal: [f32; 3],
   pub tangent: [f32; 3],
   pub bitangent: [f32; 3],
+  pub color: [f32; 3],
+  // Second UV channel (e.g. lightmaps); zero when the source only has one
+  pub tex_coords_1: [f32; 2],
+  // Up to four joints of the model's skin moving the vertex, and how much each
+  // does. Only the skinned pipeline reads them; all-zero weights leave the
+  // vertex where it is
+  pub joints: [u16; 4],
+  pub weights: [f32; 4],
+}
+
+const SKINNED_ATTRIBUTES: [wgpu::VertexAttribute; 9] = wgpu::vertex_attr_array![
+  0 => Float32x3,
+  1 => Float32x2,
+  2 => Float32x3,
+  3 => Float32x3,
+  4 => Float32x3,
+  12 => Float32x3,
+  13 => Float32x2,
+  14 => Uint16x4,
+  15 => Float32x4,
+];
+
+const _: () = assert!(
+  attribute_span(&SKINNED_ATTRIBUTES) == std::mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
+  "ModelVertex has bytes not covered by a skinned vertex attribute",
+);
+
+impl ModelVertex {
+  // `desc` plus the joints and weights at locations 14 and 15, which the other
+  // layouts leave free for PipelineOptions::extra_vertex_layouts
+  pub fn skinned_desc() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+      array_stride: std::mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
+      step_mode: wgpu::VertexStepMode::Vertex,
+      attributes: &SKINNED_ATTRIBUTES,
+    }
+  }
 }
 
 impl Vertex for ModelVertex {
@@ -46,33 +105,363 @@ impl Vertex for ModelVertex {
           shader_location: 4,
           format: wgpu::VertexFormat::Float32x3,
         },
+        // Locations 5-11 belong to the instance buffer
+        wgpu::VertexAttribute {
+          offset: mem::size_of::<[f32; 14]>() as wgpu::BufferAddress,
+          shader_location: 12,
+          format: wgpu::VertexFormat::Float32x3,
+        },
+        wgpu::VertexAttribute {
+          offset: mem::size_of::<[f32; 17]>() as wgpu::BufferAddress,
+          shader_location: 13,
+          format: wgpu::VertexFormat::Float32x2,
+        },
       ],
     }
   }
 }
 
+// How mesh vertices are laid out in their vertex buffers. Every pipeline is
+// built for one layout, so it's chosen renderer-wide through
+// `RendererConfig::vertex_format` and models have to be loaded with the same one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VertexFormatOption {
+  // ModelVertex as is, 100 bytes per vertex
+  #[default]
+  Float32,
+  // CompactVertex, 64 bytes per vertex. Positions lose precision away from the
+  // origin (half floats keep about 3 significant digits) and vertex colors are
+  // clamped to 0..1
+  Compact,
+}
+
+impl VertexFormatOption {
+  pub fn layout(self) -> wgpu::VertexBufferLayout<'static> {
+    match self {
+      VertexFormatOption::Float32 => ModelVertex::desc(),
+      VertexFormatOption::Compact => CompactVertex::desc(),
+    }
+  }
+
+  // For the skinned pipeline
+  pub fn skinned_layout(self) -> wgpu::VertexBufferLayout<'static> {
+    match self {
+      VertexFormatOption::Float32 => ModelVertex::skinned_desc(),
+      VertexFormatOption::Compact => CompactVertex::skinned_desc(),
+    }
+  }
+}
+
+// ModelVertex packed for bandwidth: positions as half floats and the normal basis
+// as normalized 16-bit integers, each padded to four components since vertex
+// formats only come in even sizes. Shaders read these locations as vec4 and use
+// `.xyz`, so the same shader serves both layouts
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CompactVertex {
+  // f16 bits, w = 1.0
+  pub position: [u16; 4],
+  // Kept at full precision so tiled UVs don't drift
+  pub tex_coords: [f32; 2],
+  pub normal: [i16; 4],
+  pub tangent: [i16; 4],
+  pub bitangent: [i16; 4],
+  pub color: [u8; 4],
+  pub tex_coords_1: [f32; 2],
+  pub joints: [u16; 4],
+  // Normalized 8-bit; the skinned shader rescales them to sum to one
+  pub weights: [u8; 4],
+}
+
+fn pack_snorm16(v: [f32; 3]) -> [i16; 4] {
+  // Only the direction matters to the shaders, which normalize after transforming
+  let v = cgmath::Vector3::from(v);
+  let length = cgmath::InnerSpace::magnitude(v);
+  let v = if length > 0.0 { v / length } else { v };
+  let pack = |x: f32| (x.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+  [pack(v.x), pack(v.y), pack(v.z), 0]
+}
+
+impl From<&ModelVertex> for CompactVertex {
+  fn from(v: &ModelVertex) -> Self {
+    let half = |x: f32| half::f16::from_f32(x).to_bits();
+    let unorm8 = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Self {
+      position: [half(v.position[0]), half(v.position[1]), half(v.position[2]), half(1.0)],
+      tex_coords: v.tex_coords,
+      normal: pack_snorm16(v.normal),
+      tangent: pack_snorm16(v.tangent),
+      bitangent: pack_snorm16(v.bitangent),
+      color: [unorm8(v.color[0]), unorm8(v.color[1]), unorm8(v.color[2]), 255],
+      tex_coords_1: v.tex_coords_1,
+      joints: v.joints,
+      weights: v.weights.map(unorm8),
+    }
+  }
+}
+
+impl Vertex for CompactVertex {
+  fn desc() -> wgpu::VertexBufferLayout<'static> {
+    use std::mem;
+    // Same shader locations as ModelVertex
+    const ATTRIBUTES: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
+      0 => Float16x4,
+      1 => Float32x2,
+      2 => Snorm16x4,
+      3 => Snorm16x4,
+      4 => Snorm16x4,
+      12 => Unorm8x4,
+      13 => Float32x2,
+    ];
+    wgpu::VertexBufferLayout {
+      array_stride: mem::size_of::<CompactVertex>() as wgpu::BufferAddress,
+      step_mode: wgpu::VertexStepMode::Vertex,
+      attributes: &ATTRIBUTES,
+    }
+  }
+}
+
+const COMPACT_SKINNED_ATTRIBUTES: [wgpu::VertexAttribute; 9] = wgpu::vertex_attr_array![
+  0 => Float16x4,
+  1 => Float32x2,
+  2 => Snorm16x4,
+  3 => Snorm16x4,
+  4 => Snorm16x4,
+  12 => Unorm8x4,
+  13 => Float32x2,
+  14 => Uint16x4,
+  15 => Unorm8x4,
+];
+
+const _: () = assert!(
+  attribute_span(&COMPACT_SKINNED_ATTRIBUTES) == std::mem::size_of::<CompactVertex>() as wgpu::BufferAddress,
+  "CompactVertex has bytes not covered by a skinned vertex attribute",
+);
+
+impl CompactVertex {
+  // As ModelVertex::skinned_desc
+  pub fn skinned_desc() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+      array_stride: std::mem::size_of::<CompactVertex>() as wgpu::BufferAddress,
+      step_mode: wgpu::VertexStepMode::Vertex,
+      attributes: &COMPACT_SKINNED_ATTRIBUTES,
+    }
+  }
+}
+
 pub struct Model {
   pub meshes: Vec<Mesh>,
   pub materials: Vec<Material>,
+  // Drawn for meshes whose material index is past `materials`, once
+  // `validate_materials` has created it
+  default_material: Option<Material>,
+  // Joints the vertices' `joints` index and the animations moving them; objects
+  // of skinned models are drawn with skinning::PIPELINE unless told otherwise
+  pub skin: Option<Skin>,
+}
+
+impl Model {
+  pub fn new(meshes: Vec<Mesh>, materials: Vec<Material>) -> Self {
+    Self {
+      meshes,
+      materials,
+      default_material: None,
+      skin: None,
+    }
+  }
+
+  pub fn with_skin(mut self, skin: Skin) -> Self {
+    self.skin = Some(skin);
+    self
+  }
+
+  pub fn default_material(&self) -> Option<&Material> {
+    self.default_material.as_ref()
+  }
+
+  // The mesh's own material, or the default one if its index is out of range;
+  // None only for models with bad indices that were never validated
+  pub fn mesh_material(&self, mesh: &Mesh) -> Option<&Material> {
+    self.materials.get(mesh.material).or(self.default_material.as_ref())
+  }
+
+  // Object-space bounds over all meshes
+  pub fn bounds(&self) -> Option<Aabb> {
+    self.meshes.iter().map(|m| m.bounds).reduce(|a, b| a.union(&b))
+  }
+
+  // Object-space sphere around every mesh's bounding sphere
+  pub fn bounding_sphere(&self) -> Option<Sphere> {
+    self.meshes.iter().map(|m| m.bounding_sphere).reduce(|a, b| a.union(&b))
+  }
+
+  pub fn total_vertices(&self) -> u64 {
+    self.meshes.iter().map(|mesh| mesh.vertex_count() as u64).sum()
+  }
+
+  pub fn total_triangles(&self) -> u64 {
+    self.meshes.iter().map(|mesh| mesh.triangle_count() as u64).sum()
+  }
+
+  // Whether any mesh is drawn with a see-through material
+  pub fn is_transparent(&self) -> bool {
+    self.meshes
+      .iter()
+      .filter_map(|mesh| self.mesh_material(mesh))
+      .any(Material::is_transparent)
+  }
+
+  // Whether some mesh's material index is out of range with no default material
+  // to draw it with yet
+  pub fn needs_default_material(&self) -> bool {
+    self.default_material.is_none() && self.meshes.iter().any(|mesh| mesh.material >= self.materials.len())
+  }
+
+  // Creates the default material if any mesh's material index is out of range,
+  // so every mesh has something to draw with
+  pub fn validate_materials(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+  ) {
+    if !self.needs_default_material() {
+      return;
+    }
+    let material_count = self.materials.len();
+    for mesh in self.meshes.iter().filter(|m| m.material >= material_count) {
+      log::warn!(
+        "Mesh {:?} references material {} but the model only has {}, using the default material",
+        mesh.name,
+        mesh.material,
+        material_count,
+      );
+    }
+    self.default_material = Some(Material::default_material(device, queue, layout));
+  }
 }
 
+// Scalar material parameters, bound next to the textures as a uniform
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+  emissive: [f32; 3],
+  shininess: f32,
+  specular: [f32; 3],
+  opacity: f32,
+}
+
+// Layout for material bind groups: diffuse texture and sampler, normal texture and
+// sampler, material uniform. A Filtering `sampler_binding` accepts nearest samplers
+// as well, so it covers materials mixing both modes; NonFiltering only accepts
+// samplers whose SamplerOptions::binding_type is NonFiltering, and a pipeline
+// drawing with it must be created against the same layout
+pub fn create_material_bind_group_layout(
+  device: &wgpu::Device,
+  sampler_binding: wgpu::SamplerBindingType,
+) -> wgpu::BindGroupLayout {
+  let label = match sampler_binding {
+    wgpu::SamplerBindingType::NonFiltering => "non_filtering_texture_bind_group_layout",
+    _ => "texture_bind_group_layout",
+  };
+  // Unfilterable entries also take filterable textures, so only layouts that may
+  // filter need to rule out data formats like R32Float
+  let filterable = sampler_binding != wgpu::SamplerBindingType::NonFiltering;
+  device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+    entries: &[
+      wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+          multisampled: false,
+          view_dimension: wgpu::TextureViewDimension::D2,
+          sample_type: wgpu::TextureSampleType::Float { filterable },
+        },
+        count: None,
+      },
+      wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(sampler_binding),
+        count: None,
+      },
+      wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+          multisampled: false,
+          view_dimension: wgpu::TextureViewDimension::D2,
+          sample_type: wgpu::TextureSampleType::Float { filterable },
+        },
+        count: None,
+      },
+      wgpu::BindGroupLayoutEntry {
+        binding: 3,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(sampler_binding),
+        count: None,
+      },
+      wgpu::BindGroupLayoutEntry {
+        binding: 4,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      },
+    ],
+    label: Some(label),
+  })
+}
+
+// Squares along each side of `Material::checkerboard`
+pub const CHECKERBOARD_CELLS: u32 = 8;
+
 pub struct Material {
   #[allow(unused)]
   pub name: String,
   #[allow(unused)]
-  pub diffuse_texture: texture::Texture,
+  pub diffuse_texture: Arc<texture::Texture>,
   #[allow(unused)]
-  pub normal_texture: texture::Texture,
+  pub normal_texture: Arc<texture::Texture>,
+  // Added on top of the lit color, unaffected by the scene's lights
+  pub emissive: [f32; 3],
+  pub specular_color: [f32; 3],
+  // Blinn-Phong exponent; higher is a tighter, shinier highlight
+  pub shininess: f32,
+  // Below 1.0 the material is drawn through the alpha-blended transparent pipeline
+  pub opacity: f32,
+  uniform_buffer: wgpu::Buffer,
   pub bind_group: wgpu::BindGroup,
 }
+
 impl Material {
   pub fn new(
     device: &wgpu::Device,
     name: &str,
-    diffuse_texture: texture::Texture,
-    normal_texture: texture::Texture,
+    diffuse_texture: impl Into<Arc<texture::Texture>>,
+    normal_texture: impl Into<Arc<texture::Texture>>,
     layout: &wgpu::BindGroupLayout,
   ) -> Self {
+    let diffuse_texture = diffuse_texture.into();
+    let normal_texture = normal_texture.into();
+    // Matches the fixed highlight the shader used before materials carried their own
+    let emissive = [0.0; 3];
+    let specular_color = [1.0; 3];
+    let shininess = 32.0;
+    let opacity = 1.0;
+    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some(&format!("{:?} Material Buffer", name)),
+      contents: bytemuck::cast_slice(&[MaterialUniform {
+        emissive,
+        shininess,
+        specular: specular_color,
+        opacity,
+      }]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
     let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
       layout,
       entries: &[
@@ -92,6 +481,10 @@ impl Material {
           binding: 3,
           resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
         },
+        wgpu::BindGroupEntry {
+          binding: 4,
+          resource: uniform_buffer.as_entire_binding(),
+        },
       ],
       label: Some(name),
     });
@@ -100,9 +493,92 @@ impl Material {
       name: String::from(name),
       diffuse_texture, 
       normal_texture, 
+      emissive,
+      specular_color,
+      shininess,
+      opacity,
+      uniform_buffer,
       bind_group 
     }
   }
+
+  // Plain white diffuse with a flat normal map, for meshes that don't come with textures
+  pub fn default_material(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+  ) -> Self {
+    let diffuse_texture = texture::Texture::from_color(device, queue, [255, 255, 255, 255], "default_diffuse", false);
+    let normal_texture = texture::Texture::from_color(device, queue, [128, 128, 255, 255], "default_normal", true);
+    Self::new(device, "default_material", diffuse_texture, normal_texture, layout)
+  }
+
+  // Procedural UV test pattern: a `size`x`size` texture of CHECKERBOARD_CELLS
+  // squares per side, sampled with nearest filtering and repeat so texel density
+  // and seams stay visible
+  pub fn checkerboard(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    size: u32,
+    color_a: [u8; 4],
+    color_b: [u8; 4],
+  ) -> Self {
+    let size = size.max(CHECKERBOARD_CELLS);
+    let cell = size / CHECKERBOARD_CELLS;
+    let img = image::RgbaImage::from_fn(size, size, |x, y| {
+      if (x / cell + y / cell).is_multiple_of(2) {
+        image::Rgba(color_a)
+      } else {
+        image::Rgba(color_b)
+      }
+    });
+    let sampler_options = texture::SamplerOptions::nearest().with_address_mode(wgpu::AddressMode::Repeat);
+    let diffuse_texture = texture::Texture::from_image_with_options(
+      device,
+      queue,
+      &image::DynamicImage::ImageRgba8(img),
+      Some("checkerboard_diffuse"),
+      false,
+      sampler_options,
+    )
+    .expect("a generated RGBA image is always a valid texture");
+    let normal_texture = texture::Texture::from_color(device, queue, [128, 128, 255, 255], "checkerboard_normal", true);
+    Self::new(device, "checkerboard_material", diffuse_texture, normal_texture, layout)
+  }
+
+  pub fn with_emissive(mut self, queue: &wgpu::Queue, color: [f32; 3]) -> Self {
+    self.emissive = color;
+    self.write_uniform(queue);
+    self
+  }
+
+  pub fn with_specular(mut self, queue: &wgpu::Queue, color: [f32; 3], shininess: f32) -> Self {
+    self.specular_color = color;
+    self.shininess = shininess;
+    self.write_uniform(queue);
+    self
+  }
+
+  pub fn with_opacity(mut self, queue: &wgpu::Queue, opacity: f32) -> Self {
+    self.opacity = opacity.clamp(0.0, 1.0);
+    self.write_uniform(queue);
+    self
+  }
+
+  pub fn is_transparent(&self) -> bool {
+    self.opacity < 1.0
+  }
+
+  fn write_uniform(&self, queue: &wgpu::Queue) {
+    let uniform = MaterialUniform {
+      emissive: self.emissive,
+      shininess: self.shininess,
+      specular: self.specular_color,
+      opacity: self.opacity,
+    };
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+  }
 }
 
 pub struct Mesh {
@@ -110,6 +586,176 @@ pub struct Mesh {
   pub name: String,
   pub vertex_buffer: wgpu::Buffer,
   pub index_buffer: wgpu::Buffer,
+  pub index_format: wgpu::IndexFormat,
+  // Index count, drawn as num_elements / 3 triangles
   pub num_elements: u32,
+  vertex_count: u32,
   pub material: usize,
-}
\ No newline at end of file
+  pub bounds: Aabb,
+  // Computed once from the vertices, for culling against
+  pub bounding_sphere: Sphere,
+  // Layout of `vertex_buffer`
+  pub vertex_format: VertexFormatOption,
+}
+
+impl Mesh {
+  pub fn new(
+    device: &wgpu::Device,
+    name: &str,
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    material: usize,
+  ) -> Self {
+    Self::with_format(device, name, vertices, indices, material, VertexFormatOption::Float32)
+  }
+
+  // Uploads `vertices` converted to `vertex_format`
+  pub fn with_format(
+    device: &wgpu::Device,
+    name: &str,
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    material: usize,
+    vertex_format: VertexFormatOption,
+  ) -> Self {
+    let vertex_data = match vertex_format {
+      VertexFormatOption::Float32 => bytemuck::cast_slice(vertices).to_vec(),
+      VertexFormatOption::Compact => {
+        let vertices = vertices.iter().map(CompactVertex::from).collect::<Vec<_>>();
+        bytemuck::cast_slice(&vertices).to_vec()
+      }
+    };
+    // Also readable as storage where the device allows it, so the wireframe
+    // overlay can pull triangle edges straight from these buffers
+    let storage = if device.limits().max_storage_buffers_per_shader_stage > 0 {
+      wgpu::BufferUsages::STORAGE
+    } else {
+      wgpu::BufferUsages::empty()
+    };
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some(&format!("{:?} Vertex Buffer", name)),
+      contents: &vertex_data,
+      usage: wgpu::BufferUsages::VERTEX | storage,
+    });
+    // Halve the index buffer whenever every index fits in 16 bits
+    let (index_format, index_data) = if vertices.len() <= u16::MAX as usize {
+      let indices = indices.iter().map(|&i| i as u16).collect::<Vec<_>>();
+      (wgpu::IndexFormat::Uint16, bytemuck::cast_slice(&indices).to_vec())
+    } else {
+      (wgpu::IndexFormat::Uint32, bytemuck::cast_slice(indices).to_vec())
+    };
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some(&format!("{:?} Index Buffer", name)),
+      contents: &index_data,
+      usage: wgpu::BufferUsages::INDEX | storage,
+    });
+
+    Self {
+      name: name.to_string(),
+      vertex_buffer,
+      index_buffer,
+      index_format,
+      num_elements: indices.len() as u32,
+      vertex_count: vertices.len() as u32,
+      material,
+      bounds: Aabb::from_points(vertices.iter().map(|v| v.position.into()))
+        .unwrap_or(Aabb::new(cgmath::Point3::new(0.0, 0.0, 0.0), cgmath::Point3::new(0.0, 0.0, 0.0))),
+      bounding_sphere: Sphere::from_points(vertices.iter().map(|v| v.position.into()))
+        .unwrap_or(Sphere::new(cgmath::Point3::new(0.0, 0.0, 0.0), 0.0)),
+      vertex_format,
+    }
+  }
+
+  pub fn vertex_count(&self) -> u32 {
+    self.vertex_count
+  }
+
+  pub fn index_count(&self) -> u32 {
+    self.num_elements
+  }
+
+  pub fn triangle_count(&self) -> u32 {
+    self.num_elements / 3
+  }
+}
+
+// Smooth normals: each vertex gets the sum of its triangles' face normals,
+// weighted by area, normalized. Unreferenced or degenerate vertices point up
+pub fn compute_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+  let mut sums = vec![cgmath::Vector3::<f32>::zero(); vertices.len()];
+  for c in indices.chunks_exact(3) {
+    let pos0: cgmath::Vector3<_> = vertices[c[0] as usize].position.into();
+    let pos1: cgmath::Vector3<_> = vertices[c[1] as usize].position.into();
+    let pos2: cgmath::Vector3<_> = vertices[c[2] as usize].position.into();
+    // Counter-clockwise winding faces outward; the length is twice the area
+    let face_normal = (pos1 - pos0).cross(pos2 - pos0);
+    for &i in c {
+      sums[i as usize] += face_normal;
+    }
+  }
+
+  for (v, sum) in vertices.iter_mut().zip(sums) {
+    v.normal = if sum.magnitude2() > 0.0 {
+      sum.normalize().into()
+    } else {
+      [0.0, 1.0, 0.0]
+    };
+  }
+}
+
+// Averages per-triangle tangents/bitangents into each vertex from its UVs
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+  let mut triangles_included = vec![0; vertices.len()];
+  for c in indices.chunks(3) {
+    let v0 = &vertices[c[0] as usize];
+    let v1 = &vertices[c[1] as usize];
+    let v2 = &vertices[c[2] as usize];
+
+    let pos0: cgmath::Vector3<_> = v0.position.into();
+    let pos1: cgmath::Vector3<_> = v1.position.into();
+    let pos2: cgmath::Vector3<_> = v2.position.into();
+
+    let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
+    let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
+    let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
+
+    let delta_pos1= pos1 - pos0;
+    let delta_pos2= pos2 - pos0;
+
+    let delta_uv1= uv1 - uv0;
+    let delta_uv2= uv2 - uv0;
+
+    let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
+    let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+    let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+    vertices[c[0] as usize].tangent = 
+      (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
+    vertices[c[0] as usize].bitangent =
+      (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
+
+    triangles_included[c[0] as usize] += 1;
+    vertices[c[1] as usize].tangent = 
+      (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
+    vertices[c[1] as usize].bitangent =
+      (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
+    triangles_included[c[1] as usize] += 1;
+
+    vertices[c[2] as usize].tangent = 
+      (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
+    vertices[c[2] as usize].bitangent =
+      (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
+    triangles_included[c[2] as usize] += 1;
+  }
+
+  for (i, n) in triangles_included.into_iter().enumerate() {
+    // Vertices not referenced by any triangle (e.g. a UV seam duplicate) keep zero tangents
+    if n == 0 {
+      continue;
+    }
+    let denom = 1.0 / n as f32;
+    let v = &mut vertices[i];
+    v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
+    v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+  }
+}
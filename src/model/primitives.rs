@@ -0,0 +1,183 @@
+use std::f32::consts::PI;
+use cgmath::prelude::*;
+
+use crate::model::{self, Material, Mesh, Model, ModelVertex, VertexFormatOption};
+
+// All primitives are centered on the origin and span -1..1 like res/cube.obj,
+// wound counter-clockwise when seen from outside, with v = 0 at the top of the texture.
+// They're uploaded in `vertex_format`, which has to be the renderer's
+// (`State::vertex_format`) for them to be added as objects
+
+fn vertex(position: cgmath::Vector3<f32>, normal: cgmath::Vector3<f32>, tex_coords: [f32; 2]) -> ModelVertex {
+  ModelVertex {
+    position: position.into(),
+    tex_coords,
+    normal: normal.into(),
+    tangent: [0.0; 3],
+    bitangent: [0.0; 3],
+    color: [1.0; 3],
+    tex_coords_1: [0.0; 2],
+    joints: [0; 4],
+    weights: [0.0; 4],
+  }
+}
+
+// Appends a (subdivisions x subdivisions) grid spanning center +/- right +/- up
+fn push_grid(
+  vertices: &mut Vec<ModelVertex>,
+  indices: &mut Vec<u32>,
+  center: cgmath::Vector3<f32>,
+  right: cgmath::Vector3<f32>,
+  up: cgmath::Vector3<f32>,
+  subdivisions: u32,
+) {
+  let n = subdivisions.max(1);
+  let normal = right.cross(up).normalize();
+  let base = vertices.len() as u32;
+
+  for j in 0..=n {
+    for i in 0..=n {
+      let u = i as f32 / n as f32;
+      let v = j as f32 / n as f32;
+      let position = center + right * (u * 2.0 - 1.0) + up * (v * 2.0 - 1.0);
+      vertices.push(vertex(position, normal, [u, 1.0 - v]));
+    }
+  }
+
+  let row = n + 1;
+  for j in 0..n {
+    for i in 0..n {
+      let a = base + j * row + i;
+      let b = a + 1;
+      let c = b + row;
+      let d = a + row;
+      indices.extend_from_slice(&[a, b, c, a, c, d]);
+    }
+  }
+}
+
+fn build(
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  layout: &wgpu::BindGroupLayout,
+  vertex_format: VertexFormatOption,
+  name: &str,
+  mut vertices: Vec<ModelVertex>,
+  indices: Vec<u32>,
+) -> Model {
+  model::compute_tangents(&mut vertices, &indices);
+  Model::new(
+    vec![Mesh::with_format(device, name, &vertices, &indices, 0, vertex_format)],
+    vec![Material::default_material(device, queue, layout)],
+  )
+}
+
+pub fn quad(
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  layout: &wgpu::BindGroupLayout,
+  vertex_format: VertexFormatOption,
+) -> Model {
+  let mut vertices = Vec::new();
+  let mut indices = Vec::new();
+  push_grid(
+    &mut vertices,
+    &mut indices,
+    cgmath::Vector3::zero(),
+    cgmath::Vector3::unit_x(),
+    cgmath::Vector3::unit_y(),
+    1,
+  );
+  build(device, queue, layout, vertex_format, "quad", vertices, indices)
+}
+
+// Lies in the XZ plane facing +Y
+pub fn plane(
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  layout: &wgpu::BindGroupLayout,
+  vertex_format: VertexFormatOption,
+  subdivisions: u32,
+) -> Model {
+  let mut vertices = Vec::new();
+  let mut indices = Vec::new();
+  push_grid(
+    &mut vertices,
+    &mut indices,
+    cgmath::Vector3::zero(),
+    cgmath::Vector3::unit_x(),
+    -cgmath::Vector3::unit_z(),
+    subdivisions,
+  );
+  build(device, queue, layout, vertex_format, "plane", vertices, indices)
+}
+
+pub fn cube(
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  layout: &wgpu::BindGroupLayout,
+  vertex_format: VertexFormatOption,
+) -> Model {
+  let x = cgmath::Vector3::unit_x();
+  let y = cgmath::Vector3::unit_y();
+  let z = cgmath::Vector3::unit_z();
+  // (normal, right, up) per face, with right x up == normal
+  let faces = [
+    (x, -z, y),
+    (-x, z, y),
+    (y, x, -z),
+    (-y, x, z),
+    (z, x, y),
+    (-z, -x, y),
+  ];
+
+  let mut vertices = Vec::new();
+  let mut indices = Vec::new();
+  for (normal, right, up) in faces {
+    push_grid(&mut vertices, &mut indices, normal, right, up, 1);
+  }
+  build(device, queue, layout, vertex_format, "cube", vertices, indices)
+}
+
+pub fn uv_sphere(
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  layout: &wgpu::BindGroupLayout,
+  vertex_format: VertexFormatOption,
+  rings: u32,
+  sectors: u32,
+) -> Model {
+  let rings = rings.max(2);
+  let sectors = sectors.max(3);
+
+  let mut vertices = Vec::new();
+  for r in 0..=rings {
+    let phi = PI * r as f32 / rings as f32;
+    for s in 0..=sectors {
+      // Negated so increasing u runs left to right when viewed from outside
+      let theta = -2.0 * PI * s as f32 / sectors as f32;
+      let normal = cgmath::Vector3::new(phi.sin() * theta.cos(), phi.cos(), phi.sin() * theta.sin());
+      let tex_coords = [s as f32 / sectors as f32, r as f32 / rings as f32];
+      vertices.push(vertex(normal, normal, tex_coords));
+    }
+  }
+
+  let row = sectors + 1;
+  let mut indices = Vec::new();
+  for r in 0..rings {
+    for s in 0..sectors {
+      let d = r * row + s;
+      let c = d + 1;
+      let a = d + row;
+      let b = a + 1;
+      // Skip the triangles that collapse onto a pole
+      if r != rings - 1 {
+        indices.extend_from_slice(&[a, b, c]);
+      }
+      if r != 0 {
+        indices.extend_from_slice(&[a, c, d]);
+      }
+    }
+  }
+  build(device, queue, layout, vertex_format, "uv_sphere", vertices, indices)
+}
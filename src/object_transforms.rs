@@ -0,0 +1,126 @@
+use crate::uniforms::DynamicUniformSlots;
+use crate::{instance, shader_rewrite};
+
+// Objects drawn with this pipeline take their transform from one shared uniform
+// buffer, addressed by a dynamic offset per object, instead of an instance buffer.
+// Only an object's first instance is used, so it suits many single-instance objects
+pub const PIPELINE: &str = "dynamic_transform_pipeline";
+
+// Bind group index of the transforms, after the texture, camera and light groups
+pub const GROUP: u32 = 3;
+
+const INITIAL_CAPACITY: usize = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TransformUniform {
+  model: [[f32; 4]; 4],
+  // mat3x3 columns are padded to vec4 in uniform buffers
+  normal: [[f32; 4]; 3],
+}
+
+impl TransformUniform {
+  fn new(instance: &instance::Instance) -> Self {
+    let model = instance.model_matrix();
+    let normal = instance::normal_matrix(&model);
+    Self {
+      model: model.into(),
+      normal: [normal.x.extend(0.0).into(), normal.y.extend(0.0).into(), normal.z.extend(0.0).into()],
+    }
+  }
+}
+
+// Turns a shader written for the standard `InstanceInput` into one reading the
+// same values from the transform uniform at `GROUP`. Fails unless exactly one
+// vertex entry point takes an InstanceInput
+pub fn shader_source(source: &str) -> anyhow::Result<String> {
+  let helpers = format!(
+    "
+struct ObjectTransform {{
+    model: mat4x4<f32>,
+    normal: mat3x3<f32>,
+}}
+@group({GROUP}) @binding(0)
+var<uniform> object_transform: ObjectTransform;
+
+fn dynamic_instance() -> InstanceInput {{
+    let m = object_transform.model;
+    let n = object_transform.normal;
+    return InstanceInput(m[0], m[1], m[2], m[3], n[0], n[1], n[2]);
+}}
+"
+  );
+  shader_rewrite::reroute_vertex_argument(source, &helpers, "dynamic_instance")
+}
+
+pub struct TransformBinding<'a> {
+  pub bind_group: &'a wgpu::BindGroup,
+  pub offset: u32,
+}
+
+// One slot per object, indexed like `State`'s objects
+pub struct ObjectTransforms {
+  slots: DynamicUniformSlots<TransformUniform>,
+}
+
+impl ObjectTransforms {
+  pub fn new(device: &wgpu::Device) -> Self {
+    Self {
+      slots: DynamicUniformSlots::new(device, wgpu::ShaderStages::VERTEX, INITIAL_CAPACITY, "object_transforms"),
+    }
+  }
+
+  pub fn layout(&self) -> &wgpu::BindGroupLayout {
+    &self.slots.layout
+  }
+
+  // Uploads each object's first instance (identity if it has none)
+  pub fn write<'a>(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    objects: impl ExactSizeIterator<Item = Option<&'a instance::Instance>>,
+  ) {
+    let identity = TransformUniform {
+      model: cgmath::Matrix4::from_scale(1.0).into(),
+      normal: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]],
+    };
+    let transforms = objects.map(|instance| instance.map(TransformUniform::new).unwrap_or(identity));
+    self.slots.write(device, queue, transforms);
+  }
+
+  pub fn binding(&self, slot: usize) -> TransformBinding<'_> {
+    TransformBinding {
+      bind_group: self.slots.bind_group(),
+      offset: self.slots.offset(slot),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn main_shader_reads_the_transform_uniform() {
+    let source = shader_source(include_str!("../shaders/shader.wgsl")).unwrap();
+    let module = naga::front::wgsl::parse_str(&source).unwrap();
+    let vs_main = module.entry_points.iter().find(|entry| entry.name == "vs_main").unwrap();
+    // Only the mesh's vertex input is left
+    assert_eq!(vs_main.function.arguments.len(), 1);
+  }
+
+  #[test]
+  fn formatting_doesnt_matter() {
+    let source = include_str!("../shaders/shader.wgsl").replace(
+      "    model: VertexInput,\n    instance: InstanceInput,\n)",
+      "model: VertexInput, instance: InstanceInput /* one line */)",
+    );
+    assert!(shader_source(&source).is_ok());
+  }
+
+  #[test]
+  fn unrecognized_shaders_are_an_error() {
+    assert!(shader_source("@vertex\nfn vs_main(instance: InstanceInput) -> VertexOutput {}").is_err());
+  }
+}
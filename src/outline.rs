@@ -0,0 +1,102 @@
+use wgpu::util::DeviceExt;
+
+pub const MASK_PIPELINE: &str = "outline_mask_pipeline";
+pub const SHELL_PIPELINE: &str = "outline_pipeline";
+
+// How much larger than the object the outline shell is drawn
+const OUTLINE_SCALE: f32 = 1.04;
+
+// Marks the whole silhouette, including the parts hidden behind other objects
+pub const MASK_STENCIL: wgpu::StencilState = stencil_state(wgpu::StencilFaceState {
+  compare: wgpu::CompareFunction::Always,
+  fail_op: wgpu::StencilOperation::Keep,
+  depth_fail_op: wgpu::StencilOperation::Replace,
+  pass_op: wgpu::StencilOperation::Replace,
+});
+
+// Draws the shell only outside the silhouette marked with the same reference
+pub const SHELL_STENCIL: wgpu::StencilState = stencil_state(wgpu::StencilFaceState {
+  compare: wgpu::CompareFunction::NotEqual,
+  fail_op: wgpu::StencilOperation::Keep,
+  depth_fail_op: wgpu::StencilOperation::Keep,
+  pass_op: wgpu::StencilOperation::Keep,
+});
+
+const fn stencil_state(face: wgpu::StencilFaceState) -> wgpu::StencilState {
+  wgpu::StencilState {
+    front: face,
+    back: face,
+    read_mask: 0xff,
+    write_mask: 0xff,
+  }
+}
+
+pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+  device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+    entries: &[wgpu::BindGroupLayoutEntry {
+      binding: 0,
+      visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    }],
+    label: Some("outline_bind_group_layout"),
+  })
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+  color: [f32; 4],
+  scale: f32,
+  _padding: [f32; 3],
+}
+
+pub struct Outline {
+  pub object: usize,
+  uniform: OutlineUniform,
+  buffer: wgpu::Buffer,
+  pub bind_group: wgpu::BindGroup,
+}
+
+impl Outline {
+  pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, object: usize, color: [f32; 4]) -> Self {
+    let uniform = OutlineUniform {
+      color,
+      scale: OUTLINE_SCALE,
+      _padding: [0.0; 3],
+    };
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Outline Buffer"),
+      contents: bytemuck::cast_slice(&[uniform]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: buffer.as_entire_binding(),
+      }],
+      label: Some("outline_bind_group"),
+    });
+
+    Self {
+      object,
+      uniform,
+      buffer,
+      bind_group,
+    }
+  }
+
+  pub fn color(&self) -> [f32; 4] {
+    self.uniform.color
+  }
+
+  pub fn set_color(&mut self, queue: &wgpu::Queue, color: [f32; 4]) {
+    self.uniform.color = color;
+    queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+  }
+}
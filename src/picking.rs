@@ -0,0 +1,279 @@
+use cgmath::Matrix4;
+use wgpu::util::DeviceExt;
+
+use crate::{instance, model};
+use crate::model::Vertex;
+use crate::renderable_object::RenderableObject;
+use crate::uniforms::DynamicUniformSlots;
+
+const PICK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Uint;
+const PICK_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+// A uniform of one u32, padded to the 16 bytes uniforms are sized in
+type IdUniform = [u32; 4];
+const INITIAL_CAPACITY: usize = 16;
+// Texel copies need a 256-byte row pitch even for a single texel
+const READBACK_SIZE: wgpu::BufferAddress = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress;
+
+// Renders object and instance ids into a single texel under the cursor and reads
+// it back. The camera is narrowed to that pixel, so the targets stay 1x1
+pub struct Picker {
+  pipeline: wgpu::RenderPipeline,
+  camera_buffer: wgpu::Buffer,
+  camera_bind_group: wgpu::BindGroup,
+  ids: DynamicUniformSlots<IdUniform>,
+  color_texture: wgpu::Texture,
+  color_view: wgpu::TextureView,
+  depth_view: wgpu::TextureView,
+  readback_buffer: wgpu::Buffer,
+}
+
+impl Picker {
+  pub fn new(device: &wgpu::Device, vertex_format: model::VertexFormatOption) -> Self {
+    let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      entries: &[wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      }],
+      label: Some("pick_camera_bind_group_layout"),
+    });
+    let identity: [[f32; 4]; 4] = Matrix4::from_scale(1.0).into();
+    let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Pick Camera Buffer"),
+      contents: bytemuck::cast_slice(&[identity]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &camera_layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: camera_buffer.as_entire_binding(),
+      }],
+      label: Some("pick_camera_bind_group"),
+    });
+
+    let ids = DynamicUniformSlots::new(device, wgpu::ShaderStages::FRAGMENT, INITIAL_CAPACITY, "pick_ids");
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("Pick Pipeline Layout"),
+      bind_group_layouts: &[&camera_layout, &ids.layout],
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some("Pick Shader"),
+      source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/pick.wgsl").into()),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Pick Pipeline"),
+      layout: Some(&layout),
+      vertex: wgpu::VertexState {
+        module: &shader,
+        entry_point: Some("vs_main"),
+        buffers: &[vertex_format.layout(), instance::InstanceRaw::desc()],
+        compilation_options: Default::default(),
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: &shader,
+        entry_point: Some("fs_main"),
+        targets: &[Some(wgpu::ColorTargetState {
+          format: PICK_FORMAT,
+          blend: None,
+          write_mask: wgpu::ColorWrites::ALL,
+        })],
+        compilation_options: Default::default(),
+      }),
+      // Unculled, so double-sided and inside-out meshes can still be hit
+      primitive: wgpu::PrimitiveState::default(),
+      depth_stencil: Some(wgpu::DepthStencilState {
+        format: PICK_DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::Less,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+      }),
+      multisample: wgpu::MultisampleState::default(),
+      multiview: None,
+      cache: None,
+    });
+
+    let size = wgpu::Extent3d {
+      width: 1,
+      height: 1,
+      depth_or_array_layers: 1,
+    };
+    let color_texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("pick_texture"),
+      size,
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: PICK_FORMAT,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+      view_formats: &[],
+    });
+    let color_view = color_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_view = device
+      .create_texture(&wgpu::TextureDescriptor {
+        label: Some("pick_depth_texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: PICK_DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+      })
+      .create_view(&wgpu::TextureViewDescriptor::default());
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Pick Readback Buffer"),
+      size: READBACK_SIZE,
+      usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+      mapped_at_creation: false,
+    });
+
+    Self {
+      pipeline,
+      camera_buffer,
+      camera_bind_group,
+      ids,
+      color_texture,
+      color_view,
+      depth_view,
+      readback_buffer,
+    }
+  }
+
+  // Scales and shifts clip space so the pixel at `ndc` (its center, in the view's
+  // NDC) fills the whole 1x1 target. Reverse-Z depth is flipped back, so the
+  // pipeline can always keep the smaller depth
+  fn pick_matrix(ndc: (f32, f32), view_width: u32, view_height: u32, reversed_depth: bool) -> Matrix4<f32> {
+    let (sx, sy) = (view_width as f32, view_height as f32);
+    let (z, zw) = if reversed_depth { (-1.0, 1.0) } else { (1.0, 0.0) };
+    Matrix4::new(
+      sx, 0.0, 0.0, 0.0,
+      0.0, sy, 0.0, 0.0,
+      0.0, 0.0, z, 0.0,
+      -ndc.0 * sx, -ndc.1 * sy, zw, 1.0,
+    )
+  }
+
+  // Draws `objects` (by their index in the scene) with `view_proj` narrowed to the
+  // pixel at `ndc`, then blocks until the texel is read back. Returns the nearest
+  // hit's object index and instance index
+  #[allow(clippy::too_many_arguments)]
+  pub fn pick<'a>(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    view_proj: Matrix4<f32>,
+    ndc: (f32, f32),
+    view_size: (u32, u32),
+    reversed_depth: bool,
+    objects: impl Iterator<Item = (usize, &'a RenderableObject)> + Clone,
+  ) -> Option<(usize, u32)> {
+    // Slots follow the scene's indices, so objects left out keep id 0 (nothing)
+    let count = objects.clone().map(|(i, _)| i + 1).max().unwrap_or(0);
+    let mut ids = vec![[0u32; 4]; count];
+    for (i, _) in objects.clone() {
+      ids[i][0] = i as u32 + 1;
+    }
+    self.ids.write(device, queue, ids.into_iter());
+
+    let matrix = Self::pick_matrix(ndc, view_size.0, view_size.1, reversed_depth) * view_proj;
+    let matrix: [[f32; 4]; 4] = matrix.into();
+    queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[matrix]));
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+      label: Some("Pick Encoder"),
+    });
+    {
+      let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Pick Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+          view: &self.color_view,
+          resolve_target: None,
+          ops: wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            store: wgpu::StoreOp::Store,
+          },
+          depth_slice: None,
+        })],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+          view: &self.depth_view,
+          depth_ops: Some(wgpu::Operations {
+            load: wgpu::LoadOp::Clear(1.0),
+            store: wgpu::StoreOp::Discard,
+          }),
+          stencil_ops: None,
+        }),
+        occlusion_query_set: None,
+        timestamp_writes: None,
+      });
+      render_pass.set_pipeline(&self.pipeline);
+      render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+
+      for (i, obj) in objects {
+        render_pass.set_bind_group(1, self.ids.bind_group(), &[self.ids.offset(i)]);
+        render_pass.set_vertex_buffer(1, obj.instance_buffer.slice(..));
+        for mesh in &obj.model.meshes {
+          render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+          render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+          render_pass.draw_indexed(0..mesh.num_elements, 0, 0..obj.instances.len() as u32);
+        }
+      }
+    }
+    encoder.copy_texture_to_buffer(
+      self.color_texture.as_image_copy(),
+      wgpu::TexelCopyBufferInfo {
+        buffer: &self.readback_buffer,
+        layout: wgpu::TexelCopyBufferLayout {
+          offset: 0,
+          bytes_per_row: Some(READBACK_SIZE as u32),
+          rows_per_image: None,
+        },
+      },
+      wgpu::Extent3d {
+        width: 1,
+        height: 1,
+        depth_or_array_layers: 1,
+      },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = self.readback_buffer.slice(..8);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+      let _ = sender.send(result);
+    });
+    // Unmapped on every way out, a map still pending included, so the next pick
+    // can map the buffer again
+    if let Err(e) = device.poll(wgpu::PollType::wait_indefinitely()) {
+      log::warn!("Pick readback failed: {}", e);
+      self.readback_buffer.unmap();
+      return None;
+    }
+    match receiver.try_recv() {
+      Ok(Ok(())) => {}
+      // A failed map leaves the buffer unmapped already
+      Ok(Err(e)) => {
+        log::warn!("Pick readback buffer couldn't be mapped: {}", e);
+        return None;
+      }
+      Err(_) => {
+        log::warn!("Pick readback buffer wasn't mapped in time");
+        self.readback_buffer.unmap();
+        return None;
+      }
+    }
+    let texel: [u32; 2] = bytemuck::pod_read_unaligned(&slice.get_mapped_range());
+    self.readback_buffer.unmap();
+
+    let [object, instance] = texel;
+    (object != 0).then(|| (object as usize - 1, instance))
+  }
+}
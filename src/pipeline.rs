@@ -1,10 +1,108 @@
+use crate::texture::Texture;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthConfig {
+  pub clear_value: f32,
+  pub compare: wgpu::CompareFunction,
+  // Texture::DEPTH_STENCIL_FORMAT adds a stencil buffer, cleared to 0 every frame
+  pub format: wgpu::TextureFormat,
+}
+
+impl DepthConfig {
+  // Clears to 0.0 and keeps the nearer (larger) depth; pairs with a reversed projection
+  pub const REVERSE_Z: Self = Self {
+    clear_value: 0.0,
+    compare: wgpu::CompareFunction::Greater,
+    format: Texture::DEPTH_FORMAT,
+  };
+
+  pub fn is_reversed(&self) -> bool {
+    matches!(self.compare, wgpu::CompareFunction::Greater | wgpu::CompareFunction::GreaterEqual)
+  }
+
+  pub fn has_stencil(&self) -> bool {
+    self.format.has_stencil_aspect()
+  }
+}
+
+impl Default for DepthConfig {
+  fn default() -> Self {
+    Self {
+      clear_value: 1.0,
+      compare: wgpu::CompareFunction::Less,
+      format: Texture::DEPTH_FORMAT,
+    }
+  }
+}
+
+// Per-pipeline settings; anything left at default matches the original fixed pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineOptions {
+  pub push_constant_size: u32,
+  // PolygonMode::Line needs Features::POLYGON_MODE_LINE
+  pub polygon_mode: wgpu::PolygonMode,
+  // Given for standard depth (negative pulls toward the camera); flipped automatically for reverse-Z
+  pub depth_bias: wgpu::DepthBiasState,
+  // Ignored (left disabled) unless the depth format has a stencil aspect
+  pub stencil: wgpu::StencilState,
+  pub color_writes: wgpu::ColorWrites,
+  pub blend: wgpu::BlendState,
+  pub topology: wgpu::PrimitiveTopology,
+  // Off keeps the depth test but leaves the depth buffer untouched, for blended
+  // geometry that shouldn't hide what's drawn after it
+  pub depth_write_enabled: bool,
+  // Winding of front (kept) faces; `None` uses RendererConfig::default_front_face
+  pub front_face: Option<wgpu::FrontFace>,
+  // Sampler binding type of the material layout (group 0). Filtering accepts
+  // nearest samplers too; NonFiltering is for shaders that must never filter, and
+  // also takes unfilterable float textures (see Texture::from_data). Its
+  // materials are created with State::texture_bind_group_layout_for
+  pub sampler_binding: wgpu::SamplerBindingType,
+  // Layouts for slots 2 onward, after the mesh and instance buffers; objects fill
+  // them with RenderableObject::with_vertex_buffer. Mesh and instance attributes
+  // use shader locations 0-13, so these should start at 14
+  pub extra_vertex_layouts: Vec<wgpu::VertexBufferLayout<'static>>,
+  // Bind group layouts for groups 3 onward, after material, camera and light;
+  // objects supply the groups with RenderableObject::with_extra_bind_group or
+  // State::set_object_extra_bind_group, and aren't drawn while missing one
+  pub extra_bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+  // Lets several pipelines share one shader source with different entry points
+  pub vertex_entry: String,
+  pub fragment_entry: String,
+}
+
+impl Default for PipelineOptions {
+  fn default() -> Self {
+    Self {
+      push_constant_size: 0,
+      polygon_mode: wgpu::PolygonMode::Fill,
+      depth_bias: wgpu::DepthBiasState::default(),
+      stencil: wgpu::StencilState::default(),
+      color_writes: wgpu::ColorWrites::ALL,
+      blend: wgpu::BlendState::REPLACE,
+      topology: wgpu::PrimitiveTopology::TriangleList,
+      depth_write_enabled: true,
+      front_face: None,
+      sampler_binding: wgpu::SamplerBindingType::Filtering,
+      extra_vertex_layouts: Vec::new(),
+      extra_bind_group_layouts: Vec::new(),
+      vertex_entry: "vs_main".to_string(),
+      fragment_entry: "fs_main".to_string(),
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn create_render_pipeline(
   device: &wgpu::Device,
   layout: &wgpu::PipelineLayout,
   color_format: wgpu::TextureFormat,
   depth_format: Option<wgpu::TextureFormat>,
+  depth_compare: wgpu::CompareFunction,
+  sample_count: u32,
   vertex_layouts: &[wgpu::VertexBufferLayout],
   shader: wgpu::ShaderModuleDescriptor,
+  options: &PipelineOptions,
 ) -> wgpu::RenderPipeline {
   let shader = device.create_shader_module(shader);
 
@@ -13,41 +111,38 @@ pub fn create_render_pipeline(
     layout: Some(layout),
     vertex: wgpu::VertexState {
       module: &shader,
-      entry_point: Some("vs_main"),
+      entry_point: Some(&options.vertex_entry),
       buffers: vertex_layouts,
       compilation_options: Default::default(),
     },
     fragment: Some(wgpu::FragmentState {
       module: &shader,
-      entry_point: Some("fs_main"),
+      entry_point: Some(&options.fragment_entry),
       targets: &[Some(wgpu::ColorTargetState {
         format: color_format,
-        blend: Some(wgpu::BlendState {
-          color: wgpu::BlendComponent::REPLACE,
-          alpha: wgpu::BlendComponent::REPLACE,
-        }),
-        write_mask: wgpu::ColorWrites::ALL,
+        blend: Some(options.blend),
+        write_mask: options.color_writes,
       })],
       compilation_options: Default::default(),
     }),
     primitive: wgpu::PrimitiveState {
-      topology: wgpu::PrimitiveTopology::TriangleList,
+      topology: options.topology,
       strip_index_format: None,
-      front_face: wgpu::FrontFace::Ccw,
+      front_face: options.front_face.unwrap_or(wgpu::FrontFace::Ccw),
       cull_mode: Some(wgpu::Face::Back),
-      polygon_mode: wgpu::PolygonMode::Fill,
+      polygon_mode: options.polygon_mode,
       unclipped_depth: false,
       conservative: false,
     },
     depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
       format,
-      depth_write_enabled: true,
-      depth_compare: wgpu::CompareFunction::Less,
-      stencil: wgpu::StencilState::default(),
-      bias: wgpu::DepthBiasState::default(),
+      depth_write_enabled: options.depth_write_enabled,
+      depth_compare,
+      stencil: options.stencil.clone(),
+      bias: options.depth_bias,
     }),
     multisample: wgpu::MultisampleState {
-      count: 1,
+      count: sample_count,
       mask: !0,
       alpha_to_coverage_enabled: false,
     },
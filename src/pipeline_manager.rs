@@ -1,59 +1,231 @@
 use std::collections::HashMap;
-use crate::{texture, pipeline};
+use crate::pipeline;
+use crate::pipeline::{DepthConfig, PipelineOptions};
+use crate::push_constants::{self, PushConstantBinding, PushConstantFallback, PushConstantLayout};
+
+// Everything needed to (re)create a pipeline, kept so global settings like the
+// depth config can be changed after pipelines are registered
+struct PipelineSpec {
+  name: String,
+  // With push constants already rewritten for the fallback, where it's used
+  shader_source: String,
+  bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+  vertex_layouts: Vec<wgpu::VertexBufferLayout<'static>>,
+  surface_format: wgpu::TextureFormat,
+  options: PipelineOptions,
+  push_constant_layout: Option<PushConstantLayout>,
+}
 
 pub struct PipelineManager {
   pipelines: Vec<wgpu::RenderPipeline>,
+  specs: Vec<PipelineSpec>,
   pipeline_map: HashMap<String, usize>,
+  push_constant_fallback: Option<PushConstantFallback>,
+  depth_config: DepthConfig,
+  // Off, pipelines are built without a depth-stencil state
+  use_depth: bool,
+  // MSAA samples of the scene targets every pipeline renders into
+  sample_count: u32,
+  // Used by pipelines whose options leave `front_face` unset
+  default_front_face: wgpu::FrontFace,
 }
 
 impl PipelineManager {
   pub fn new() -> Self {
     Self {
       pipelines: Vec::new(),
+      specs: Vec::new(),
       pipeline_map: HashMap::new(),
+      push_constant_fallback: None,
+      depth_config: DepthConfig::default(),
+      use_depth: true,
+      sample_count: 1,
+      default_front_face: wgpu::FrontFace::Ccw,
     }
   }
 
+  // Only affects pipelines added afterwards (and all of them on the next rebuild)
+  pub fn set_default_front_face(&mut self, front_face: wgpu::FrontFace) {
+    self.default_front_face = front_face;
+  }
+
+  pub fn use_depth(&self) -> bool {
+    self.use_depth
+  }
+
+  // Only affects pipelines added afterwards (and all of them on the next rebuild)
+  pub fn set_use_depth(&mut self, use_depth: bool) {
+    self.use_depth = use_depth;
+  }
+
+  // Fails if the push constant size is out of range, or if the shader's
+  // `var<push_constant>` can't be turned into the uniform fallback this device
+  // needs. Adding a name that's already registered returns the existing index
+  #[allow(clippy::too_many_arguments)]
   pub fn add_pipeline(
     &mut self,
     device: &wgpu::Device,
     name: String,
     shader_source: &str,
     bind_group_layouts: &[&wgpu::BindGroupLayout],
-    vertex_layouts: &[wgpu::VertexBufferLayout],
+    vertex_layouts: &[wgpu::VertexBufferLayout<'static>],
     surface_format: wgpu::TextureFormat,
-  ) -> usize {
+    options: PipelineOptions,
+  ) -> anyhow::Result<usize> {
     if let Some(&index) = self.pipeline_map.get(&name) {
-      return index;
+      return Ok(index);
+    }
+
+    let push_constant_size = options.push_constant_size;
+    if push_constant_size > push_constants::MAX_PUSH_CONSTANT_SIZE || !push_constant_size.is_multiple_of(4) {
+      anyhow::bail!(
+        "pipeline {name:?}: push constant size must be a multiple of 4 and at most {} bytes, got {push_constant_size}",
+        push_constants::MAX_PUSH_CONSTANT_SIZE,
+      );
+    }
+    let push_constant_layout = if push_constant_size == 0 {
+      None
+    } else if push_constants::is_supported(device) {
+      Some(PushConstantLayout::Native { size: push_constant_size })
+    } else {
+      Some(PushConstantLayout::Fallback { group: bind_group_layouts.len() as u32 })
+    };
+    // Rewritten once here, so rebuilding for new global settings can't fail
+    let shader_source = match push_constant_layout {
+      Some(PushConstantLayout::Fallback { group }) => push_constants::fallback_shader_source(shader_source, group)
+        .map_err(|e| e.context(format!("pipeline {name:?}")))?,
+      _ => shader_source.to_string(),
+    };
+
+    let spec = PipelineSpec {
+      name: name.clone(),
+      shader_source,
+      bind_group_layouts: bind_group_layouts.iter().map(|&layout| layout.clone()).collect(),
+      vertex_layouts: vertex_layouts.to_vec(),
+      surface_format,
+      options,
+      push_constant_layout,
+    };
+
+    let render_pipeline =
+      Self::create(device, &spec, self.use_depth.then_some(&self.depth_config), self.sample_count, self.default_front_face, &mut self.push_constant_fallback);
+
+    let index = self.pipelines.len();
+    self.pipelines.push(render_pipeline);
+    self.specs.push(spec);
+    self.pipeline_map.insert(name, index);
+    Ok(index)
+  }
+
+  // For the renderer's own pipelines, which have no push constants and so can't
+  // fail to add
+  #[allow(clippy::too_many_arguments)]
+  pub(crate) fn add_builtin_pipeline(
+    &mut self,
+    device: &wgpu::Device,
+    name: String,
+    shader_source: &str,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    vertex_layouts: &[wgpu::VertexBufferLayout<'static>],
+    surface_format: wgpu::TextureFormat,
+    options: PipelineOptions,
+  ) -> usize {
+    debug_assert_eq!(options.push_constant_size, 0);
+    self
+      .add_pipeline(device, name, shader_source, bind_group_layouts, vertex_layouts, surface_format, options)
+      .expect("built-in pipelines have no push constants")
+  }
+
+  fn create(
+    device: &wgpu::Device,
+    spec: &PipelineSpec,
+    // `None` without a depth buffer
+    depth_config: Option<&DepthConfig>,
+    sample_count: u32,
+    default_front_face: wgpu::FrontFace,
+    push_constant_fallback: &mut Option<PushConstantFallback>,
+  ) -> wgpu::RenderPipeline {
+    let mut layouts = spec.bind_group_layouts.iter().collect::<Vec<_>>();
+    let mut push_constant_ranges = Vec::new();
+    match spec.push_constant_layout {
+      Some(PushConstantLayout::Native { size }) => {
+        push_constant_ranges.push(wgpu::PushConstantRange {
+          stages: push_constants::PUSH_CONSTANT_STAGES,
+          range: 0..size,
+        });
+      }
+      Some(PushConstantLayout::Fallback { .. }) => {
+        let fallback = push_constant_fallback.get_or_insert_with(|| PushConstantFallback::new(device));
+        layouts.push(fallback.layout());
+      }
+      None => {}
     }
 
     let render_pipeline_layout = 
       device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some(&format!("{} Pipeline Layout", name)),
-        bind_group_layouts: bind_group_layouts,
-        push_constant_ranges: &[],
+        label: Some(&format!("{} Pipeline Layout", spec.name)),
+        bind_group_layouts: &layouts,
+        push_constant_ranges: &push_constant_ranges,
       });
 
     let shader = wgpu::ShaderModuleDescriptor {
-      label: Some(&format!("{} Shader", name)),
-      source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+      label: Some(&format!("{} Shader", spec.name)),
+      source: wgpu::ShaderSource::Wgsl(spec.shader_source.as_str().into()),
     };
 
-    let render_pipeline = pipeline::create_render_pipeline(
+    let mut options = spec.options.clone();
+    options.front_face.get_or_insert(default_front_face);
+    if !depth_config.is_some_and(DepthConfig::has_stencil) {
+      options.stencil = wgpu::StencilState::default();
+    }
+    if depth_config.is_some_and(DepthConfig::is_reversed) {
+      options.depth_bias.constant = -options.depth_bias.constant;
+      options.depth_bias.slope_scale = -options.depth_bias.slope_scale;
+    }
+
+    pipeline::create_render_pipeline(
       device,
       &render_pipeline_layout,
-      surface_format,
-      Some(texture::Texture::DEPTH_FORMAT),
-      vertex_layouts,
+      spec.surface_format,
+      depth_config.map(|config| config.format),
+      depth_config.map_or(wgpu::CompareFunction::Always, |config| config.compare),
+      sample_count,
+      &spec.vertex_layouts,
       shader,
-    );
+      &options,
+    )
+  }
 
-    let index = self.pipelines.len();
-    self.pipelines.push(render_pipeline);
-    self.pipeline_map.insert(name, index);
-    index
+  // Recreates every registered pipeline from its retained spec
+  pub fn rebuild(&mut self, device: &wgpu::Device) {
+    for (i, spec) in self.specs.iter().enumerate() {
+      self.pipelines[i] =
+        Self::create(device, spec, self.use_depth.then_some(&self.depth_config), self.sample_count, self.default_front_face, &mut self.push_constant_fallback);
+    }
   }
 
+  pub fn depth_config(&self) -> DepthConfig {
+    self.depth_config
+  }
+
+  pub fn set_depth_config(&mut self, device: &wgpu::Device, depth_config: DepthConfig) {
+    if self.depth_config != depth_config {
+      self.depth_config = depth_config;
+      self.rebuild(device);
+    }
+  }
+
+  pub fn sample_count(&self) -> u32 {
+    self.sample_count
+  }
+
+  // The count has to be one the adapter supports for both the color and depth formats
+  pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+    if self.sample_count != sample_count {
+      self.sample_count = sample_count;
+      self.rebuild(device);
+    }
+  }
 
   fn get(&self, index: usize) -> Option<&wgpu::RenderPipeline> {
     self.pipelines.get(index)
@@ -62,10 +234,69 @@ impl PipelineManager {
   pub fn get_by_name(&self, name: &str) -> Option<&wgpu::RenderPipeline> {
     self.pipeline_map.get(name).and_then(|&i| self.get(i))
   }
+
+  // Whether the pipeline reads a per-instance vertex buffer
+  pub fn uses_instances(&self, name: &str) -> bool {
+    self.instance_slot(name).is_some()
+  }
+
+  // Vertex buffer slot of the pipeline's per-instance layout, if it has one
+  pub fn instance_slot(&self, name: &str) -> Option<u32> {
+    let &i = self.pipeline_map.get(name)?;
+    self.specs[i]
+      .vertex_layouts
+      .iter()
+      .position(|layout| layout.step_mode == wgpu::VertexStepMode::Instance)
+      .map(|slot| slot as u32)
+  }
+
+  // Step mode of the pipeline's vertex layout at `slot`, if it declares one
+  pub fn vertex_step_mode(&self, name: &str, slot: u32) -> Option<wgpu::VertexStepMode> {
+    let &i = self.pipeline_map.get(name)?;
+    self.specs[i].vertex_layouts.get(slot as usize).map(|layout| layout.step_mode)
+  }
+
+  // Vertex buffer slots the pipeline's objects fill themselves, from its
+  // PipelineOptions::extra_vertex_layouts; empty for unknown pipelines
+  pub fn extra_vertex_slots(&self, name: &str) -> std::ops::Range<u32> {
+    let Some(&i) = self.pipeline_map.get(name) else { return 0..0 };
+    let spec = &self.specs[i];
+    let end = spec.vertex_layouts.len() as u32;
+    end - spec.options.extra_vertex_layouts.len() as u32..end
+  }
+
+  // Groups the pipeline's objects bind themselves, from its
+  // PipelineOptions::extra_bind_group_layouts; empty for unknown pipelines
+  pub fn extra_bind_groups(&self, name: &str) -> std::ops::Range<u32> {
+    let Some(&i) = self.pipeline_map.get(name) else { return 0..0 };
+    let spec = &self.specs[i];
+    let end = spec.bind_group_layouts.len() as u32;
+    end - spec.options.extra_bind_group_layouts.len() as u32..end
+  }
+
+  pub(crate) fn push_constant_fallback_mut(&mut self) -> Option<&mut PushConstantFallback> {
+    self.push_constant_fallback.as_mut()
+  }
+
+  // How the object in `slot` should provide push constants when drawn with `name`
+  pub fn push_constant_binding(&self, name: &str, slot: usize) -> Option<PushConstantBinding<'_>> {
+    let index = *self.pipeline_map.get(name)?;
+    match self.specs[index].push_constant_layout? {
+      PushConstantLayout::Native { size } => Some(PushConstantBinding::Native { size }),
+      PushConstantLayout::Fallback { group } => {
+        let fallback = self.push_constant_fallback.as_ref()?;
+        Some(PushConstantBinding::Fallback {
+          group,
+          bind_group: fallback.bind_group(),
+          offset: fallback.offset(slot),
+        })
+      }
+    }
+  }
 }
 
 impl Default for PipelineManager {
   fn default() -> Self {
     Self::new()
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,119 @@
+use crate::uniforms::DynamicUniformSlots;
+
+pub const MAX_PUSH_CONSTANT_SIZE: u32 = 128;
+pub const PUSH_CONSTANT_STAGES: wgpu::ShaderStages = wgpu::ShaderStages::VERTEX_FRAGMENT;
+
+const INITIAL_FALLBACK_CAPACITY: usize = 16;
+
+pub fn is_supported(device: &wgpu::Device) -> bool {
+  device.features().contains(wgpu::Features::PUSH_CONSTANTS)
+}
+
+// Shaders declare their parameters as one `var<push_constant>`; on backends
+// without push constants that variable becomes a uniform at `group`, binding 0.
+// The shader is parsed and written back out rather than searched as text, so
+// formatting and comments don't matter and a missing declaration is an error
+pub fn fallback_shader_source(source: &str, group: u32) -> anyhow::Result<String> {
+  let mut module = naga::front::wgsl::parse_str(source).map_err(|e| anyhow::anyhow!(e.emit_to_string(source)))?;
+  let push_constants = module
+    .global_variables
+    .iter()
+    .filter(|(_, var)| var.space == naga::AddressSpace::PushConstant)
+    .map(|(handle, _)| handle)
+    .collect::<Vec<_>>();
+  let [handle] = push_constants[..] else {
+    anyhow::bail!("expected one var<push_constant> in the shader, found {}", push_constants.len());
+  };
+  let var = &mut module.global_variables[handle];
+  var.space = naga::AddressSpace::Uniform;
+  var.binding = Some(naga::ResourceBinding { group, binding: 0 });
+
+  // Also catches push constant types that break uniform layout rules
+  let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+    .validate(&module)
+    .map_err(|e| anyhow::anyhow!(e.emit_to_string(source)))?;
+  Ok(naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushConstantLayout {
+  Native { size: u32 },
+  Fallback { group: u32 },
+}
+
+pub enum PushConstantBinding<'a> {
+  Native { size: u32 },
+  Fallback { group: u32, bind_group: &'a wgpu::BindGroup, offset: u32 },
+}
+
+// One-slot-per-object uniform buffer bound with a dynamic offset, used in
+// place of push constants on backends like WebGL
+pub struct PushConstantFallback {
+  slots: DynamicUniformSlots<[u8; MAX_PUSH_CONSTANT_SIZE as usize]>,
+}
+
+impl PushConstantFallback {
+  pub fn new(device: &wgpu::Device) -> Self {
+    Self {
+      slots: DynamicUniformSlots::new(device, PUSH_CONSTANT_STAGES, INITIAL_FALLBACK_CAPACITY, "push_constant_fallback"),
+    }
+  }
+
+  pub fn layout(&self) -> &wgpu::BindGroupLayout {
+    &self.slots.layout
+  }
+
+  // Uploads one slot per entry, each zero-padded to the full push constant range
+  pub fn write<'a>(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    slots: impl ExactSizeIterator<Item = &'a [u8]>,
+  ) {
+    let slots = slots.map(|slot| {
+      let mut data = [0u8; MAX_PUSH_CONSTANT_SIZE as usize];
+      let len = slot.len().min(data.len());
+      data[..len].copy_from_slice(&slot[..len]);
+      data
+    });
+    self.slots.write(device, queue, slots);
+  }
+
+  pub fn bind_group(&self) -> &wgpu::BindGroup {
+    self.slots.bind_group()
+  }
+
+  pub fn offset(&self, slot: usize) -> u32 {
+    self.slots.offset(slot)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SHADER: &str = "
+struct Params { tint: vec4<f32> }
+var <push_constant>   params: Params; // odd spacing on purpose
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return params.tint;
+}
+";
+
+  #[test]
+  fn fallback_turns_the_push_constant_into_a_uniform() {
+    let source = fallback_shader_source(SHADER, 3).unwrap();
+    let module = naga::front::wgsl::parse_str(&source).unwrap();
+    let (_, var) = module.global_variables.iter().next().unwrap();
+    assert_eq!(var.space, naga::AddressSpace::Uniform);
+    assert_eq!(var.binding, Some(naga::ResourceBinding { group: 3, binding: 0 }));
+  }
+
+  #[test]
+  fn fallback_needs_exactly_one_push_constant() {
+    let without = "@fragment\nfn fs_main() -> @location(0) vec4<f32> { return vec4<f32>(1.0); }";
+    assert!(fallback_shader_source(without, 3).is_err());
+  }
+}
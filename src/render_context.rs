@@ -0,0 +1,9 @@
+use crate::pipeline_manager::PipelineManager;
+
+// What a `State::render_with` callback gets to draw with. Bind groups follow the
+// built-in layouts: the camera is group 1 for material pipelines and group 0 otherwise
+pub struct RenderContext<'a> {
+  pub camera_bind_group: &'a wgpu::BindGroup,
+  pub light_bind_group: &'a wgpu::BindGroup,
+  pub pipeline_manager: &'a PipelineManager,
+}
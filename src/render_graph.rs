@@ -0,0 +1,125 @@
+use crate::pipeline_manager::PipelineManager;
+
+// Passes the renderer always runs; custom passes are placed relative to these names
+pub const COMPUTE_PASS: &str = "compute";
+pub const SHADOW_PASS: &str = "shadow";
+pub const BACKGROUND_PASS: &str = "background";
+pub const SCENE_PASS: &str = "scene";
+pub const TONEMAP_PASS: &str = "tonemap";
+
+// What a custom pass can read or render into. Everything but the surface view
+// persists across frames; the scene is rendered into `hdr_view` and only reaches
+// `surface_view` in the tone map pass. With MSAA the scene is drawn into
+// `msaa_view` instead, and resolved into `hdr_view` at the end of each scene pass
+// unless `State::set_msaa_resolve(false)` leaves that to a custom pass
+pub struct FrameResources<'a> {
+  pub device: &'a wgpu::Device,
+  pub queue: &'a wgpu::Queue,
+  pub surface_view: &'a wgpu::TextureView,
+  pub surface_format: wgpu::TextureFormat,
+  pub hdr_view: &'a wgpu::TextureView,
+  pub msaa_view: Option<&'a wgpu::TextureView>,
+  pub sample_count: u32,
+  // `None` when RendererConfig::use_depth is off
+  pub depth_view: Option<&'a wgpu::TextureView>,
+  pub shadow_map_view: &'a wgpu::TextureView,
+  pub camera_bind_group: &'a wgpu::BindGroup,
+  pub light_bind_group: &'a wgpu::BindGroup,
+  pub pipeline_manager: &'a PipelineManager,
+  pub width: u32,
+  pub height: u32,
+}
+
+pub type PassFn = Box<dyn FnMut(&mut wgpu::CommandEncoder, &FrameResources<'_>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BuiltinPass {
+  Compute,
+  Shadow,
+  Background,
+  Scene,
+  ToneMap,
+}
+
+pub(crate) enum PassKind {
+  Builtin(BuiltinPass),
+  Custom(PassFn),
+}
+
+pub(crate) struct Pass {
+  pub name: String,
+  pub kind: PassKind,
+}
+
+// Named passes run in order by `State::render`, all recorded into one encoder
+pub struct RenderGraph {
+  passes: Vec<Pass>,
+}
+
+impl Default for RenderGraph {
+  fn default() -> Self {
+    let builtin = |name: &str, pass| Pass {
+      name: name.to_string(),
+      kind: PassKind::Builtin(pass),
+    };
+    Self {
+      passes: vec![
+        builtin(COMPUTE_PASS, BuiltinPass::Compute),
+        builtin(SHADOW_PASS, BuiltinPass::Shadow),
+        builtin(BACKGROUND_PASS, BuiltinPass::Background),
+        builtin(SCENE_PASS, BuiltinPass::Scene),
+        builtin(TONEMAP_PASS, BuiltinPass::ToneMap),
+      ],
+    }
+  }
+}
+
+impl RenderGraph {
+  fn position(&self, name: &str) -> Option<usize> {
+    self.passes.iter().position(|pass| pass.name == name)
+  }
+
+  // Returns false, adding nothing, if `anchor` isn't a pass or `name` is already taken
+  pub fn add_pass_before(&mut self, anchor: &str, name: impl Into<String>, pass: PassFn) -> bool {
+    self.insert(anchor, 0, name.into(), pass)
+  }
+
+  pub fn add_pass_after(&mut self, anchor: &str, name: impl Into<String>, pass: PassFn) -> bool {
+    self.insert(anchor, 1, name.into(), pass)
+  }
+
+  fn insert(&mut self, anchor: &str, offset: usize, name: String, pass: PassFn) -> bool {
+    if self.position(&name).is_some() {
+      log::warn!("A render pass named {:?} already exists", name);
+      return false;
+    }
+    let Some(index) = self.position(anchor) else {
+      log::warn!("No render pass named {:?}", anchor);
+      return false;
+    };
+    self.passes.insert(index + offset, Pass {
+      name,
+      kind: PassKind::Custom(pass),
+    });
+    true
+  }
+
+  // Built-in passes can't be removed
+  pub fn remove_pass(&mut self, name: &str) -> bool {
+    match self.position(name) {
+      Some(index) if matches!(self.passes[index].kind, PassKind::Custom(_)) => {
+        self.passes.remove(index);
+        true
+      }
+      _ => false,
+    }
+  }
+
+  pub fn pass_names(&self) -> impl Iterator<Item = &str> {
+    self.passes.iter().map(|pass| pass.name.as_str())
+  }
+
+  pub(crate) fn passes_mut(&mut self) -> &mut [Pass] {
+    &mut self.passes
+  }
+}
@@ -0,0 +1,21 @@
+use crate::model::Model;
+
+// Work issued by the built-in passes during the last `State::render`; draws made
+// from a `render_with` callback aren't counted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+  pub draw_calls: u32,
+  pub instances: u32,
+  pub triangles: u64,
+}
+
+impl RenderStats {
+  // One indexed draw per mesh, each covering every instance
+  pub fn record_model(&mut self, model: &Model, instances: u32) {
+    for mesh in &model.meshes {
+      self.draw_calls += 1;
+      self.instances += instances;
+      self.triangles += mesh.triangle_count() as u64 * instances as u64;
+    }
+  }
+}
@@ -1,6 +1,28 @@
 use std::sync::Arc;
-use crate::{instance, model};
+use wgpu::util::RenderEncoder;
+use crate::{bounds, gpu_culling, instance, model, skinning};
+use crate::object_transforms::{self, TransformBinding};
 use crate::draw_traits::{DrawWithMaterial, DrawWithoutMaterial, DrawMethod};
+use crate::push_constants::{self, PushConstantBinding};
+
+// Layer every object starts on
+pub const DEFAULT_LAYER: u32 = 1;
+// Objects on this layer are drawn after the rest of their view against a depth
+// buffer of their own, cleared in between, so they show over the world (e.g.
+// in-world 3D widgets) while still depth testing among themselves
+pub const OVERLAY_LAYER: u32 = 1 << 31;
+
+// A vertex buffer bound at `slot` next to the mesh (slot 0) and instance (slot 1)
+// buffers, e.g. per-instance animation state. The object's pipeline has to declare
+// a layout at that slot with the same step mode, through
+// PipelineOptions::extra_vertex_layouts; `State::add_renderable` refuses buffers
+// it doesn't declare, and objects missing one it does aren't drawn
+#[derive(Debug, Clone)]
+pub struct VertexBufferBinding {
+  pub slot: u32,
+  pub buffer: wgpu::Buffer,
+  pub step_mode: wgpu::VertexStepMode,
+}
 
 pub struct RenderableObject {
   pub model: Arc<model::Model>,
@@ -8,6 +30,31 @@ pub struct RenderableObject {
   pub instance_buffer: wgpu::Buffer,
   pub pipeline_name: Option<String>,
   pub draw_method: DrawMethod,
+  pub push_constants: Option<Vec<u8>>,
+  // Recorded once into a render bundle by `State::bake_static_objects` instead of every frame
+  pub is_static: bool,
+  // Bitmask of the layers the object belongs to; it's drawn when any of them is
+  // in `State::set_visible_layers`
+  pub layer: u32,
+  // Lower values draw first; objects with equal values keep the pipeline grouping
+  // (transparent last, then insertion order). Change it through
+  // `State::set_object_render_order`, which re-sorts the draw list
+  pub render_order: i32,
+  // Bound after the instance buffer wherever the object's own pipeline draws it
+  pub vertex_buffers: Vec<VertexBufferBinding>,
+  // Bound at their groups (3 onward, after material, camera and light) before the
+  // object draws, for per-object shader data such as a mask texture. The pipeline
+  // declares the layouts through PipelineOptions::extra_bind_group_layouts, and
+  // objects missing any of its groups aren't drawn with it
+  pub extra_bind_groups: Vec<(u32, wgpu::BindGroup)>,
+  // Only present for DrawMethod::Indirect and GpuCulled. Bound as STORAGE too so a
+  // compute pass can rewrite the instance counts (e.g. after GPU culling)
+  pub indirect_buffer: Option<wgpu::Buffer>,
+  // DrawMethod::GpuCulled's compacted instances, drawn instead of `instance_buffer`
+  pub(crate) cull_target: Option<gpu_culling::CullTarget>,
+  // Only present for models with a skin; its joint matrices are bound at
+  // skinning::GROUP by `State::add_renderable`
+  pub(crate) skin_pose: Option<skinning::SkinPose>,
 }
 
 impl RenderableObject {
@@ -25,28 +72,184 @@ impl RenderableObject {
       .map(instance::Instance::to_raw)
       .collect::<Vec<_>>();
     
+    let gpu_culled = draw_method == DrawMethod::GpuCulled;
+    // The cull pass reads the instances as storage
+    let storage = if gpu_culled { wgpu::BufferUsages::STORAGE } else { wgpu::BufferUsages::empty() };
     let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
       label: Some("Instance Buffer"),
       contents: bytemuck::cast_slice(&instance_data),
-      usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+      usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | storage,
     });
 
+    let indirect_buffer = matches!(draw_method, DrawMethod::Indirect | DrawMethod::GpuCulled).then(|| {
+      device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Indirect Args Buffer"),
+        contents: &Self::indirect_args(&model, instances.len() as u32),
+        usage: wgpu::BufferUsages::INDIRECT
+          | wgpu::BufferUsages::STORAGE
+          | wgpu::BufferUsages::COPY_DST,
+      })
+    });
+    let cull_target = gpu_culled.then(|| gpu_culling::CullTarget::new(device, &model, instances.len() as u32));
+    let skin_pose = model.skin.is_some().then(skinning::SkinPose::new);
+
     Self {
       model,
       instances,
       instance_buffer,
       pipeline_name,
       draw_method,
+      push_constants: None,
+      is_static: false,
+      layer: DEFAULT_LAYER,
+      render_order: 0,
+      vertex_buffers: Vec::new(),
+      extra_bind_groups: Vec::new(),
+      indirect_buffer,
+      cull_target,
+      skin_pose,
     }
   }
 
-  pub fn draw<'a>(
+  pub fn is_overlay(&self) -> bool {
+    self.layer & OVERLAY_LAYER != 0
+  }
+
+  fn indirect_args(model: &model::Model, instance_count: u32) -> Vec<u8> {
+    model.meshes
+      .iter()
+      .flat_map(|mesh| {
+        wgpu::util::DrawIndexedIndirectArgs {
+          index_count: mesh.num_elements,
+          instance_count,
+          first_index: 0,
+          base_vertex: 0,
+          first_instance: 0,
+        }
+        .as_bytes()
+        .to_vec()
+      })
+      .collect()
+  }
+
+  // Builder-style setters for configuring an object before `State::add_renderable`
+  pub fn with_push_constants<T: bytemuck::Pod>(mut self, data: &T) -> anyhow::Result<Self> {
+    self.set_push_constants(data)?;
+    Ok(self)
+  }
+
+  pub fn with_static(mut self, is_static: bool) -> Self {
+    self.is_static = is_static;
+    self
+  }
+
+  pub fn with_layer(mut self, layer: u32) -> Self {
+    self.layer = layer;
+    self
+  }
+
+  pub fn with_render_order(mut self, render_order: i32) -> Self {
+    self.render_order = render_order;
+    self
+  }
+
+  // Replaces any bind group already at `group`
+  pub fn with_extra_bind_group(mut self, group: u32, bind_group: wgpu::BindGroup) -> Self {
+    self.set_extra_bind_group(group, bind_group);
+    self
+  }
+
+  pub fn set_extra_bind_group(&mut self, group: u32, bind_group: wgpu::BindGroup) {
+    self.extra_bind_groups.retain(|(existing, _)| *existing != group);
+    self.extra_bind_groups.push((group, bind_group));
+  }
+
+  pub fn has_extra_bind_group(&self, group: u32) -> bool {
+    self.extra_bind_groups.iter().any(|(existing, _)| *existing == group)
+  }
+
+  // Replaces any buffer already at `slot`
+  pub fn with_vertex_buffer(mut self, slot: u32, buffer: wgpu::Buffer, step_mode: wgpu::VertexStepMode) -> Self {
+    self.set_vertex_buffer(VertexBufferBinding { slot, buffer, step_mode });
+    self
+  }
+
+  pub fn set_vertex_buffer(&mut self, binding: VertexBufferBinding) {
+    self.vertex_buffers.retain(|existing| existing.slot != binding.slot);
+    self.vertex_buffers.push(binding);
+  }
+
+  // The instance buffer at `instance_slot` (the culled one for GpuCulled objects),
+  // if the pipeline has one, then every
+  // extra buffer whose slot and step mode `step_mode_at` agrees with
+  pub fn bind_vertex_buffers<'a, E: RenderEncoder<'a>>(
+    &'a self,
+    render_pass: &mut E,
+    instance_slot: Option<u32>,
+    step_mode_at: impl Fn(u32) -> Option<wgpu::VertexStepMode>,
+  ) {
+    if let Some(slot) = instance_slot {
+      let instance_buffer = self.cull_target.as_ref().map_or(&self.instance_buffer, |target| &target.culled_buffer);
+      render_pass.set_vertex_buffer(slot, instance_buffer.slice(..));
+    }
+    for binding in &self.vertex_buffers {
+      if step_mode_at(binding.slot) == Some(binding.step_mode) {
+        render_pass.set_vertex_buffer(binding.slot, binding.buffer.slice(..));
+      }
+    }
+  }
+
+  // Fails unless `data` is a multiple of 4 and at most MAX_PUSH_CONSTANT_SIZE bytes
+  pub fn set_push_constants<T: bytemuck::Pod>(&mut self, data: &T) -> anyhow::Result<()> {
+    let bytes = bytemuck::bytes_of(data);
+    if bytes.len() > push_constants::MAX_PUSH_CONSTANT_SIZE as usize || !bytes.len().is_multiple_of(4) {
+      anyhow::bail!(
+        "push constants must be a multiple of 4 and at most {} bytes, got {}",
+        push_constants::MAX_PUSH_CONSTANT_SIZE,
+        bytes.len(),
+      );
+    }
+    self.push_constants = Some(bytes.to_vec());
+    Ok(())
+  }
+
+  pub fn clear_push_constants(&mut self) {
+    self.push_constants = None;
+  }
+
+  // Works on render passes and render bundle encoders alike
+  pub fn draw<'a, E: RenderEncoder<'a>>(
     &'a self,
-    render_pass: &mut wgpu::RenderPass<'a>,
+    render_pass: &mut E,
     camera_bind_group: &'a wgpu::BindGroup,
     light_bind_group: &'a wgpu::BindGroup,
+    push_constants: Option<PushConstantBinding<'a>>,
+    transform: Option<TransformBinding<'a>>,
   ) {
-    let instances = 0..self.instances.len() as u32;
+    match push_constants {
+      Some(PushConstantBinding::Native { size }) => {
+        // Always push the full range so values don't leak from the previous object
+        let mut data = vec![0u8; size as usize];
+        if let Some(bytes) = &self.push_constants {
+          let len = bytes.len().min(data.len());
+          data[..len].copy_from_slice(&bytes[..len]);
+        }
+        render_pass.set_push_constants(push_constants::PUSH_CONSTANT_STAGES, 0, &data);
+      }
+      Some(PushConstantBinding::Fallback { group, bind_group, offset }) => {
+        render_pass.set_bind_group(group, Some(bind_group), &[offset]);
+      }
+      None => {}
+    }
+
+    // A uniform transform places a single copy, whatever the instance count
+    let instances = match transform {
+      Some(TransformBinding { bind_group, offset }) => {
+        render_pass.set_bind_group(object_transforms::GROUP, Some(bind_group), &[offset]);
+        0..1
+      }
+      None => 0..self.instances.len() as u32,
+    };
     match self.draw_method {
       DrawMethod::WithMaterial => {
         DrawWithMaterial::draw_model_instanced(
@@ -55,9 +258,13 @@ impl RenderableObject {
           instances,
           camera_bind_group,
           light_bind_group,
+          &self.extra_bind_groups,
         );
       }
       DrawMethod::WithoutMaterial => {
+        for (group, bind_group) in &self.extra_bind_groups {
+          render_pass.set_bind_group(*group, Some(bind_group), &[]);
+        }
         DrawWithoutMaterial::draw_model_instanced(
           render_pass,
           &self.model,
@@ -66,15 +273,130 @@ impl RenderableObject {
           light_bind_group,
         );
       }
+      DrawMethod::Indirect | DrawMethod::GpuCulled => {
+        let indirect_buffer = self.indirect_buffer
+          .as_ref()
+          .expect("indirect objects are created with an args buffer");
+        DrawWithMaterial::draw_model_indirect(
+          render_pass,
+          &self.model,
+          indirect_buffer,
+          camera_bind_group,
+          light_bind_group,
+          &self.extra_bind_groups,
+        );
+      }
+    }
+  }
+
+  // Plays the model's animation `name` from its start, looping, every instance
+  // in step. Returns false if the model has no skin or no such animation
+  pub fn play_animation(&mut self, name: &str) -> bool {
+    let (Some(skin), Some(pose)) = (&self.model.skin, &mut self.skin_pose) else {
+      log::warn!("Can't play animation {:?}, the model has no skin", name);
+      return false;
+    };
+    let Some(clip) = skin.animation_index(name) else {
+      log::warn!("The model has no animation {:?}", name);
+      return false;
+    };
+    pose.play(clip);
+    true
+  }
+
+  // Puts the joints back at rest
+  pub fn stop_animation(&mut self) {
+    if let Some(pose) = &mut self.skin_pose {
+      pose.stop();
+    }
+  }
+
+  // Name of the animation playing, if any
+  pub fn animation(&self) -> Option<&str> {
+    let clip = self.skin_pose.as_ref()?.clip()?;
+    Some(&self.model.skin.as_ref()?.animations()[clip].name)
+  }
+
+  pub(crate) fn advance_animation(&mut self, dt: f32) {
+    if let (Some(skin), Some(pose)) = (&self.model.skin, &mut self.skin_pose) {
+      pose.advance(skin, dt);
     }
   }
 
-  #[allow(dead_code)]
-  pub fn update_instances(&mut self, queue: &wgpu::Queue) {
+  pub(crate) fn upload_pose(&mut self, queue: &wgpu::Queue) {
+    if let (Some(skin), Some(pose)) = (&self.model.skin, &mut self.skin_pose) {
+      pose.upload(queue, skin);
+    }
+  }
+
+  // Union of the model's bounds placed at every instance
+  pub fn world_bounds(&self) -> Option<bounds::Aabb> {
+    let local = self.model.bounds()?;
+    self.instances
+      .iter()
+      .map(|instance| local.transformed(&instance.model_matrix()))
+      .reduce(|a, b| a.union(&b))
+  }
+
+  // Follow up with `update_instance` (or `update_instances`) to upload the change
+  pub fn instance_mut(&mut self, index: usize) -> Option<&mut instance::Instance> {
+    self.instances.get_mut(index)
+  }
+
+  // Uploads only instance `index`, for when a few instances move per frame.
+  // Returns false if there's no such instance
+  pub fn update_instance(&self, queue: &wgpu::Queue, index: usize) -> bool {
+    let Some(instance) = self.instances.get(index) else {
+      return false;
+    };
+    let offset = (index * std::mem::size_of::<instance::InstanceRaw>()) as wgpu::BufferAddress;
+    queue.write_buffer(&self.instance_buffer, offset, bytemuck::bytes_of(&instance.to_raw()));
+    true
+  }
+
+  // Uploads one model matrix per instance straight into the instance buffer, for
+  // callers that keep their own transforms (normal matrices are derived here).
+  // `instances` isn't touched, so bounds, picking and scene saving keep seeing
+  // the old transforms. Returns false unless there's exactly one matrix per instance
+  pub fn set_instance_matrices(&self, queue: &wgpu::Queue, matrices: &[cgmath::Matrix4<f32>]) -> bool {
+    if matrices.len() != self.instances.len() {
+      log::warn!("Got {} instance matrices for an object with {} instances", matrices.len(), self.instances.len());
+      return false;
+    }
+    let instance_data = matrices
+      .iter()
+      .map(instance::InstanceRaw::from_matrix)
+      .collect::<Vec<_>>();
+    queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+    true
+  }
+
+  // The instance buffer is sized for the instances the object was created with,
+  // so this can't grow the object: returns false, uploading nothing, if there are
+  // now more instances than that
+  pub fn update_instances(&mut self, queue: &wgpu::Queue) -> bool {
+    let capacity = self.instance_buffer.size() / std::mem::size_of::<instance::InstanceRaw>() as wgpu::BufferAddress;
+    if self.instances.len() as wgpu::BufferAddress > capacity {
+      log::warn!(
+        "Object grew from {} to {} instances; recreate it instead",
+        capacity,
+        self.instances.len(),
+      );
+      return false;
+    }
+    if let Some(target) = &mut self.cull_target {
+      if !target.update(queue, &self.model, self.instances.len() as u32) {
+        return false;
+      }
+    }
     let instance_data = self.instances
       .iter()
       .map(instance::Instance::to_raw)
       .collect::<Vec<_>>();
     queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+    if let Some(indirect_buffer) = &self.indirect_buffer {
+      queue.write_buffer(indirect_buffer, 0, &Self::indirect_args(&self.model, self.instances.len() as u32));
+    }
+    true
   }
 }
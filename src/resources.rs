@@ -1,32 +1,83 @@
+// wgpu's handles aren't Send or Sync on wasm, so neither are the models and
+// textures shared here; that's fine since wasm runs everything on one thread
+#![cfg_attr(target_arch = "wasm32", allow(clippy::arc_with_non_send_sync))]
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::io::{BufReader, Cursor};
-use wgpu::util::DeviceExt;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context;
 
 use crate::{model, texture};
 
+// Where file names passed to the loaders are resolved. Unset, native builds read
+// the `res` directory build.rs copies into OUT_DIR (or `res` under the working
+// directory when OUT_DIR isn't set), and web builds fetch from the page's origin
+#[cfg(not(target_arch = "wasm32"))]
+static ASSET_ROOT: RwLock<Option<PathBuf>> = RwLock::new(None);
+#[cfg(target_arch = "wasm32")]
+static ASSET_BASE_URL: RwLock<Option<String>> = RwLock::new(None);
+
+// Applies to every load started afterwards, including the resource cache's
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_asset_root(root: impl Into<PathBuf>) {
+  *ASSET_ROOT.write().unwrap() = Some(root.into());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn asset_root() -> PathBuf {
+  if let Some(root) = ASSET_ROOT.read().unwrap().as_ref() {
+    return root.clone();
+  }
+  match std::env::var("OUT_DIR") {
+    Ok(out_dir) => Path::new(&out_dir).join("res"),
+    Err(_) => PathBuf::from("res"),
+  }
+}
+
+// Files are fetched relative to `url`, e.g. "https://example.com/assets/"
+#[cfg(target_arch = "wasm32")]
+pub fn set_asset_base_url(url: impl Into<String>) {
+  *ASSET_BASE_URL.write().unwrap() = Some(url.into());
+}
+
 #[cfg(target_arch = "wasm32")]
-fn format_url(file_name: &str) -> reqwest::Url {
-    let window = web_sys::window().unwrap();
-    let location = window.location();
-    let mut origin = location.origin().unwrap();
-    if !origin.ends_with("learn-wgpu") {
+fn format_url(file_name: &str) -> anyhow::Result<reqwest::Url> {
+  let base = match ASSET_BASE_URL.read().unwrap().as_ref() {
+    Some(url) if url.ends_with('/') => url.clone(),
+    Some(url) => format!("{}/", url),
+    None => {
+      let window = web_sys::window().unwrap();
+      let location = window.location();
+      let mut origin = location.origin().unwrap();
+      if !origin.ends_with("learn-wgpu") {
         origin = format!("{}/learn-wgpu", origin);
+      }
+      format!("{}/", origin)
     }
-    let base = reqwest::Url::parse(&format!("{}/", origin,)).unwrap();
-    base.join(file_name).unwrap()
+  };
+  let base = reqwest::Url::parse(&base).with_context(|| format!("invalid asset base URL {:?}", base))?;
+  Ok(base.join(file_name)?)
 }
 
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
   #[cfg(target_arch = "wasm32")]
   let txt = {
-    let url = format_url(file_name);
-    reqwest::get(url).await?.text().await?
+    let url = format_url(file_name)?;
+    reqwest::get(url.clone())
+      .await
+      .and_then(|response| response.error_for_status())
+      .with_context(|| format!("couldn't fetch {}", url))?
+      .text()
+      .await?
   };
   #[cfg(not(target_arch = "wasm32"))]
   let txt = {
-    let path = std::path::Path::new(&std::env::var("OUT_DIR")?)
-      .join("res")
-      .join(file_name);
-    std::fs::read_to_string(path)?
+    let path = asset_root().join(file_name);
+    std::fs::read_to_string(&path).with_context(|| format!("couldn't read {}", path.display()))?
   };
   Ok(txt)
 }
@@ -34,27 +85,48 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
   #[cfg(target_arch = "wasm32")]
   let data = {
-    let url = format_url(file_name);
-    reqwest::get(url).await?.bytes().await?.to_vec()
+    let url = format_url(file_name)?;
+    reqwest::get(url.clone())
+      .await
+      .and_then(|response| response.error_for_status())
+      .with_context(|| format!("couldn't fetch {}", url))?
+      .bytes()
+      .await?
+      .to_vec()
   };
   #[cfg(not(target_arch = "wasm32"))]
   let data = {
-    let path = std::path::Path::new(&std::env::var("OUT_DIR")?)
-      .join("res")
-      .join(file_name);
-    std::fs::read(path)?
+    let path = asset_root().join(file_name);
+    std::fs::read(&path).with_context(|| format!("couldn't read {}", path.display()))?
   };
   Ok(data)
 }
 
+// Loads an image (or KTX2) file from the resource directory as a texture sampled
+// with `sampler_options`
 pub async fn load_texture(
   file_name: &str,
   is_normal_map: bool,
+  sampler_options: texture::SamplerOptions,
   device: &wgpu::Device,
   queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+  load_texture_with_uploader(file_name, is_normal_map, sampler_options, device, &mut texture::Uploader::Queue(queue)).await
+}
+
+async fn load_texture_with_uploader(
+  file_name: &str,
+  is_normal_map: bool,
+  sampler_options: texture::SamplerOptions,
+  device: &wgpu::Device,
+  uploader: &mut texture::Uploader<'_>,
 ) -> anyhow::Result<texture::Texture> {
   let data = load_binary(file_name).await?;
-  texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+  // KTX2 files carry their own format, so `is_normal_map` doesn't apply
+  if file_name.ends_with(".ktx2") {
+    return texture::Texture::from_ktx2_with_uploader(device, uploader, &data, file_name, sampler_options);
+  }
+  texture::Texture::from_bytes_with_uploader(device, uploader, &data, file_name, is_normal_map, sampler_options)
 }
 
 pub async fn load_model(
@@ -63,10 +135,89 @@ pub async fn load_model(
   queue: &wgpu::Queue,
   layout: &wgpu::BindGroupLayout,
 ) -> anyhow::Result<model::Model> {
+  load_model_with_progress(file_name, device, queue, layout, |_| {}).await
+}
+
+// Same as `load_model`, but reports progress in 0..=1 as the OBJ, each texture,
+// and each mesh finish loading
+pub async fn load_model_with_progress(
+  file_name: &str,
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  layout: &wgpu::BindGroupLayout,
+  on_progress: impl Fn(f32),
+) -> anyhow::Result<model::Model> {
+  load_model_inner(file_name, device, queue, layout, on_progress, ModelLoadOptions::default(), None).await
+}
+
+// Same as `load_model`, with vertex buffers in `vertex_format`; it has to match
+// the renderer's `RendererConfig::vertex_format` for the model to draw
+pub async fn load_model_with_format(
+  file_name: &str,
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  layout: &wgpu::BindGroupLayout,
+  vertex_format: model::VertexFormatOption,
+) -> anyhow::Result<model::Model> {
+  let options = ModelLoadOptions {
+    vertex_format,
+    ..Default::default()
+  };
+  load_model_with_options(file_name, device, queue, layout, options).await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelLoadOptions {
+  pub vertex_format: model::VertexFormatOption,
+  // Replace the file's normals with smooth ones averaged from the faces. Meshes
+  // whose OBJ has no (or only zero) normals get them either way
+  pub recompute_normals: bool,
+  // Flip V so a texture coordinate of 0 is the bottom row of the image, as OBJ
+  // expects, rather than the top row wgpu samples first. On by default; turn it
+  // off for assets exported with top-left texture origins
+  pub flip_v: bool,
+  // How every texture of the model is sampled
+  pub sampler_options: texture::SamplerOptions,
+}
+
+impl Default for ModelLoadOptions {
+  fn default() -> Self {
+    Self {
+      vertex_format: model::VertexFormatOption::default(),
+      recompute_normals: false,
+      flip_v: true,
+      sampler_options: texture::SamplerOptions::default(),
+    }
+  }
+}
+
+pub async fn load_model_with_options(
+  file_name: &str,
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  layout: &wgpu::BindGroupLayout,
+  options: ModelLoadOptions,
+) -> anyhow::Result<model::Model> {
+  load_model_inner(file_name, device, queue, layout, |_| {}, options, None).await
+}
+
+async fn load_model_inner(
+  file_name: &str,
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  layout: &wgpu::BindGroupLayout,
+  on_progress: impl Fn(f32),
+  options: ModelLoadOptions,
+  mut cache: Option<&mut ResourceCache>,
+) -> anyhow::Result<model::Model> {
+  on_progress(0.0);
+
   let obj_text = load_string(file_name).await?;
   let obj_cursor = Cursor::new(obj_text);
   let mut obj_reader = BufReader::new(obj_cursor);
 
+  // tobj fills in a black `Ks` for materials without one
+  let specular_materials = RefCell::new(HashSet::new());
   let (models, obj_materials) = tobj::load_obj_buf_async(
     &mut obj_reader,
     &tobj::LoadOptions {
@@ -74,29 +225,67 @@ pub async fn load_model(
       single_index: true,
       ..Default::default()
     },
-    |p| async move {
-      let mat_text = load_string(&p).await.unwrap();
-      tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+    |p| {
+      let specular_materials = &specular_materials;
+      async move {
+        let mat_text = load_string(&p).await.unwrap();
+        specular_materials.borrow_mut().extend(materials_with_specular(&mat_text));
+        tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+      }
     },
   ).await?;
+  let specular_materials = specular_materials.into_inner();
 
+  let obj_materials = obj_materials?;
+  // One step for the OBJ itself, two textures per material, then one per mesh
+  let total_steps = (1 + obj_materials.len() * 2 + models.len()) as f32;
+  let mut completed_steps = 1.0;
+  on_progress(completed_steps / total_steps);
+
+  // Every texture of the model goes up in one submission
+  let mut batch = texture::UploadBatch::new(device);
   let mut materials = Vec::new();
-  for m in obj_materials? {
-    let diffuse_texture = load_texture(&m.diffuse_texture, false, device, queue).await?;
-    let normal_texture = load_texture(&m.normal_texture, true, device, queue).await?;
-
-    materials.push(model::Material::new(
-      &device,
-      &m.name,
-      diffuse_texture,
-      normal_texture,
-      layout,
-    ));
+  for m in obj_materials {
+    let mut uploader = texture::Uploader::Batch(&mut batch);
+    let diffuse_texture = match cache.as_deref_mut() {
+      Some(cache) => cache.load_texture_with_uploader(&m.diffuse_texture, false, options.sampler_options, device, &mut uploader).await?,
+      None => Arc::new(load_texture_with_uploader(&m.diffuse_texture, false, options.sampler_options, device, &mut uploader).await?),
+    };
+    completed_steps += 1.0;
+    on_progress(completed_steps / total_steps);
+    let normal_texture = match cache.as_deref_mut() {
+      Some(cache) => cache.load_texture_with_uploader(&m.normal_texture, true, options.sampler_options, device, &mut uploader).await?,
+      None => Arc::new(load_texture_with_uploader(&m.normal_texture, true, options.sampler_options, device, &mut uploader).await?),
+    };
+    completed_steps += 1.0;
+    on_progress(completed_steps / total_steps);
+
+    let mut material = model::Material::new(device, &m.name, diffuse_texture, normal_texture, layout);
+    // Ns is 0 when the MTL leaves it out, which would turn the highlight into a flat wash
+    let shininess = if m.shininess > 0.0 { m.shininess } else { material.shininess };
+    let specular = if specular_materials.contains(&m.name) { m.specular } else { material.specular_color };
+    material = material.with_specular(queue, specular, shininess);
+    // `d` is opacity, `Tr` its inverse; tobj only parses `d` and defaults it to 1.0
+    let opacity = match m.unknown_param.get("Tr").and_then(|v| v.trim().parse::<f32>().ok()) {
+      Some(transparency) if m.dissolve == 1.0 => 1.0 - transparency,
+      _ => m.dissolve,
+    };
+    if opacity < 1.0 {
+      material = material.with_opacity(queue, opacity);
+    }
+    // tobj doesn't know Ke and leaves it with the unrecognized parameters
+    if let Some(emissive) = m.unknown_param.get("Ke").and_then(|v| parse_color(v)) {
+      material = material.with_emissive(queue, emissive);
+    }
+    materials.push(material);
   }
+  batch.submit(queue);
 
   let meshes = models
     .into_iter()
     .map(|m| {
+      // Missing `vn` lines leave tobj's normals empty
+      let has_normals = m.mesh.normals.iter().any(|&n| n != 0.0);
       let mut vertices =(0..m.mesh.positions.len() / 3)
         .map(|i| model::ModelVertex {
           position: [
@@ -106,89 +295,175 @@ pub async fn load_model(
           ],
           tex_coords: [
             m.mesh.texcoords[i * 2],
-            1.0 - m.mesh.texcoords[i * 2 + 1],
-          ],
-          normal: [
-            m.mesh.normals[i * 3],
-            m.mesh.normals[i * 3 + 1],
-            m.mesh.normals[i * 3 + 2],
+            if options.flip_v {
+              1.0 - m.mesh.texcoords[i * 2 + 1]
+            } else {
+              m.mesh.texcoords[i * 2 + 1]
+            },
           ],
+          normal: if has_normals {
+            [
+              m.mesh.normals[i * 3],
+              m.mesh.normals[i * 3 + 1],
+              m.mesh.normals[i * 3 + 2],
+            ]
+          } else {
+            [0.0; 3]
+          },
           tangent: [0.0; 3],
           bitangent: [0.0; 3],
+          // Vertex colors are optional in OBJ; white leaves the texture untouched
+          color: if m.mesh.vertex_color.is_empty() {
+            [1.0; 3]
+          } else {
+            [
+              m.mesh.vertex_color[i * 3],
+              m.mesh.vertex_color[i * 3 + 1],
+              m.mesh.vertex_color[i * 3 + 2],
+            ]
+          },
+          // OBJ has a single UV set and no skins
+          tex_coords_1: [0.0; 2],
+          joints: [0; 4],
+          weights: [0.0; 4],
         })
         .collect::<Vec<_>>();
 
-      let indices = &m.mesh.indices;
-      let mut triangles_included = vec![0; vertices.len()];
-      for c in indices.chunks(3) {
-        let v0 = &vertices[c[0] as usize];
-        let v1 = &vertices[c[1] as usize];
-        let v2 = &vertices[c[2] as usize];
-
-        let pos0: cgmath::Vector3<_> = v0.position.into();
-        let pos1: cgmath::Vector3<_> = v1.position.into();
-        let pos2: cgmath::Vector3<_> = v2.position.into();
-
-        let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-        let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-        let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
-
-        let delta_pos1= pos1 - pos0;
-        let delta_pos2= pos2 - pos0;
-
-        let delta_uv1= uv1 - uv0;
-        let delta_uv2= uv2 - uv0;
-
-        let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-        vertices[c[0] as usize].tangent = 
-          (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-        vertices[c[0] as usize].bitangent =
-          (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-
-        triangles_included[c[0] as usize] += 1;
-        vertices[c[1] as usize].tangent = 
-          (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-        vertices[c[1] as usize].bitangent =
-          (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-        triangles_included[c[1] as usize] += 1;
-
-        vertices[c[2] as usize].tangent = 
-          (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-        vertices[c[2] as usize].bitangent =
-          (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-        triangles_included[c[2] as usize] += 1;
-      }
-
-      for (i, n) in triangles_included.into_iter().enumerate() {
-        let denom = 1.0 / n as f32;
-        let v = &mut vertices[i];
-        v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-        v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+      if options.recompute_normals || !has_normals {
+        model::compute_normals(&mut vertices, &m.mesh.indices);
       }
+      model::compute_tangents(&mut vertices, &m.mesh.indices);
 
-      let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some(&format!("{:?} Vertex Buffer", file_name)),
-        contents: bytemuck::cast_slice(&vertices),
-        usage: wgpu::BufferUsages::VERTEX,
-      });
-      let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some(&format!("{:?} Index Buffer", file_name)),
-        contents: bytemuck::cast_slice(&m.mesh.indices),
-        usage: wgpu::BufferUsages::INDEX,
-      });
-
-      model::Mesh {
-        name: file_name.to_string(),
-        vertex_buffer,
-        index_buffer,
-        num_elements: m.mesh.indices.len() as u32,
-        material: m.mesh.material_id.unwrap_or(0),
-      }
+      let mesh = model::Mesh::with_format(
+        device,
+        file_name,
+        &vertices,
+        &m.mesh.indices,
+        m.mesh.material_id.unwrap_or(0),
+        options.vertex_format,
+      );
+      completed_steps += 1.0;
+      on_progress(completed_steps / total_steps);
+      mesh
     })
     .collect::<Vec<_>>();
 
-  Ok(model::Model { meshes, materials })
-}
\ No newline at end of file
+  let mut model = model::Model::new(meshes, materials);
+  model.validate_materials(device, queue, layout);
+  Ok(model)
+}
+
+// Names of the MTL's materials with a `Ks` line
+fn materials_with_specular(mtl_text: &str) -> HashSet<String> {
+  let mut names = HashSet::new();
+  let mut current = None;
+  for line in mtl_text.lines() {
+    let mut words = line.split_whitespace();
+    match words.next() {
+      Some("newmtl") => current = words.next(),
+      Some("Ks") => names.extend(current.map(str::to_string)),
+      _ => {}
+    }
+  }
+  names
+}
+
+fn parse_color(value: &str) -> Option<[f32; 3]> {
+  let mut components = value.split_whitespace().map(str::parse::<f32>);
+  let r = components.next()?.ok()?;
+  // A single value means grey
+  let g = components.next().unwrap_or(Ok(r)).ok()?;
+  let b = components.next().unwrap_or(Ok(g)).ok()?;
+  Some([r, g, b])
+}
+
+// Keeps loaded models and textures by file name so repeated loads share one GPU upload
+#[derive(Default)]
+pub struct ResourceCache {
+  models: HashMap<String, Arc<model::Model>>,
+  // Keyed on the normal-map flag too, since it changes the texture format, and on
+  // the sampler the texture was made with
+  textures: HashMap<(String, bool, texture::SamplerOptions), Arc<texture::Texture>>,
+  // Applied to every model loaded through the cache
+  options: ModelLoadOptions,
+}
+
+impl ResourceCache {
+  pub fn with_options(options: ModelLoadOptions) -> Self {
+    Self {
+      options,
+      ..Self::default()
+    }
+  }
+
+  pub async fn load_model(
+    &mut self,
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+  ) -> anyhow::Result<Arc<model::Model>> {
+    if let Some(model) = self.models.get(file_name) {
+      return Ok(model.clone());
+    }
+
+    let model = Arc::new(load_model_inner(file_name, device, queue, layout, |_| {}, self.options, Some(self)).await?);
+    self.models.insert(file_name.to_string(), model.clone());
+    Ok(model)
+  }
+
+  pub async fn load_texture(
+    &mut self,
+    file_name: &str,
+    is_normal_map: bool,
+    sampler_options: texture::SamplerOptions,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+  ) -> anyhow::Result<Arc<texture::Texture>> {
+    self.load_texture_with_uploader(file_name, is_normal_map, sampler_options, device, &mut texture::Uploader::Queue(queue)).await
+  }
+
+  async fn load_texture_with_uploader(
+    &mut self,
+    file_name: &str,
+    is_normal_map: bool,
+    sampler_options: texture::SamplerOptions,
+    device: &wgpu::Device,
+    uploader: &mut texture::Uploader<'_>,
+  ) -> anyhow::Result<Arc<texture::Texture>> {
+    let key = (file_name.to_string(), is_normal_map, sampler_options);
+    if let Some(texture) = self.textures.get(&key) {
+      return Ok(texture.clone());
+    }
+
+    let texture = Arc::new(
+      load_texture_with_uploader(file_name, is_normal_map, sampler_options, device, uploader).await?,
+    );
+    self.textures.insert(key, texture.clone());
+    Ok(texture)
+  }
+
+  // File name a cached model was loaded from, if it came through this cache
+  pub fn model_name(&self, model: &Arc<model::Model>) -> Option<&str> {
+    self.models
+      .iter()
+      .find(|(_, cached)| Arc::ptr_eq(cached, model))
+      .map(|(name, _)| name.as_str())
+  }
+
+  pub fn clear(&mut self) {
+    self.models.clear();
+    self.textures.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_materials_with_ks_have_specular() {
+    let mtl = "newmtl shiny\nKd 1 1 1\nKs 0.5 0.5 0.5\n\nnewmtl matte\nKd 1 0 0\nNs 10\n";
+    assert_eq!(materials_with_specular(mtl), HashSet::from(["shiny".to_string()]));
+  }
+}
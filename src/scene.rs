@@ -0,0 +1,134 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::draw_traits::DrawMethod;
+use crate::instance::Instance;
+
+// Plain-data snapshot of what `State` is drawing. Everything is stored as the raw
+// f32 values, so capturing and reloading a scene reproduces it exactly
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+  pub objects: Vec<SceneObject>,
+  pub camera: SceneCamera,
+  pub light: SceneLight,
+}
+
+impl Scene {
+  // Pretty-printed, so saved scenes can be edited by hand
+  pub fn to_json(&self) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(self)?)
+  }
+
+  pub fn from_json(json: &str) -> anyhow::Result<Self> {
+    Ok(serde_json::from_str(json)?)
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    std::fs::write(path, self.to_json()?).with_context(|| format!("couldn't write {}", path.display()))
+  }
+
+  // Natively `path` is a file path; on wasm it's fetched like any other resource
+  pub async fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+    let path = path.as_ref();
+    #[cfg(not(target_arch = "wasm32"))]
+    let json = std::fs::read_to_string(path).with_context(|| format!("couldn't read {}", path.display()))?;
+    #[cfg(target_arch = "wasm32")]
+    let json = crate::resources::load_string(&path.to_string_lossy()).await?;
+    Self::from_json(&json).with_context(|| format!("{} isn't a valid scene", path.display()))
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneObject {
+  // Resource file name, loaded through the resource cache
+  pub model: String,
+  pub pipeline: Option<String>,
+  pub draw_method: DrawMethod,
+  pub instances: Vec<SceneInstance>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SceneInstance {
+  pub position: [f32; 3],
+  // Quaternion as (x, y, z, w)
+  pub rotation: [f32; 4],
+  pub scale: [f32; 3],
+}
+
+impl From<&Instance> for SceneInstance {
+  fn from(instance: &Instance) -> Self {
+    let r = instance.rotation;
+    Self {
+      position: instance.position.into(),
+      rotation: [r.v.x, r.v.y, r.v.z, r.s],
+      scale: instance.scale.into(),
+    }
+  }
+}
+
+impl From<&SceneInstance> for Instance {
+  fn from(instance: &SceneInstance) -> Self {
+    let [x, y, z, w] = instance.rotation;
+    Self {
+      position: instance.position.into(),
+      rotation: cgmath::Quaternion::new(w, x, y, z),
+      scale: instance.scale.into(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SceneCamera {
+  pub position: [f32; 3],
+  // Radians
+  pub yaw: f32,
+  pub pitch: f32,
+  pub fovy: f32,
+  pub znear: f32,
+  pub zfar: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SceneLight {
+  pub position: [f32; 3],
+  pub color: [f32; 3],
+  pub animated: bool,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn json_round_trips_exactly() {
+    let scene = Scene {
+      objects: vec![SceneObject {
+        model: "cube.obj".to_string(),
+        pipeline: None,
+        draw_method: DrawMethod::WithMaterial,
+        instances: vec![SceneInstance {
+          position: [0.1, -3.3333333, 1e-7],
+          rotation: [0.0, 0.6, 0.0, 0.8],
+          scale: [1.0, 2.5, 0.3],
+        }],
+      }],
+      camera: SceneCamera {
+        position: [0.0, 5.0, 10.0],
+        yaw: -1.2345678,
+        pitch: -0.34906584,
+        fovy: 0.8,
+        znear: 0.1,
+        zfar: 100.0,
+      },
+      light: SceneLight {
+        position: [2.0, 2.0, 2.0],
+        color: [1.0, 0.9, 0.8],
+        animated: true,
+      },
+    };
+    let json = scene.to_json().unwrap();
+    assert_eq!(Scene::from_json(&json).unwrap(), scene);
+  }
+}
@@ -0,0 +1,122 @@
+use naga::{Expression, Function, FunctionResult, Span, Statement};
+
+// Appends `helpers` to `source`, then rewrites the one vertex entry point taking
+// an argument of the type `function` returns: in its place it takes `function`'s
+// parameters and passes on what `function` makes of them, so the original body
+// runs unchanged on the derived value. Works on the parsed module, so names,
+// formatting and comments in `source` don't matter
+pub(crate) fn reroute_vertex_argument(source: &str, helpers: &str, function: &str) -> anyhow::Result<String> {
+  let source = format!("{source}\n{helpers}");
+  let mut module = naga::front::wgsl::parse_str(&source).map_err(|e| anyhow::anyhow!(e.emit_to_string(&source)))?;
+
+  let (helper, helper_fn) = module
+    .functions
+    .iter()
+    .find(|(_, f)| f.name.as_deref() == Some(function))
+    .ok_or_else(|| anyhow::anyhow!("no function {function:?} in the helpers"))?;
+  let Some(FunctionResult { ty: input_ty, .. }) = helper_fn.result else {
+    anyhow::bail!("{function:?} has to return the argument it derives");
+  };
+  let helper_arguments = helper_fn.arguments.clone();
+  let input_name = module.types[input_ty].name.clone().unwrap_or_default();
+
+  let entries = module
+    .entry_points
+    .iter()
+    .enumerate()
+    .filter(|(_, entry)| {
+      entry.stage == naga::ShaderStage::Vertex && entry.function.arguments.iter().any(|arg| arg.ty == input_ty)
+    })
+    .map(|(index, _)| index)
+    .collect::<Vec<_>>();
+  let [index] = entries[..] else {
+    anyhow::bail!("expected one vertex entry point taking {input_name}, found {}", entries.len());
+  };
+
+  // The original body becomes a plain function the new entry point calls
+  let entry = &mut module.entry_points[index];
+  let mut body = std::mem::take(&mut entry.function);
+  let mut wrapper = Function {
+    name: body.name.clone(),
+    arguments: body.arguments.clone(),
+    result: body.result.clone(),
+    ..Default::default()
+  };
+  body.name = Some(format!("{}_body", entry.name));
+  for arg in &mut body.arguments {
+    arg.binding = None;
+  }
+  if let Some(result) = &mut body.result {
+    result.binding = None;
+  }
+  let position = body.arguments.iter().position(|arg| arg.ty == input_ty).unwrap();
+  wrapper.arguments.splice(position..=position, helper_arguments.iter().cloned());
+  let body_arguments = body.arguments.len();
+  let body = module.functions.append(body, Span::UNDEFINED);
+
+  let arguments = (0..wrapper.arguments.len() as u32)
+    .map(|i| wrapper.expressions.append(Expression::FunctionArgument(i), Span::UNDEFINED))
+    .collect::<Vec<_>>();
+  let input = wrapper.expressions.append(Expression::CallResult(helper), Span::UNDEFINED);
+  wrapper.body.push(
+    Statement::Call {
+      function: helper,
+      arguments: arguments[position..position + helper_arguments.len()].to_vec(),
+      result: Some(input),
+    },
+    Span::UNDEFINED,
+  );
+  let mut passed = arguments[..position].to_vec();
+  passed.push(input);
+  passed.extend_from_slice(&arguments[position + helper_arguments.len()..]);
+  debug_assert_eq!(passed.len(), body_arguments);
+  let result = wrapper
+    .result
+    .is_some()
+    .then(|| wrapper.expressions.append(Expression::CallResult(body), Span::UNDEFINED));
+  wrapper.body.push(Statement::Call { function: body, arguments: passed, result }, Span::UNDEFINED);
+  wrapper.body.push(Statement::Return { value: result }, Span::UNDEFINED);
+  module.entry_points[index].function = wrapper;
+
+  let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+    .validate(&module)
+    .map_err(|e| anyhow::anyhow!(e.emit_to_string(&source)))?;
+  Ok(naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SHADER: &str = "
+struct In { @location(0) value: f32 }
+struct Out { @builtin(position) position: vec4<f32> }
+
+@vertex fn main(@builtin(vertex_index) index: u32, input: In) -> Out { // on one line on purpose
+    return Out(vec4<f32>(input.value, f32(index), 0.0, 1.0));
+}
+";
+
+  const DOUBLE: &str = "
+struct Raw { @location(1) value: f32 }
+fn double(raw: Raw) -> In {
+    return In(raw.value * 2.0);
+}
+";
+
+  #[test]
+  fn the_argument_is_replaced_by_the_helpers_parameters() {
+    let source = reroute_vertex_argument(SHADER, DOUBLE, "double").unwrap();
+    let module = naga::front::wgsl::parse_str(&source).unwrap();
+    let main = &module.entry_points[0].function;
+    let types = main.arguments.iter().map(|arg| module.types[arg.ty].name.as_deref()).collect::<Vec<_>>();
+    assert_eq!(types, [None, Some("Raw")]);
+  }
+
+  #[test]
+  fn the_entry_point_has_to_take_the_helpers_result() {
+    let unrelated = "struct Other { v: f32 }\nfn make() -> Other { return Other(1.0); }";
+    assert!(reroute_vertex_argument(SHADER, unrelated, "make").is_err());
+    assert!(reroute_vertex_argument(SHADER, DOUBLE, "missing").is_err());
+  }
+}
@@ -0,0 +1,305 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3};
+use wgpu::util::DeviceExt;
+
+use crate::{bounds, camera, instance, model, render_stats};
+use crate::model::Vertex;
+use crate::renderable_object::RenderableObject;
+
+pub const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// Framed when the scene has nothing with bounds yet
+const FALLBACK_RADIUS: f32 = 10.0;
+// Closest the shadow camera gets to the bounds' center, in bounding radii; at 2
+// the frustum is at most 60 degrees wide
+const MIN_DISTANCE_RADII: f32 = 2.0;
+// Widest PCF kernel; every tap is a texture read per lit fragment
+const MAX_PCF_SAMPLES: u32 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowConfig {
+  // Width and height of the square shadow map in texels
+  pub resolution: u32,
+  // Subtracted from the fragment's light-space depth; fights acne on lit faces
+  pub bias: f32,
+  // World-space push along the surface normal before the lookup; fights acne on
+  // grazing faces without the peter-panning a large `bias` causes
+  pub normal_bias: f32,
+  // Width of the square filter kernel in texels: 1 is a single tap, 3 is 3x3, ...
+  // Even widths round up to the next odd one, and it's capped at 9
+  pub pcf_samples: u32,
+  // Bilinear comparison filtering in the sampler, so every tap already blends the
+  // four texels around it. Off, taps are binary and edges step texel by texel
+  pub hardware_filtering: bool,
+}
+
+impl Default for ShadowConfig {
+  fn default() -> Self {
+    Self {
+      resolution: 2048,
+      bias: 0.002,
+      normal_bias: 0.02,
+      pcf_samples: 3,
+      hardware_filtering: true,
+    }
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+  view_proj: [[f32; 4]; 4],
+  bias: f32,
+  normal_bias: f32,
+  pcf_samples: u32,
+  enabled: u32,
+}
+
+// A single shadow map rendered from the light toward the center of the scene,
+// with a frustum just wide enough to cover the scene's bounds
+pub struct ShadowMap {
+  config: ShadowConfig,
+  view: wgpu::TextureView,
+  sampler: wgpu::Sampler,
+  uniform: ShadowUniform,
+  uniform_buffer: wgpu::Buffer,
+  camera_buffer: wgpu::Buffer,
+  camera_bind_group: wgpu::BindGroup,
+  pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMap {
+  pub fn new(device: &wgpu::Device, config: ShadowConfig, vertex_format: model::VertexFormatOption) -> Self {
+    let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      entries: &[wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      }],
+      label: Some("shadow_camera_bind_group_layout"),
+    });
+    let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+    let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Shadow Camera Buffer"),
+      contents: bytemuck::cast_slice(&[identity]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &camera_layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: camera_buffer.as_entire_binding(),
+      }],
+      label: Some("shadow_camera_bind_group"),
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("Shadow Pipeline Layout"),
+      bind_group_layouts: &[&camera_layout],
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some("Shadow Shader"),
+      source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shadow.wgsl").into()),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Shadow Pipeline"),
+      layout: Some(&layout),
+      vertex: wgpu::VertexState {
+        module: &shader,
+        entry_point: Some("vs_main"),
+        buffers: &[vertex_format.layout(), instance::InstanceRaw::desc()],
+        compilation_options: Default::default(),
+      },
+      fragment: None,
+      primitive: wgpu::PrimitiveState {
+        cull_mode: Some(wgpu::Face::Back),
+        ..Default::default()
+      },
+      depth_stencil: Some(wgpu::DepthStencilState {
+        format: SHADOW_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::LessEqual,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+      }),
+      multisample: wgpu::MultisampleState::default(),
+      multiview: None,
+      cache: None,
+    });
+
+    let uniform = ShadowUniform {
+      view_proj: identity,
+      bias: config.bias,
+      normal_bias: config.normal_bias,
+      pcf_samples: Self::pcf_samples(&config),
+      enabled: 0,
+    };
+    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Shadow Buffer"),
+      contents: bytemuck::cast_slice(&[uniform]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    Self {
+      config,
+      view: Self::create_view(device, config.resolution),
+      sampler: Self::create_sampler(device, config.hardware_filtering),
+      uniform,
+      uniform_buffer,
+      camera_buffer,
+      camera_bind_group,
+      pipeline,
+    }
+  }
+
+  // Lit where the fragment's depth is no farther than the stored one
+  fn create_sampler(device: &wgpu::Device, hardware_filtering: bool) -> wgpu::Sampler {
+    let filter = if hardware_filtering {
+      wgpu::FilterMode::Linear
+    } else {
+      wgpu::FilterMode::Nearest
+    };
+    device.create_sampler(&wgpu::SamplerDescriptor {
+      label: Some("Shadow Sampler"),
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      mag_filter: filter,
+      min_filter: filter,
+      compare: Some(wgpu::CompareFunction::LessEqual),
+      ..Default::default()
+    })
+  }
+
+  // Odd, so the kernel centers on the fragment's texel
+  fn pcf_samples(config: &ShadowConfig) -> u32 {
+    config.pcf_samples.clamp(1, MAX_PCF_SAMPLES) | 1
+  }
+
+  fn create_view(device: &wgpu::Device, resolution: u32) -> wgpu::TextureView {
+    let resolution = resolution.clamp(1, device.limits().max_texture_dimension_2d);
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("shadow_map"),
+      size: wgpu::Extent3d {
+        width: resolution,
+        height: resolution,
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: SHADOW_FORMAT,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+      view_formats: &[],
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+  }
+
+  pub fn config(&self) -> ShadowConfig {
+    self.config
+  }
+
+  // Returns true if the map or its sampler was recreated, in which case bind
+  // groups holding `view()` or `sampler()` have to be rebuilt
+  pub fn set_config(&mut self, device: &wgpu::Device, config: ShadowConfig) -> bool {
+    let resized = config.resolution != self.config.resolution;
+    if resized {
+      self.view = Self::create_view(device, config.resolution);
+    }
+    let refiltered = config.hardware_filtering != self.config.hardware_filtering;
+    if refiltered {
+      self.sampler = Self::create_sampler(device, config.hardware_filtering);
+    }
+    self.config = config;
+    self.uniform.bias = config.bias;
+    self.uniform.normal_bias = config.normal_bias;
+    self.uniform.pcf_samples = Self::pcf_samples(&config);
+    resized || refiltered
+  }
+
+  pub fn view(&self) -> &wgpu::TextureView {
+    &self.view
+  }
+
+  pub fn sampler(&self) -> &wgpu::Sampler {
+    &self.sampler
+  }
+
+  pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+    &self.uniform_buffer
+  }
+
+  // Aims the light's frustum at `scene_bounds` and uploads it; shading ignores the
+  // map while `enabled` is false
+  pub fn update(&mut self, queue: &wgpu::Queue, light_position: [f32; 3], scene_bounds: Option<bounds::Aabb>, enabled: bool) {
+    let light = Point3::from(light_position);
+    let (target, radius) = scene_bounds
+      .map(|bounds| (bounds.center(), bounds.radius()))
+      .unwrap_or((Point3::origin(), FALLBACK_RADIUS));
+    // A light inside or close to the bounds would need a field of view nearing
+    // 180 degrees, so the map is rendered from further out along the light's
+    // direction instead, which keeps its shadows pointing the same way
+    let offset = light - target;
+    let direction = if offset.magnitude2() > 0.0 { offset.normalize() } else { cgmath::Vector3::unit_y() };
+    let distance = offset.magnitude().max(radius * MIN_DISTANCE_RADII).max(0.1);
+    let eye = target + direction * distance;
+    let half_fov = (radius / distance).asin();
+    let up = if (target - eye).cross(cgmath::Vector3::unit_y()).is_zero() {
+      cgmath::Vector3::unit_z()
+    } else {
+      cgmath::Vector3::unit_y()
+    };
+
+    let view = Matrix4::look_at_rh(eye, target, up);
+    let projection = camera::OPENGL_TO_WGPU_MATRIX
+      * cgmath::perspective(cgmath::Rad(half_fov * 2.0), 1.0, (distance - radius).max(0.05), distance + radius);
+    let view_proj = projection * view;
+
+    self.uniform.view_proj = view_proj.into();
+    self.uniform.enabled = enabled as u32;
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.uniform.view_proj]));
+  }
+
+  // Draws every given object's instances into the map
+  pub fn render<'a>(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    objects: impl Iterator<Item = &'a RenderableObject>,
+    stats: &mut render_stats::RenderStats,
+    timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+  ) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("Shadow Pass"),
+      color_attachments: &[],
+      depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+        view: &self.view,
+        depth_ops: Some(wgpu::Operations {
+          load: wgpu::LoadOp::Clear(1.0),
+          store: wgpu::StoreOp::Store,
+        }),
+        stencil_ops: None,
+      }),
+      occlusion_query_set: None,
+      timestamp_writes,
+    });
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+
+    for obj in objects {
+      let instances = 0..obj.instances.len() as u32;
+      render_pass.set_vertex_buffer(1, obj.instance_buffer.slice(..));
+      for mesh in &obj.model.meshes {
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+        render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+      }
+      stats.record_model(&obj.model, obj.instances.len() as u32);
+    }
+  }
+}
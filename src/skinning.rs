@@ -0,0 +1,426 @@
+use std::num::NonZeroU64;
+
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Quaternion, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::shader_rewrite;
+
+// main_pipeline's shading with every vertex moved by its joints first; objects of
+// skinned models are drawn with it unless they name a pipeline of their own.
+// Shadows, picking, outlines and debug views still see the rest pose
+pub const PIPELINE: &str = "skinned_pipeline";
+
+// Bind group index of the joint matrices, after the texture, camera and light groups
+pub const GROUP: u32 = 3;
+
+const JOINT_MATRIX_SIZE: u64 = std::mem::size_of::<[[f32; 4]; 4]>() as u64;
+
+// The joint matrices are read from a storage buffer in the vertex shader, which
+// WebGL2 and some downlevel backends can't do
+pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+  adapter
+    .get_downlevel_capabilities()
+    .flags
+    .contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+    && device.limits().max_storage_buffers_per_shader_stage >= 1
+}
+
+pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+  device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+    entries: &[wgpu::BindGroupLayoutEntry {
+      binding: 0,
+      visibility: wgpu::ShaderStages::VERTEX,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Storage { read_only: true },
+        has_dynamic_offset: false,
+        min_binding_size: NonZeroU64::new(JOINT_MATRIX_SIZE),
+      },
+      count: None,
+    }],
+    label: Some("joint_matrices_layout"),
+  })
+}
+
+// Turns a shader written for the standard `VertexInput` into one that skins it
+// with the joint matrices at `GROUP` before anything else reads it. Fails unless
+// exactly one vertex entry point takes a VertexInput
+pub fn shader_source(source: &str) -> anyhow::Result<String> {
+  let helpers = format!(
+    "
+struct SkinInput {{
+    @location(14) joints: vec4<u32>,
+    @location(15) weights: vec4<f32>,
+}}
+@group({GROUP}) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+// The normal basis goes through the blended matrix's upper 3x3, which is exact
+// for joints that only rotate and scale uniformly
+fn skin_vertex(v: VertexInput, skin: SkinInput) -> VertexInput {{
+    let total = dot(skin.weights, vec4<f32>(1.0));
+    if total <= 0.0 {{
+        return v;
+    }}
+    let w = skin.weights / total;
+    let m = joint_matrices[skin.joints.x] * w.x
+        + joint_matrices[skin.joints.y] * w.y
+        + joint_matrices[skin.joints.z] * w.z
+        + joint_matrices[skin.joints.w] * w.w;
+    let basis = mat3x3<f32>(m[0].xyz, m[1].xyz, m[2].xyz);
+    var out = v;
+    out.position = vec4<f32>((m * vec4<f32>(v.position.xyz, 1.0)).xyz, 1.0);
+    out.normal = vec4<f32>(basis * v.normal.xyz, 0.0);
+    out.tangent = vec4<f32>(basis * v.tangent.xyz, 0.0);
+    out.bitangent = vec4<f32>(basis * v.bitangent.xyz, 0.0);
+    return out;
+}}
+"
+  );
+  shader_rewrite::reroute_vertex_argument(source, &helpers, "skin_vertex")
+}
+
+// A joint's placement relative to its parent, or to the model for root joints
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JointTransform {
+  pub translation: Vector3<f32>,
+  pub rotation: Quaternion<f32>,
+  pub scale: Vector3<f32>,
+}
+
+impl JointTransform {
+  pub const IDENTITY: Self = Self {
+    translation: Vector3::new(0.0, 0.0, 0.0),
+    rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+    scale: Vector3::new(1.0, 1.0, 1.0),
+  };
+
+  // Scales, then rotates, then translates, as glTF nodes do
+  pub fn matrix(&self) -> Matrix4<f32> {
+    Matrix4::from_translation(self.translation)
+      * Matrix4::from(self.rotation)
+      * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+  }
+}
+
+impl Default for JointTransform {
+  fn default() -> Self {
+    Self::IDENTITY
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Joint {
+  pub name: String,
+  // Index into the skin's joints, which lists parents before their children
+  pub parent: Option<usize>,
+  // Where the joint sits when no animation moves it
+  pub rest: JointTransform,
+  // From model space into the joint's space in the pose the mesh was modeled in
+  pub inverse_bind: Matrix4<f32>,
+}
+
+// One keyframe value per time of a Channel, as glTF's translation, rotation and
+// scale paths
+#[derive(Debug, Clone)]
+pub enum Keyframes {
+  Translation(Vec<Vector3<f32>>),
+  Rotation(Vec<Quaternion<f32>>),
+  Scale(Vec<Vector3<f32>>),
+}
+
+impl Keyframes {
+  fn len(&self) -> usize {
+    match self {
+      Keyframes::Translation(values) | Keyframes::Scale(values) => values.len(),
+      Keyframes::Rotation(values) => values.len(),
+    }
+  }
+}
+
+// One property of one joint over time: interpolated linearly between keyframes
+// (rotations spherically) and held before the first and after the last
+#[derive(Debug, Clone)]
+pub struct Channel {
+  pub joint: usize,
+  // Seconds from the start of the clip, increasing
+  pub times: Vec<f32>,
+  pub keyframes: Keyframes,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+  pub name: String,
+  pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+  // Time of the last keyframe of any channel, in seconds
+  pub fn duration(&self) -> f32 {
+    self
+      .channels
+      .iter()
+      .filter_map(|channel| channel.times.last().copied())
+      .fold(0.0, f32::max)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Skin {
+  joints: Vec<Joint>,
+  animations: Vec<AnimationClip>,
+}
+
+impl Skin {
+  // Fails unless there's at least one joint, every parent comes before its
+  // children, joint indices fit the vertices' u16s and each channel targets a
+  // joint with one finite, increasing time per keyframe
+  pub fn new(joints: Vec<Joint>, animations: Vec<AnimationClip>) -> anyhow::Result<Self> {
+    if joints.is_empty() || joints.len() > u16::MAX as usize + 1 {
+      anyhow::bail!("a skin needs 1 to {} joints, got {}", u16::MAX as usize + 1, joints.len());
+    }
+    for (i, joint) in joints.iter().enumerate() {
+      if joint.parent.is_some_and(|parent| parent >= i) {
+        anyhow::bail!("joint {} ({:?}) comes before its parent {:?}", i, joint.name, joint.parent);
+      }
+    }
+    for clip in &animations {
+      for channel in &clip.channels {
+        if channel.joint >= joints.len() {
+          anyhow::bail!("animation {:?} targets joint {}, but there are only {}", clip.name, channel.joint, joints.len());
+        }
+        if channel.times.is_empty() || channel.times.len() != channel.keyframes.len() {
+          anyhow::bail!(
+            "animation {:?} has a channel with {} times for {} keyframes",
+            clip.name,
+            channel.times.len(),
+            channel.keyframes.len(),
+          );
+        }
+        if channel.times.iter().any(|time| !time.is_finite()) || channel.times.windows(2).any(|pair| pair[0] >= pair[1]) {
+          anyhow::bail!("animation {:?} has a channel whose times aren't finite and increasing", clip.name);
+        }
+      }
+    }
+    Ok(Self { joints, animations })
+  }
+
+  pub fn joints(&self) -> &[Joint] {
+    &self.joints
+  }
+
+  pub fn animations(&self) -> &[AnimationClip] {
+    &self.animations
+  }
+
+  pub fn animation_index(&self, name: &str) -> Option<usize> {
+    self.animations.iter().position(|clip| clip.name == name)
+  }
+
+  // Every joint's transform `time` seconds into animation `clip`; joints the clip
+  // doesn't move, or all of them without a clip, stay at rest
+  pub fn pose(&self, clip: Option<usize>, time: f32) -> Vec<JointTransform> {
+    let mut pose = self.joints.iter().map(|joint| joint.rest).collect::<Vec<_>>();
+    let Some(clip) = clip.and_then(|clip| self.animations.get(clip)) else {
+      return pose;
+    };
+    for channel in &clip.channels {
+      let transform = &mut pose[channel.joint];
+      match &channel.keyframes {
+        Keyframes::Translation(values) => transform.translation = sample(&channel.times, values, time, Vector3::lerp),
+        Keyframes::Rotation(values) => {
+          transform.rotation = sample(&channel.times, values, time, |a, b, t| a.normalize().slerp(b.normalize(), t))
+        }
+        Keyframes::Scale(values) => transform.scale = sample(&channel.times, values, time, Vector3::lerp),
+      }
+    }
+    pose
+  }
+
+  // One matrix per joint taking vertices from where they were modeled to where
+  // `pose` puts them, in model space
+  pub fn joint_matrices(&self, pose: &[JointTransform]) -> Vec<Matrix4<f32>> {
+    let mut globals: Vec<Matrix4<f32>> = Vec::with_capacity(self.joints.len());
+    for (joint, transform) in self.joints.iter().zip(pose) {
+      let local = transform.matrix();
+      let global = match joint.parent {
+        Some(parent) => globals[parent] * local,
+        None => local,
+      };
+      globals.push(global);
+    }
+    globals.iter().zip(&self.joints).map(|(global, joint)| global * joint.inverse_bind).collect()
+  }
+}
+
+fn sample<T: Copy>(times: &[f32], values: &[T], time: f32, interpolate: impl Fn(T, T, f32) -> T) -> T {
+  let next = times.partition_point(|&keyframe| keyframe <= time);
+  if next == 0 {
+    return values[0];
+  }
+  if next == times.len() {
+    return values[next - 1];
+  }
+  let t = (time - times[next - 1]) / (times[next] - times[next - 1]);
+  interpolate(values[next - 1], values[next], t)
+}
+
+// Playback of one object's skin, and the GPU copy of its joint matrices once
+// it's been added to a State that supports skinning
+pub(crate) struct SkinPose {
+  clip: Option<usize>,
+  time: f32,
+  // Set when the pose changed without a clip playing, e.g. on stopping
+  dirty: bool,
+  joint_buffer: Option<wgpu::Buffer>,
+}
+
+impl SkinPose {
+  pub fn new() -> Self {
+    Self {
+      clip: None,
+      time: 0.0,
+      dirty: false,
+      joint_buffer: None,
+    }
+  }
+
+  // From the start, looping
+  pub fn play(&mut self, clip: usize) {
+    self.clip = Some(clip);
+    self.time = 0.0;
+  }
+
+  // Back to the rest pose
+  pub fn stop(&mut self) {
+    self.clip = None;
+    self.time = 0.0;
+    self.dirty = true;
+  }
+
+  pub fn clip(&self) -> Option<usize> {
+    self.clip
+  }
+
+  pub fn advance(&mut self, skin: &Skin, dt: f32) {
+    let Some(clip) = self.clip.and_then(|clip| skin.animations.get(clip)) else {
+      return;
+    };
+    let duration = clip.duration();
+    self.time = if duration > 0.0 { (self.time + dt) % duration } else { 0.0 };
+  }
+
+  // Creates the joint buffer in the current pose; the returned bind group goes at GROUP
+  pub fn attach(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, skin: &Skin) -> wgpu::BindGroup {
+    let joint_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Joint Matrix Buffer"),
+      contents: bytemuck::cast_slice(&self.matrices(skin)),
+      usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: joint_buffer.as_entire_binding(),
+      }],
+      label: Some("joint_matrices_bind_group"),
+    });
+    self.joint_buffer = Some(joint_buffer);
+    self.dirty = false;
+    bind_group
+  }
+
+  // Writes the joint matrices while a clip plays, or once after it stopped
+  pub fn upload(&mut self, queue: &wgpu::Queue, skin: &Skin) {
+    let Some(joint_buffer) = &self.joint_buffer else { return };
+    if self.clip.is_none() && !self.dirty {
+      return;
+    }
+    queue.write_buffer(joint_buffer, 0, bytemuck::cast_slice(&self.matrices(skin)));
+    self.dirty = false;
+  }
+
+  fn matrices(&self, skin: &Skin) -> Vec<[[f32; 4]; 4]> {
+    skin
+      .joint_matrices(&skin.pose(self.clip, self.time))
+      .into_iter()
+      .map(Into::into)
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn joint(parent: Option<usize>, translation: Vector3<f32>) -> Joint {
+    Joint {
+      name: String::new(),
+      parent,
+      rest: JointTransform { translation, ..JointTransform::IDENTITY },
+      inverse_bind: Matrix4::identity(),
+    }
+  }
+
+  fn slide(times: Vec<f32>, values: Vec<Vector3<f32>>) -> AnimationClip {
+    AnimationClip {
+      name: "slide".to_string(),
+      channels: vec![Channel {
+        joint: 0,
+        times,
+        keyframes: Keyframes::Translation(values),
+      }],
+    }
+  }
+
+  #[test]
+  fn channels_interpolate_and_hold_their_ends() {
+    let clip = slide(vec![1.0, 2.0], vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0)]);
+    let skin = Skin::new(vec![joint(None, Vector3::new(0.0, 5.0, 0.0))], vec![clip]).unwrap();
+    let translation_at = |time| skin.pose(Some(0), time)[0].translation;
+    assert_eq!(translation_at(0.0), Vector3::new(0.0, 0.0, 0.0));
+    assert_eq!(translation_at(1.5), Vector3::new(1.0, 0.0, 0.0));
+    assert_eq!(translation_at(3.0), Vector3::new(2.0, 0.0, 0.0));
+    // No clip leaves the joint at rest
+    assert_eq!(skin.pose(None, 1.5)[0].translation, Vector3::new(0.0, 5.0, 0.0));
+  }
+
+  #[test]
+  fn joint_matrices_follow_parents_and_undo_the_bind_pose() {
+    let mut joints = vec![joint(None, Vector3::new(1.0, 0.0, 0.0)), joint(Some(0), Vector3::new(0.0, 1.0, 0.0))];
+    let skin = Skin::new(joints.clone(), Vec::new()).unwrap();
+    let rest = skin.joint_matrices(&skin.pose(None, 0.0));
+    assert_eq!(rest[1], Matrix4::from_translation(Vector3::new(1.0, 1.0, 0.0)));
+
+    // Bound where they rest, the joints leave vertices in place
+    joints[0].inverse_bind = Matrix4::from_translation(Vector3::new(-1.0, 0.0, 0.0));
+    joints[1].inverse_bind = Matrix4::from_translation(Vector3::new(-1.0, -1.0, 0.0));
+    let skin = Skin::new(joints, Vec::new()).unwrap();
+    for matrix in skin.joint_matrices(&skin.pose(None, 0.0)) {
+      assert_eq!(matrix, Matrix4::identity());
+    }
+  }
+
+  #[test]
+  fn malformed_skins_are_an_error() {
+    let origin = Vector3::new(0.0, 0.0, 0.0);
+    assert!(Skin::new(Vec::new(), Vec::new()).is_err());
+    assert!(Skin::new(vec![joint(Some(1), origin), joint(None, origin)], Vec::new()).is_err());
+    assert!(Skin::new(vec![joint(None, origin)], vec![slide(vec![1.0, 0.0], vec![origin, origin])]).is_err());
+    assert!(Skin::new(vec![joint(None, origin)], vec![slide(vec![0.0], vec![origin, origin])]).is_err());
+  }
+
+  #[test]
+  fn main_shader_reads_the_skin() {
+    let source = shader_source(include_str!("../shaders/shader.wgsl")).unwrap();
+    let module = naga::front::wgsl::parse_str(&source).unwrap();
+    let vs_main = module.entry_points.iter().find(|entry| entry.name == "vs_main").unwrap();
+    // The unskinned vertex, the skin, then the instance
+    assert_eq!(vs_main.function.arguments.len(), 3);
+  }
+
+  #[test]
+  fn unrecognized_shaders_are_an_error() {
+    assert!(shader_source("@vertex\nfn vs_main(model: VertexInput) -> VertexOutput {}").is_err());
+  }
+}
@@ -1,89 +1,320 @@
-use std::{iter, sync::Arc};
+use std::{collections::HashMap, iter, sync::Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
 use cgmath::prelude::*;
 use wgpu::util::DeviceExt;
-use winit::{event::*, event_loop::ActiveEventLoop, keyboard::KeyCode, window::Window};
+use winit::{event::*, keyboard::KeyCode, window::Window};
 
 use crate::{
-  camera, instance, light, model, resources, texture, uniforms, pipeline_manager,
+  background, bounds, camera, compute_pipeline_manager, config, debug_lines, debug_view, diagnostics, fog, gpu_culling, gpu_timer, grid, input, instance, light, model, object_transforms, outline, render_context, render_graph, render_stats, resources, scene, shadow, skinning, texture, uniforms, pipeline, pipeline_manager, push_constants, tonemap, viewport, wireframe,
 };
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{benchmark, picking};
+#[cfg(feature = "egui")]
+use crate::egui_layer;
 use crate::model::Vertex;
-use crate::draw_traits::DrawMethod;
-use crate::renderable_object::RenderableObject;
+use crate::draw_traits::{DrawMethod, DrawWithMaterial, DrawWithoutMaterial};
+use crate::render_graph::{BuiltinPass, PassKind};
+use crate::renderable_object::{RenderableObject, VertexBufferBinding};
+
+const MAX_FIXED_STEPS_PER_FRAME: u32 = 8;
+// Name of the camera created by `new`, the only one the camera controller moves
+const FREE_CAMERA: &str = "free";
+// Every count wgpu can multisample with; which of them work depends on the adapter and format
+const SAMPLE_COUNTS: [u32; 5] = [1, 2, 4, 8, 16];
+const TRANSPARENT_PIPELINE: &str = "transparent_pipeline";
+// Lit like main_pipeline but biased toward the camera, for decals and markings
+// lying on other surfaces
+pub const DECAL_PIPELINE: &str = "decal_pipeline";
+const DECAL_DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState {
+  constant: -2,
+  slope_scale: -1.0,
+  clamp: 0.0,
+};
+
+type DeviceLostCallback = Arc<dyn Fn(wgpu::DeviceLostReason) + Send + Sync>;
+
+// What `State::try_recreate_device` couldn't carry over, because it was built
+// from the lost device's own resources
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LostResources {
+  // Indices, in the old state, of the objects whose model wasn't loaded from a file
+  pub objects: Vec<usize>,
+  // Objects that were kept, but without their extra vertex buffers and bind group
+  pub object_bindings: Vec<usize>,
+  // Added through `add_pipeline` with extra bind group layouts
+  pub pipelines: Vec<String>,
+  pub compute_pipelines: Vec<String>,
+}
+
+impl LostResources {
+  pub fn is_empty(&self) -> bool {
+    self.objects.is_empty()
+      && self.object_bindings.is_empty()
+      && self.pipelines.is_empty()
+      && self.compute_pipelines.is_empty()
+  }
+}
 
 pub struct State {
     pub window: Arc<Window>,
-    surface: wgpu::Surface<'static>,
+    // Kept so `try_recreate_device` can find a new adapter for the same surface
+    instance: wgpu::Instance,
+    // Only `None` while `try_recreate_device` moves it over to the new state
+    surface: Option<wgpu::Surface<'static>>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
+    supported_alpha_modes: Vec<wgpu::CompositeAlphaMode>,
+    // Kept to check format capabilities after creation, e.g. MSAA sample counts
+    adapter: wgpu::Adapter,
+    // Kept so `try_recreate_device` can request the same device again
+    renderer_config: config::RendererConfig,
+    // Set from wgpu's callback, possibly on another thread
+    device_lost: Arc<AtomicBool>,
+    device_lost_callback: Option<DeviceLostCallback>,
 
     pipeline_manager: pipeline_manager::PipelineManager,
+    // Name, shader source and options of each pipeline added through
+    // `add_pipeline`, to register again on a new device
+    user_pipelines: Vec<(String, String, pipeline::PipelineOptions)>,
+    compute_pipeline_manager: compute_pipeline_manager::ComputePipelineManager,
+    pending_dispatches: Vec<compute_pipeline_manager::Dispatch>,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    // For pipelines whose PipelineOptions::sampler_binding is NonFiltering
+    non_filtering_texture_bind_group_layout: wgpu::BindGroupLayout,
+    resource_cache: resources::ResourceCache,
 
     objects: Vec<RenderableObject>,
+    // Object indices grouped by pipeline so each pipeline is bound once per pass
+    draw_order: Vec<usize>,
 
     camera: camera::Camera,
     projection: camera::Projection,
     pub camera_controller: camera::CameraController,
+    // Inactive cameras by name; the active one lives in `camera`/`projection`
+    named_cameras: HashMap<String, (camera::Camera, camera::Projection)>,
+    active_camera: String,
     camera_uniform: uniforms::CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
 
-    light_uniform: light::LightUniform,
-    light_buffer: wgpu::Buffer,
-    light_bind_group: wgpu::BindGroup,
+    main_viewport: viewport::ViewportRect,
+    viewports: Vec<viewport::Viewport>,
 
-    depth_texture: texture::Texture,
+    background_pass: background::BackgroundPass,
+    // Off, the scene starts from the HDR target's previous contents instead
+    clear_scene: bool,
+    fog: fog::FogBuffer,
+    light: light::LightObject,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    shadow_map: shadow::ShadowMap,
+    light_animation: bool,
+    wireframe_overlay: bool,
+    // None where the wireframe shader can't read storage buffers
+    wireframe: Option<wireframe::WireframeBindings>,
+    // None where the vertex shader can't read the joint matrices of skinned models
+    skin_layout: Option<wgpu::BindGroupLayout>,
+    debug_view: debug_view::DebugView,
+    shadow_map_overlay: debug_view::ShadowMapOverlay,
+    depth_overlay: debug_view::DepthOverlay,
+    // Objects on none of these layers are skipped entirely
+    visible_layers: u32,
+    debug_lines: debug_lines::DebugLines,
+    line_style: debug_lines::LineStyle,
+    ground_grid: Option<grid::Grid>,
+    render_stats: render_stats::RenderStats,
+    render_graph: render_graph::RenderGraph,
+    // Created by the first `pick`
+    #[cfg(not(target_arch = "wasm32"))]
+    picker: Option<picking::Picker>,
+    indirect_supported: bool,
+    // Created with the first DrawMethod::GpuCulled object
+    gpu_culler: Option<gpu_culling::GpuCuller>,
+    outline_bind_group_layout: wgpu::BindGroupLayout,
+    outlines: Vec<outline::Outline>,
+    // One bundle of the static objects per view (main, then viewports); `None`
+    // until baked, and again whenever what they recorded goes stale
+    static_bundles: Option<Vec<wgpu::RenderBundle>>,
+    object_transforms: object_transforms::ObjectTransforms,
+
+    // `None` when RendererConfig::use_depth is off
+    depth_texture: Option<texture::Texture>,
+    // Cleared before the overlay objects of each view; only allocated while some
+    // object is on renderable_object::OVERLAY_LAYER
+    overlay_depth_texture: Option<texture::Texture>,
+    tonemap_pass: tonemap::ToneMapPass,
+    // Times the built-in passes; `None` without Features::TIMESTAMP_QUERY
+    frame_timer: Option<gpu_timer::GpuTimer>,
     is_surface_configured: bool,
+    paused: bool,
+    // Set on resume so the first update doesn't see the whole pause as one frame
+    skip_next_dt: bool,
+    fixed_timestep: Option<instant::Duration>,
+    accumulator: instant::Duration,
+    interpolation_alpha: f32,
+    #[cfg(feature = "egui")]
+    egui_layer: egui_layer::EguiLayer,
+    #[cfg(feature = "egui")]
+    ui: Option<egui_layer::UiCallback>,
 
     pub mouse_pressed: bool,
 }
 
+// Whether a surface configured at `current` (None if it isn't yet) has to be
+// reconfigured for `size`. Minimized windows report 0x0, which keeps the old
+// surface until a real size arrives, and compositors can fire a flood of
+// identical sizes during a drag
+fn needs_resize(current: Option<(u32, u32)>, (width, height): (u32, u32)) -> bool {
+  width > 0 && height > 0 && current != Some((width, height))
+}
+
 impl State {
-  pub async fn new(window: Arc<Window>) -> anyhow::Result<State> {
-    let size = window.inner_size();
+  // Walks RendererConfig::backends until one has an adapter that can present to
+  // the window
+  async fn request_adapter(
+    window: &Arc<Window>,
+    renderer_config: &config::RendererConfig,
+  ) -> anyhow::Result<(wgpu::Instance, wgpu::Surface<'static>, wgpu::Adapter)> {
+    for &backends in &renderer_config.backends {
+      // In a browser the first surface claims the canvas for its API, so a WebGPU
+      // one would leave nothing for a WebGL fallback; WebGPU is only tried when
+      // the browser can give out an adapter for it
+      let mut backends = backends;
+      if backends.contains(wgpu::Backends::BROWSER_WEBGPU) && !wgpu::util::is_browser_webgpu_supported().await {
+        backends.remove(wgpu::Backends::BROWSER_WEBGPU);
+        if backends.is_empty() {
+          log::warn!("WebGPU isn't supported here");
+          continue;
+        }
+      }
+      let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends,
+        ..Default::default()
+      });
+      let surface = match instance.create_surface(window.clone()) {
+        Ok(surface) => surface,
+        Err(e) => {
+          log::warn!("Can't create a {:?} surface: {}", backends, e);
+          continue;
+        }
+      };
+      let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+          power_preference: renderer_config.power_preference,
+          compatible_surface: Some(&surface),
+          force_fallback_adapter: false,
+        })
+        .await;
+      match adapter {
+        Ok(adapter) => {
+          let info = adapter.get_info();
+          log::info!("Using {:?} adapter {:?}", info.backend, info.name);
+          return Ok((instance, surface, adapter));
+        }
+        Err(e) => log::warn!("No {:?} adapter: {}", backends, e),
+      }
+    }
+    anyhow::bail!("no adapter on any of the backends {:?}", renderer_config.backends)
+  }
 
-    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-      #[cfg(not(target_arch = "wasm32"))]
-      backends: wgpu::Backends::PRIMARY,
-      #[cfg(target_arch = "wasm32")]
-      backends: wgpu::Backends::GL,
-      ..Default::default()
-    });
+  pub async fn new(window: Arc<Window>, renderer_config: config::RendererConfig) -> anyhow::Result<State> {
+    let (instance, surface, adapter) = Self::request_adapter(&window, &renderer_config).await?;
+    let (device, queue) = Self::request_device(&adapter, &renderer_config).await?;
+    Self::with_device(window, renderer_config, instance, &mut Some(surface), adapter, device, queue).await
+  }
 
-    let surface = instance.create_surface(window.clone()).unwrap();
+  async fn request_device(
+    adapter: &wgpu::Adapter,
+    renderer_config: &config::RendererConfig,
+  ) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let missing_features = renderer_config.required_features - adapter.features();
+    if !missing_features.is_empty() {
+      anyhow::bail!(
+        "adapter {:?} doesn't support the required features {:?}",
+        adapter.get_info().name,
+        missing_features,
+      );
+    }
 
-    let adapter = instance
-      .request_adapter(&wgpu::RequestAdapterOptions {
-        power_preference: wgpu::PowerPreference::default(),
-        compatible_surface: Some(&surface),
-        force_fallback_adapter: false,
-      })
-      .await
-      .unwrap();
+    // Push constants are optional; pipelines fall back to a uniform buffer without them
+    let push_constants_supported = adapter.features().contains(wgpu::Features::PUSH_CONSTANTS);
+    let mut required_features = renderer_config.required_features;
+    let mut required_limits = if adapter.get_info().backend == wgpu::Backend::Gl {
+      wgpu::Limits::downlevel_webgl2_defaults()
+    } else {
+      wgpu::Limits::default()
+    };
+    if push_constants_supported {
+      required_features |= wgpu::Features::PUSH_CONSTANTS;
+      required_limits.max_push_constant_size = push_constants::MAX_PUSH_CONSTANT_SIZE;
+    }
+    // Only needed for GPU timings, which are left out without it
+    required_features |= adapter.features() & wgpu::Features::TIMESTAMP_QUERY;
+    // Lets KTX2 textures in these formats load; others fail with a clear error
+    required_features |= adapter.features()
+      & (wgpu::Features::TEXTURE_COMPRESSION_BC
+        | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+        | wgpu::Features::TEXTURE_COMPRESSION_ASTC);
 
     let (device, queue) = adapter
       .request_device(&wgpu::DeviceDescriptor {
         label: None,
-        required_features: wgpu::Features::empty(),
+        required_features,
         experimental_features: wgpu::ExperimentalFeatures::disabled(),
-        required_limits: if cfg!(target_arch = "wasm32") {
-          wgpu::Limits::downlevel_webgl2_defaults()
-        } else {
-          wgpu::Limits::default()
-        },
+        required_limits,
         memory_hints: Default::default(),
         trace: wgpu::Trace::Off,
       })
       .await?;
+    Ok((device, queue))
+  }
 
-    let surface_caps = surface.get_capabilities(&adapter);
+  // Everything past getting a device. `surface` is only taken once nothing else
+  // can fail, so on error `try_recreate_device` still has it for a retry
+  async fn with_device(
+    window: Arc<Window>,
+    renderer_config: config::RendererConfig,
+    instance: wgpu::Instance,
+    surface: &mut Option<wgpu::Surface<'static>>,
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+  ) -> anyhow::Result<State> {
+    let Some(window_surface) = surface.as_ref() else {
+      anyhow::bail!("no surface to create the renderer for");
+    };
+    let size = window.inner_size();
+    // Missing on WebGL2, where DrawMethod::Indirect falls back to per-object draws
+    let indirect_supported = adapter
+      .get_downlevel_capabilities()
+      .flags
+      .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION);
+
+    let device_lost = Arc::new(AtomicBool::new(false));
+    Self::watch_device_loss(&device, &device_lost, None);
+
+    let surface_caps = window_surface.get_capabilities(&adapter);
     let surface_format = surface_caps
       .formats
       .iter()
       .copied()
       .find(|f| f.is_srgb())
       .unwrap_or(surface_caps.formats[0]);
+    let alpha_mode = match renderer_config.alpha_mode {
+      Some(mode) if surface_caps.alpha_modes.contains(&mode) => mode,
+      Some(mode) => {
+        log::warn!(
+          "Surface doesn't support alpha mode {:?}, using {:?}; supported: {:?}",
+          mode,
+          surface_caps.alpha_modes[0],
+          surface_caps.alpha_modes
+        );
+        surface_caps.alpha_modes[0]
+      }
+      None => surface_caps.alpha_modes[0],
+    };
 
     let config = wgpu::SurfaceConfiguration {
       usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -91,50 +322,16 @@ impl State {
       width: size.width,
       height: size.height,
       present_mode: surface_caps.present_modes[0],
-      alpha_mode: surface_caps.alpha_modes[0],
-      desired_maximum_frame_latency: 2,
+      alpha_mode,
+      desired_maximum_frame_latency: renderer_config.max_frame_latency.max(1),
       view_formats: vec![],
     };
 
-    surface.configure(&device, &config);
+    window_surface.configure(&device, &config);
 
-    let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-      entries: &[
-        wgpu::BindGroupLayoutEntry {
-          binding: 0,
-          visibility: wgpu::ShaderStages::FRAGMENT,
-          ty: wgpu::BindingType::Texture {
-            multisampled: false,
-            view_dimension: wgpu::TextureViewDimension::D2,
-            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-          },
-          count: None,
-        },
-        wgpu::BindGroupLayoutEntry {
-          binding: 1,
-          visibility: wgpu::ShaderStages::FRAGMENT,
-          ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-          count: None,
-        },
-        wgpu::BindGroupLayoutEntry {
-          binding: 2,
-          visibility: wgpu::ShaderStages::FRAGMENT,
-          ty: wgpu::BindingType::Texture {
-            multisampled: false,
-            view_dimension: wgpu::TextureViewDimension::D2,
-            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-          },
-          count: None,
-        },
-        wgpu::BindGroupLayoutEntry {
-          binding: 3,
-          visibility: wgpu::ShaderStages::FRAGMENT,
-          ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-          count: None,
-        },
-      ],
-      label: Some("texture_bind_group_layout"),
-    });
+    let texture_bind_group_layout = model::create_material_bind_group_layout(&device, wgpu::SamplerBindingType::Filtering);
+    let non_filtering_texture_bind_group_layout =
+      model::create_material_bind_group_layout(&device, wgpu::SamplerBindingType::NonFiltering);
 
     let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
       entries: &[
@@ -152,19 +349,8 @@ impl State {
       label: Some("camera_bind_group_layout"),
     });
 
-    let light_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-      entries: &[wgpu::BindGroupLayoutEntry {
-        binding: 0,
-        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-        ty: wgpu::BindingType::Buffer {
-          ty: wgpu::BufferBindingType::Uniform,
-          has_dynamic_offset: false,
-          min_binding_size: None,
-        },
-        count: None,
-      }],
-      label: Some("light_bind_group_layout"),
-    });
+    let light_bind_group_layout = light::create_bind_group_layout(&device);
+    let shadow_map = shadow::ShadowMap::new(&device, shadow::ShadowConfig::default(), renderer_config.vertex_format);
 
     let camera = camera::Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
     let projection = camera::Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
@@ -192,230 +378,2400 @@ impl State {
       label: Some("camera_bind_group"),
     });
 
-    let light_uniform = light::LightUniform::new(
-      [2.0, 2.0, 2.0], 
-      [1.0, 1.0, 1.0],
-    );
+    let depth_texture = renderer_config
+      .use_depth
+      .then(|| texture::Texture::create_depth_texture(&device, &config, "depth_texture"));
+    let tonemap_pass = tonemap::ToneMapPass::new(&device, config.format, config.width, config.height);
+    let shadow_map_overlay = debug_view::ShadowMapOverlay::new(&device, config.format, shadow_map.view());
+    let depth_overlay = debug_view::DepthOverlay::new(&device, config.format);
+    let background_pass = background::BackgroundPass::new(&device);
+    let frame_timer = gpu_timer::GpuTimer::new(&device, &queue);
 
-    let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-      label: Some("Light Buffer"),
-      contents: bytemuck::cast_slice(&[light_uniform]),
-      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-    });
+    let vertex_layout = renderer_config.vertex_format.layout();
+    let mut pipeline_manager = pipeline_manager::PipelineManager::new();
+    pipeline_manager.set_default_front_face(renderer_config.default_front_face);
+    pipeline_manager.set_use_depth(renderer_config.use_depth);
 
-    let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-      layout: &light_bind_group_layout,
-      entries: &[wgpu::BindGroupEntry {
-        binding: 0,
-        resource: light_buffer.as_entire_binding(),
-      }],
-      label: Some("light_bind_group"),
-    });
+    pipeline_manager.add_builtin_pipeline(
+      &device,
+      "main_pipeline".to_string(),
+      include_str!("../shaders/shader.wgsl"),
+      &[
+        &texture_bind_group_layout,
+        &camera_bind_group_layout,
+        &light_bind_group_layout,
+      ],
+      &[vertex_layout.clone(), instance::InstanceRaw::desc()],
+      tonemap::HDR_FORMAT,
+      pipeline::PipelineOptions::default(),
+    );
+
+    // Same shading as main_pipeline, with each object's transform read from a shared
+    // uniform buffer instead of its instance buffer
+    let object_transforms = object_transforms::ObjectTransforms::new(&device);
+    pipeline_manager.add_builtin_pipeline(
+      &device,
+      object_transforms::PIPELINE.to_string(),
+      &object_transforms::shader_source(include_str!("../shaders/shader.wgsl"))
+        .expect("shader.wgsl's vs_main takes the standard InstanceInput"),
+      &[
+        &texture_bind_group_layout,
+        &camera_bind_group_layout,
+        &light_bind_group_layout,
+        object_transforms.layout(),
+      ],
+      std::slice::from_ref(&vertex_layout),
+      tonemap::HDR_FORMAT,
+      pipeline::PipelineOptions::default(),
+    );
 
-    let depth_texture = texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+    // Same shading as main_pipeline, with vertices moved first by the joints of the
+    // object's skin; skinned models are routed here by `add_object`
+    let skin_layout = skinning::is_supported(&adapter, &device).then(|| skinning::create_bind_group_layout(&device));
+    if let Some(skin_layout) = &skin_layout {
+      pipeline_manager.add_builtin_pipeline(
+        &device,
+        skinning::PIPELINE.to_string(),
+        &skinning::shader_source(include_str!("../shaders/shader.wgsl"))
+          .expect("shader.wgsl's vs_main takes the standard VertexInput"),
+        &[
+          &texture_bind_group_layout,
+          &camera_bind_group_layout,
+          &light_bind_group_layout,
+          skin_layout,
+        ],
+        &[renderer_config.vertex_format.skinned_layout(), instance::InstanceRaw::desc()],
+        tonemap::HDR_FORMAT,
+        pipeline::PipelineOptions {
+          extra_bind_group_layouts: vec![skin_layout.clone()],
+          ..Default::default()
+        },
+      );
+    }
 
-    let mut pipeline_manager = pipeline_manager::PipelineManager::new();
+    // Same shading as main_pipeline, blended by the material's opacity; models
+    // with translucent materials are routed here by `add_object`. They're tested
+    // against the depth buffer but don't write to it, so one translucent surface
+    // doesn't cut another out
+    pipeline_manager.add_builtin_pipeline(
+      &device,
+      TRANSPARENT_PIPELINE.to_string(),
+      include_str!("../shaders/shader.wgsl"),
+      &[
+        &texture_bind_group_layout,
+        &camera_bind_group_layout,
+        &light_bind_group_layout,
+      ],
+      &[vertex_layout.clone(), instance::InstanceRaw::desc()],
+      tonemap::HDR_FORMAT,
+      pipeline::PipelineOptions {
+        blend: wgpu::BlendState::ALPHA_BLENDING,
+        depth_write_enabled: false,
+        ..Default::default()
+      },
+    );
 
-    pipeline_manager.add_pipeline(
+    pipeline_manager.add_builtin_pipeline(
       &device,
-      "main_pipeline".to_string(),
+      DECAL_PIPELINE.to_string(),
       include_str!("../shaders/shader.wgsl"),
       &[
         &texture_bind_group_layout,
         &camera_bind_group_layout,
         &light_bind_group_layout,
       ],
-      &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
-      config.format,
+      &[vertex_layout.clone(), instance::InstanceRaw::desc()],
+      tonemap::HDR_FORMAT,
+      pipeline::PipelineOptions {
+        depth_bias: DECAL_DEPTH_BIAS,
+        ..Default::default()
+      },
     );
 
-    pipeline_manager.add_pipeline(
+    pipeline_manager.add_builtin_pipeline(
       &device,
-      "light_pipeline".to_string(),
+      light::PIPELINE.to_string(),
       include_str!("../shaders/light.wgsl"),
       &[
         &camera_bind_group_layout,
         &light_bind_group_layout,
       ],
-      &[model::ModelVertex::desc()],
-      config.format,
+      &[vertex_layout.clone(), instance::InstanceRaw::desc()],
+      tonemap::HDR_FORMAT,
+      pipeline::PipelineOptions::default(),
+    );
+
+    // Untextured meshes lit purely from their vertex colors, drawn with DrawMethod::WithoutMaterial
+    pipeline_manager.add_builtin_pipeline(
+      &device,
+      "vertex_color_pipeline".to_string(),
+      include_str!("../shaders/vertex_color.wgsl"),
+      &[
+        &camera_bind_group_layout,
+        &light_bind_group_layout,
+      ],
+      &[vertex_layout.clone(), instance::InstanceRaw::desc()],
+      tonemap::HDR_FORMAT,
+      pipeline::PipelineOptions::default(),
     );
 
-    let obj_model = Arc::new(
-      resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
-        .await
-        .unwrap()
+    let mut resource_cache = resources::ResourceCache::with_options(resources::ModelLoadOptions {
+      vertex_format: renderer_config.vertex_format,
+      recompute_normals: renderer_config.recompute_normals,
+      flip_v: renderer_config.flip_v,
+      sampler_options: renderer_config.sampler_options,
+    });
+    // Laid out like main_pipeline so any loaded model can be drawn through them
+    for view in debug_view::DebugView::ALL {
+      pipeline_manager.add_builtin_pipeline(
+        &device,
+        view.pipeline_name().unwrap().to_string(),
+        &view.shader_source(),
+        &[
+          &texture_bind_group_layout,
+          &camera_bind_group_layout,
+          &light_bind_group_layout,
+        ],
+        &[vertex_layout.clone(), instance::InstanceRaw::desc()],
+        tonemap::HDR_FORMAT,
+        pipeline::PipelineOptions::default(),
+      );
+    }
+
+    let line_style = debug_lines::LineStyle::new(&device);
+    pipeline_manager.add_builtin_pipeline(
+      &device,
+      debug_lines::PIPELINE.to_string(),
+      concat!(include_str!("../shaders/line_quad.wgsl"), include_str!("../shaders/debug_lines.wgsl")),
+      &[&camera_bind_group_layout, line_style.layout()],
+      &[debug_lines::LineSegment::desc()],
+      tonemap::HDR_FORMAT,
+      pipeline::PipelineOptions::default(),
     );
 
-    let instances = instance::create_instances();
+    let wireframe = wireframe::is_supported(&adapter, &device).then(|| wireframe::WireframeBindings::new(&device));
+    if let Some(wireframe) = &wireframe {
+      pipeline_manager.add_builtin_pipeline(
+        &device,
+        wireframe::PIPELINE.to_string(),
+        concat!(include_str!("../shaders/line_quad.wgsl"), include_str!("../shaders/wireframe.wgsl")),
+        &[&camera_bind_group_layout, line_style.layout(), &wireframe.layout],
+        &[instance::InstanceRaw::desc()],
+        tonemap::HDR_FORMAT,
+        pipeline::PipelineOptions {
+          // Pull the lines in front of the filled triangles they sit on
+          depth_bias: wgpu::DepthBiasState {
+            constant: -2,
+            slope_scale: -1.0,
+            clamp: 0.0,
+          },
+          ..Default::default()
+        },
+      );
+    }
+
+    // Selection outlines: stamp the silhouette into the stencil, then draw a
+    // scaled shell wherever the stamp isn't
+    let outline_bind_group_layout = outline::create_bind_group_layout(&device);
+    pipeline_manager.add_builtin_pipeline(
+      &device,
+      outline::MASK_PIPELINE.to_string(),
+      include_str!("../shaders/outline_mask.wgsl"),
+      &[
+        &camera_bind_group_layout,
+        &light_bind_group_layout,
+      ],
+      &[vertex_layout.clone(), instance::InstanceRaw::desc()],
+      tonemap::HDR_FORMAT,
+      pipeline::PipelineOptions {
+        stencil: outline::MASK_STENCIL,
+        color_writes: wgpu::ColorWrites::empty(),
+        ..Default::default()
+      },
+    );
+    pipeline_manager.add_builtin_pipeline(
+      &device,
+      outline::SHELL_PIPELINE.to_string(),
+      include_str!("../shaders/outline.wgsl"),
+      &[
+        &camera_bind_group_layout,
+        &light_bind_group_layout,
+        &outline_bind_group_layout,
+      ],
+      &[vertex_layout.clone(), instance::InstanceRaw::desc()],
+      tonemap::HDR_FORMAT,
+      pipeline::PipelineOptions {
+        stencil: outline::SHELL_STENCIL,
+        ..Default::default()
+      },
+    );
+
+    let obj_model = resource_cache
+      .load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
+      .await?;
 
-    // Create light object with single instance
-    let light_instances = vec![instance::Instance {
-      position: cgmath::Vector3::new(0.0, 0.0, 0.0),
-      rotation: cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0)),
-    }];
+    let instances = instance::create_instances();
 
-    let mut objects = Vec::new();
-    
-    // Add main objects
-    objects.push(RenderableObject::new(
+    let objects = vec![RenderableObject::new(
       &device,
       obj_model.clone(),
       instances,
       None,
       DrawMethod::WithMaterial,
-    ));
-    
-    // Add light object using light_pipeline
-    objects.push(RenderableObject::new(
-      &device,
-      obj_model,
-      light_instances,
-      Some("light_pipeline".to_string()),
-      DrawMethod::WithoutMaterial,
-    ));
+    )];
+
+    let fog = fog::FogBuffer::new(&device);
+    let light = light::LightObject::new(&device, &light_bind_group_layout, &shadow_map, &fog.buffer, [2.0, 2.0, 2.0], [1.0, 1.0, 1.0])
+      .with_debug_mesh(&device, obj_model);
 
+    let debug_lines = debug_lines::DebugLines::new(&device);
+    #[cfg(feature = "egui")]
+    let egui_layer = egui_layer::EguiLayer::new(&device, config.format, &window);
 
-    Ok(Self {
+    let mut state = Self {
       window,
-      surface,
+      instance,
+      surface: surface.take(),
       device,
       queue,
       config,
+      supported_alpha_modes: surface_caps.alpha_modes,
+      adapter,
+      renderer_config,
+      device_lost,
+      device_lost_callback: None,
       pipeline_manager,
+      user_pipelines: Vec::new(),
+      compute_pipeline_manager: compute_pipeline_manager::ComputePipelineManager::new(),
+      pending_dispatches: Vec::new(),
+      texture_bind_group_layout,
+      non_filtering_texture_bind_group_layout,
+      resource_cache,
+      draw_order: Vec::new(),
       objects,
       camera,
       projection,
       camera_controller,
+      named_cameras: HashMap::new(),
+      active_camera: FREE_CAMERA.to_string(),
       camera_uniform,
       camera_buffer,
       camera_bind_group,
-      light_uniform,
-      light_buffer,
-      light_bind_group,
+      camera_bind_group_layout,
+      main_viewport: viewport::ViewportRect::FULL,
+      viewports: Vec::new(),
+      fog,
+      light,
+      light_bind_group_layout,
+      shadow_map,
+      light_animation: true,
+      wireframe_overlay: false,
+      wireframe,
+      skin_layout,
+      debug_view: debug_view::DebugView::None,
+      shadow_map_overlay,
+      depth_overlay,
+      visible_layers: u32::MAX,
+      debug_lines,
+      line_style,
+      ground_grid: None,
+      render_stats: render_stats::RenderStats::default(),
+      render_graph: render_graph::RenderGraph::default(),
+      background_pass,
+      clear_scene: true,
+      #[cfg(not(target_arch = "wasm32"))]
+      picker: None,
+      indirect_supported,
+      gpu_culler: None,
+      outline_bind_group_layout,
+      outlines: Vec::new(),
+      static_bundles: None,
+      object_transforms,
       depth_texture,
+      overlay_depth_texture: None,
+      tonemap_pass,
+      frame_timer,
       is_surface_configured: false,
+      paused: false,
+      skip_next_dt: false,
+      fixed_timestep: None,
+      accumulator: instant::Duration::ZERO,
+      interpolation_alpha: 1.0,
+      #[cfg(feature = "egui")]
+      egui_layer,
+      #[cfg(feature = "egui")]
+      ui: None,
       mouse_pressed: false,
-    })
+    };
+    state.rebuild_draw_order();
+
+    Ok(state)
   }
 
-  pub fn add_object(&mut self, model: Arc<model::Model>, instances: Vec<instance::Instance>, pipeline_name: Option<String>, draw_method: DrawMethod) {
-    self.objects.push(RenderableObject::new(
-      &self.device,
-      model,
-      instances,
-      pipeline_name,
-      draw_method,
-    ));
+  fn watch_device_loss(device: &wgpu::Device, flag: &Arc<AtomicBool>, callback: Option<DeviceLostCallback>) {
+    let flag = flag.clone();
+    device.set_device_lost_callback(move |reason, message| {
+      log::error!("GPU device lost ({:?}): {}", reason, message);
+      flag.store(true, Ordering::Release);
+      if let Some(callback) = &callback {
+        callback(reason);
+      }
+    });
   }
 
-  pub fn resize(&mut self, width: u32, height: u32) {
-    if width > 0 && height > 0 {
-      self.config.width = width;
-      self.config.height = height;
-      self.is_surface_configured = true;
-      self.projection.resize(self.config.width, self.config.height);
-      self.surface.configure(&self.device, &self.config);
-      self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-    }
+  // `callback` may run on any thread, so it should only signal the event loop,
+  // which can then call `try_recreate_device`
+  pub fn set_device_lost_callback(&mut self, callback: impl Fn(wgpu::DeviceLostReason) + Send + Sync + 'static) {
+    self.device_lost_callback = Some(Arc::new(callback));
+    Self::watch_device_loss(&self.device, &self.device_lost, self.device_lost_callback.clone());
   }
 
-  pub fn handle_key(&mut self, event_loop: &ActiveEventLoop, key: KeyCode, pressed: bool) {
-    if !self.camera_controller.handle_key(key, pressed) {
-      match (key, pressed) {
-        (KeyCode::Escape, true) => event_loop.exit(),
-        _ => {},
+  pub fn is_device_lost(&self) -> bool {
+    self.device_lost.load(Ordering::Acquire)
+  }
+
+  // Requests a new device for the same surface and rebuilds every GPU resource
+  // from CPU-side state: the scene's objects (reloaded through the resource
+  // cache), cameras, viewports, light, outlines, grid, renderer settings, custom
+  // render passes and pipelines added through `add_pipeline`. Anything built from
+  // the old device's own resources can't be, and is named in the returned
+  // LostResources. Objects whose model wasn't loaded from a file are dropped,
+  // along with the outlines and push constants of every object, since the
+  // remaining indices would shift. On error the old (lost) state is left in place
+  // so the call can be retried
+  pub async fn try_recreate_device(&mut self) -> anyhow::Result<LostResources> {
+    let adapter = self
+      .instance
+      .request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: self.renderer_config.power_preference,
+        compatible_surface: self.surface.as_ref(),
+        force_fallback_adapter: false,
+      })
+      .await?;
+    let (device, queue) = Self::request_device(&adapter, &self.renderer_config).await?;
+    let mut state = Self::with_device(
+      self.window.clone(),
+      self.renderer_config.clone(),
+      self.instance.clone(),
+      &mut self.surface,
+      adapter,
+      device,
+      queue,
+    )
+    .await?;
+    state.device_lost_callback = self.device_lost_callback.clone();
+    Self::watch_device_loss(&state.device, &state.device_lost, state.device_lost_callback.clone());
+
+    let scene = self.scene();
+    let keeps_indices = scene.objects.len() == self.objects.len();
+    state.set_depth_config(self.depth_config());
+    if let Err(e) = state.set_scene(&scene).await {
+      self.surface = state.surface.take();
+      return Err(e);
+    }
+
+    let mut lost = LostResources::default();
+    for (index, obj) in self.objects.iter().enumerate() {
+      if self.resource_cache.model_name(&obj.model).is_none() {
+        lost.objects.push(index);
+      }
+    }
+    for (name, shader_source, options) in &self.user_pipelines {
+      if !options.extra_bind_group_layouts.is_empty() {
+        lost.pipelines.push(name.clone());
+      } else if let Err(e) = state.add_pipeline(name.clone(), shader_source, options.clone()) {
+        log::warn!("Couldn't re-add pipeline {:?}: {:#}", name, e);
+        lost.pipelines.push(name.clone());
+      }
+    }
+    lost.compute_pipelines = self.compute_pipeline_manager.names().map(str::to_string).collect();
+    // Custom passes get the new device through FrameResources every frame
+    state.render_graph = std::mem::take(&mut self.render_graph);
+
+    state.camera = self.camera.clone();
+    state.projection = self.projection.clone();
+    state.active_camera = self.active_camera.clone();
+    state.named_cameras = std::mem::take(&mut self.named_cameras);
+    std::mem::swap(&mut state.camera_controller, &mut self.camera_controller);
+    state.main_viewport = self.main_viewport;
+    for old in &self.viewports {
+      let index = state.add_viewport(old.rect, old.camera.clone());
+      state.viewports[index].projection = old.projection.clone();
+    }
+
+    if keeps_indices {
+      for (index, (object, old)) in state.objects.iter_mut().zip(&self.objects).enumerate() {
+        if !old.vertex_buffers.is_empty() || !old.extra_bind_groups.is_empty() {
+          lost.object_bindings.push(index);
+        }
+        object.push_constants = old.push_constants.clone();
+        object.is_static = old.is_static;
+        object.layer = old.layer;
+        object.render_order = old.render_order;
+      }
+      state.rebuild_draw_order();
+      for outline in &self.outlines {
+        state.set_outline(outline.object, outline.color());
       }
     }
+    state.ground_grid = self.ground_grid.as_ref().map(|grid| grid.recreate(&state.device));
+
+    state.set_wireframe_overlay(self.wireframe_overlay);
+    state.line_style.set_width(self.line_width());
+    state.debug_view = self.debug_view;
+    state.visible_layers = self.visible_layers;
+    state.set_shadow_config(self.shadow_config());
+    state.light.casts_shadows = self.light.casts_shadows;
+    state.light.debug_mesh_visible = self.light.debug_mesh_visible;
+    state.set_background(self.background());
+    state.clear_scene = self.clear_scene;
+    state.fog.set_fog(&state.queue, self.fog());
+    state.set_tone_mapping(self.tone_mapping());
+    state.set_exposure(self.exposure());
+    state.set_max_frame_latency(self.max_frame_latency());
+    state.set_sample_count(self.sample_count());
+    state.set_msaa_resolve(self.msaa_resolve());
+    state.set_alpha_mode(self.alpha_mode());
+    state.paused = self.paused;
+    state.fixed_timestep = self.fixed_timestep;
+    state.mouse_pressed = self.mouse_pressed;
+    #[cfg(feature = "egui")]
+    {
+      state.ui = self.ui.take();
+    }
+
+    state.fit_active_projection();
+    state.write_camera_uniform();
+    state.reconfigure_surface();
+
+    // The old device may still report its own loss while it's dropped
+    self.device.set_device_lost_callback(|_, _| {});
+    *self = state;
+    if !lost.is_empty() {
+      log::warn!("Recreated the GPU device without these, which need adding again: {:?}", lost);
+    }
+    Ok(lost)
+  }
+
+  pub fn add_object(&mut self, model: Arc<model::Model>, instances: Vec<instance::Instance>, pipeline_name: Option<String>, draw_method: DrawMethod) -> anyhow::Result<usize> {
+    let obj = RenderableObject::new(&self.device, model, instances, pipeline_name, draw_method);
+    self.add_renderable(obj)
   }
 
-  pub fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool) {
-    match button {
-      MouseButton::Left => {
-        self.mouse_pressed = pressed;
+  // For objects built with `RenderableObject::new` and configured before insertion.
+  // Returns the index the other object methods take. Fails, adding nothing, if
+  // any of the model's meshes isn't in the renderer's vertex format, since every
+  // pipeline would read its vertices wrong
+  pub fn add_renderable(&mut self, mut obj: RenderableObject) -> anyhow::Result<usize> {
+    let vertex_format = self.renderer_config.vertex_format;
+    if let Some(mesh) = obj.model.meshes.iter().find(|mesh| mesh.vertex_format != vertex_format) {
+      anyhow::bail!(
+        "mesh {:?} is in the {:?} vertex format, but the renderer draws {:?}",
+        mesh.name,
+        mesh.vertex_format,
+        vertex_format,
+      );
+    }
+    if obj.model.needs_default_material() {
+      match Arc::get_mut(&mut obj.model) {
+        Some(model) => model.validate_materials(&self.device, &self.queue, &self.texture_bind_group_layout),
+        None => log::warn!(
+          "Model is shared, so it can't get a default material; meshes with out-of-range material indices won't draw",
+        ),
+      }
+    }
+    if obj.pipeline_name.is_none() && obj.model.skin.is_some() {
+      if self.skin_layout.is_some() {
+        obj.pipeline_name = Some(skinning::PIPELINE.to_string());
+      } else {
+        log::warn!("Skinning needs storage buffers in vertex shaders; the model will be drawn in its rest pose");
       }
-      _ => {}
     }
+    if obj.pipeline_name.is_none() && obj.model.is_transparent() {
+      obj.pipeline_name = Some(TRANSPARENT_PIPELINE.to_string());
+    }
+    if obj.draw_method == DrawMethod::GpuCulled && !self.gpu_culling_supported() {
+      log::warn!("GPU culling needs compute shaders and indirect draws, falling back to DrawMethod::Indirect");
+      obj.draw_method = DrawMethod::Indirect;
+      obj.cull_target = None;
+    }
+    if obj.draw_method == DrawMethod::Indirect && !self.indirect_supported {
+      log::warn!("Indirect draws aren't supported by this adapter, falling back to DrawMethod::WithMaterial");
+      obj.draw_method = DrawMethod::WithMaterial;
+    }
+    if obj.cull_target.is_some() && self.gpu_culler.is_none() {
+      self.gpu_culler = Some(gpu_culling::GpuCuller::new(&self.device));
+    }
+    let skinned = obj.pipeline_name.as_deref() == Some(skinning::PIPELINE);
+    if let (true, Some(skin), Some(pose), Some(layout)) = (skinned, &obj.model.skin, &mut obj.skin_pose, &self.skin_layout) {
+      let bind_group = pose.attach(&self.device, layout, skin);
+      obj.set_extra_bind_group(skinning::GROUP, bind_group);
+    }
+    let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+    let declared_groups = self.pipeline_manager.extra_bind_groups(pipeline_name);
+    if let Some((group, _)) = obj.extra_bind_groups.iter().find(|(group, _)| !declared_groups.contains(group)) {
+      anyhow::bail!(
+        "pipeline {:?} declares no extra bind group {}; its extra groups are {:?}",
+        pipeline_name,
+        group,
+        declared_groups,
+      );
+    }
+    self.check_vertex_buffers(&obj)?;
+    if !self.has_declared_bindings(&obj, pipeline_name) {
+      log::warn!(
+        "Object is missing vertex buffers or bind groups its pipeline {:?} declares, and won't be drawn until it has them",
+        pipeline_name,
+      );
+    }
+    self.objects.push(obj);
+    self.rebuild_draw_order();
+    Ok(self.objects.len() - 1)
   }
 
-  pub fn handle_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
-      self.camera_controller.handle_mouse_scroll(delta);
+  fn gpu_culling_supported(&self) -> bool {
+    self.indirect_supported
+      && self
+        .adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
   }
 
-  pub fn update(&mut self, dt: instant::Duration) {
-    self.camera_controller.update_camera(&mut self.camera, dt);
-    self.camera_uniform.update_view_proj(&self.camera, &self.projection);
-    self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+  // Slots 0 and 1 belong to the mesh and instance buffers
+  fn declares_vertex_buffer(&self, pipeline_name: &str, binding: &VertexBufferBinding) -> bool {
+    binding.slot >= 2 && self.pipeline_manager.vertex_step_mode(pipeline_name, binding.slot) == Some(binding.step_mode)
+  }
 
-    let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-    self.light_uniform.position = 
-      (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(60.0 * dt.as_secs_f32()))
-        * old_position)
-      .into();
-    self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light_uniform]));
+  // Extra buffers the object's pipeline doesn't declare would never be bound
+  fn check_vertex_buffers(&self, obj: &RenderableObject) -> anyhow::Result<()> {
+    let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+    if let Some(binding) = obj.vertex_buffers.iter().find(|binding| !self.declares_vertex_buffer(pipeline_name, binding)) {
+      anyhow::bail!(
+        "pipeline {:?} declares no {:?} vertex layout at slot {}",
+        pipeline_name,
+        binding.step_mode,
+        binding.slot,
+      );
+    }
+    Ok(())
   }
 
-  pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-    self.window.request_redraw();
+  // Adds or replaces the extra vertex buffer at `binding.slot`. Returns false if
+  // there's no such object or its pipeline declares no matching layout there
+  pub fn set_object_vertex_buffer(&mut self, index: usize, binding: VertexBufferBinding) -> bool {
+    let Some(obj) = self.objects.get(index) else { return false };
+    let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+    if !self.declares_vertex_buffer(pipeline_name, &binding) {
+      log::warn!("Pipeline {:?} declares no {:?} vertex layout at slot {}", pipeline_name, binding.step_mode, binding.slot);
+      return false;
+    }
+    self.objects[index].set_vertex_buffer(binding);
+    self.static_bundles = None;
+    true
+  }
 
-    if !self.is_surface_configured {
-      return Ok(());
+  // Adds or replaces the object's bind group at `group`. Returns false if there's
+  // no such object or its pipeline doesn't declare that group as an extra one
+  pub fn set_object_extra_bind_group(&mut self, index: usize, group: u32, bind_group: wgpu::BindGroup) -> bool {
+    let Some(obj) = self.objects.get(index) else { return false };
+    let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+    let declared_groups = self.pipeline_manager.extra_bind_groups(pipeline_name);
+    if !declared_groups.contains(&group) {
+      log::warn!("Pipeline {:?} declares no extra bind group {}; its extra groups are {:?}", pipeline_name, group, declared_groups);
+      return false;
     }
+    self.objects[index].set_extra_bind_group(group, bind_group);
+    self.static_bundles = None;
+    true
+  }
 
-    let output = self.surface.get_current_texture()?;
-    let view = output
-      .texture
-      .create_view(&wgpu::TextureViewDescriptor::default());
+  // Whether the object brings every vertex buffer and bind group its pipeline
+  // declares on top of the standard ones; drawing without would fail validation
+  fn has_declared_bindings(&self, obj: &RenderableObject, pipeline_name: &str) -> bool {
+    let has_vertex_buffer = |slot| {
+      let step_mode = self.pipeline_manager.vertex_step_mode(pipeline_name, slot);
+      obj.vertex_buffers.iter().any(|binding| binding.slot == slot && Some(binding.step_mode) == step_mode)
+    };
+    self.pipeline_manager.extra_vertex_slots(pipeline_name).all(has_vertex_buffer)
+      && self.pipeline_manager.extra_bind_groups(pipeline_name).all(|group| obj.has_extra_bind_group(group))
+  }
 
-    let mut encoder = self
-      .device
-      .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        label: Some("Render Encoder"),
-      });
+  fn rebuild_draw_order(&mut self) {
+    let objects = &self.objects;
+    let mut draw_order = (0..objects.len()).collect::<Vec<_>>();
+    // Stable, so objects sharing a pipeline keep their insertion order. Within a
+    // render order, blended objects go last so they composite over everything opaque
+    draw_order.sort_by_key(|&i| {
+      let pipeline_name = objects[i].pipeline_name.as_deref().unwrap_or("main_pipeline");
+      (objects[i].render_order, pipeline_name == TRANSPARENT_PIPELINE, pipeline_name)
+    });
+    self.draw_order = draw_order;
+    self.static_bundles = None;
+  }
 
-    {
-      let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-        label: Some("Render Pass"),
-        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-          view: &view,
-          resolve_target: None,
-          ops: wgpu::Operations {
-            load: wgpu::LoadOp::Clear(wgpu::Color {
-              r: 0.1,
-              g: 0.2,
-              b: 0.3,
-              a: 1.0,
-            }),
-            store: wgpu::StoreOp::Store,
-          },
-          depth_slice: None,
-        })],
-        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-          view: &self.depth_texture.view,
-          depth_ops: Some(wgpu::Operations {
-            load: wgpu::LoadOp::Clear(1.0),
-            store: wgpu::StoreOp::Store,
-          }),
-          stencil_ops: None,
-        }),
-        occlusion_query_set: None,
-        timestamp_writes: None,
-      });
+  // For extensions that create their own buffers, textures and pipelines
+  pub fn device(&self) -> &wgpu::Device {
+    &self.device
+  }
 
-      // Render all objects - draw method is encapsulated in the object
-      for obj in &self.objects {
-        let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
-        if let Some(pipeline) = self.pipeline_manager.get_by_name(pipeline_name) {
-          render_pass.set_pipeline(pipeline);
-          render_pass.set_vertex_buffer(1, obj.instance_buffer.slice(..));
-          obj.draw(&mut render_pass, &self.camera_bind_group, &self.light_bind_group);
-        }
-      }
+  pub fn queue(&self) -> &wgpu::Queue {
+    &self.queue
+  }
+
+  // Registers a pipeline drawn like main_pipeline: material, camera and light bind
+  // groups and instanced ModelVertex input, so `shader_source` should follow
+  // shader.wgsl's interface. `options` carries the per-pipeline state, e.g. a
+  // negative `depth_bias` for geometry coplanar with other surfaces, or layouts
+  // for per-object bind groups past the light's. An existing name is left as is
+  pub fn add_pipeline(
+    &mut self,
+    name: impl Into<String>,
+    shader_source: &str,
+    options: pipeline::PipelineOptions,
+  ) -> anyhow::Result<usize> {
+    let name = name.into();
+    let is_new = self.pipeline_manager.get_by_name(&name).is_none();
+    let texture_bind_group_layout = match options.sampler_binding {
+      wgpu::SamplerBindingType::NonFiltering => &self.non_filtering_texture_bind_group_layout,
+      _ => &self.texture_bind_group_layout,
+    };
+    // Extra groups and vertex buffers follow the standard ones
+    let extra_bind_group_layouts = options.extra_bind_group_layouts.clone();
+    let bind_group_layouts = [
+      texture_bind_group_layout,
+      &self.camera_bind_group_layout,
+      &self.light_bind_group_layout,
+    ]
+    .into_iter()
+    .chain(&extra_bind_group_layouts)
+    .collect::<Vec<_>>();
+    let vertex_layouts = [self.renderer_config.vertex_format.layout(), instance::InstanceRaw::desc()]
+      .into_iter()
+      .chain(options.extra_vertex_layouts.iter().cloned())
+      .collect::<Vec<_>>();
+    let index = self.pipeline_manager.add_pipeline(
+      &self.device,
+      name.clone(),
+      shader_source,
+      &bind_group_layouts,
+      &vertex_layouts,
+      tonemap::HDR_FORMAT,
+      options.clone(),
+    )?;
+    if is_new {
+      self.user_pipelines.push((name, shader_source.to_string(), options));
     }
+    Ok(index)
+  }
 
-    self.queue.submit(iter::once(encoder.finish()));
-    output.present();
+  pub fn add_compute_pipeline(
+    &mut self,
+    name: impl Into<String>,
+    shader_source: &str,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+  ) -> usize {
+    self.compute_pipeline_manager.add_compute_pipeline(&self.device, name.into(), shader_source, bind_group_layouts)
+  }
 
-    Ok(())
+  // Queues a compute dispatch for the start of the next `render`, before any render
+  // pass, so its output (e.g. an instance buffer written as storage) is ready to draw.
+  // `bind_groups` are bound in order from group 0
+  pub fn dispatch(&mut self, name: &str, x: u32, y: u32, z: u32, bind_groups: &[&wgpu::BindGroup]) {
+    if self.compute_pipeline_manager.get_by_name(name).is_none() {
+      log::warn!("No compute pipeline named {:?}", name);
+      return;
+    }
+    self.pending_dispatches.push(compute_pipeline_manager::Dispatch {
+      pipeline: name.to_string(),
+      workgroups: (x, y, z),
+      bind_groups: bind_groups.iter().map(|&bind_group| bind_group.clone()).collect(),
+    });
+  }
+
+  // Runs `pass` on the frame's encoder just before the pass named `anchor`, either
+  // a built-in one (render_graph::SHADOW_PASS etc.) or one added earlier. Returns
+  // false if `anchor` doesn't exist or `name` is taken
+  pub fn add_render_pass_before<F>(&mut self, anchor: &str, name: impl Into<String>, pass: F) -> bool
+  where
+    F: FnMut(&mut wgpu::CommandEncoder, &render_graph::FrameResources<'_>) + 'static,
+  {
+    self.render_graph.add_pass_before(anchor, name, Box::new(pass))
+  }
+
+  pub fn add_render_pass_after<F>(&mut self, anchor: &str, name: impl Into<String>, pass: F) -> bool
+  where
+    F: FnMut(&mut wgpu::CommandEncoder, &render_graph::FrameResources<'_>) + 'static,
+  {
+    self.render_graph.add_pass_after(anchor, name, Box::new(pass))
+  }
+
+  pub fn remove_render_pass(&mut self, name: &str) -> bool {
+    self.render_graph.remove_pass(name)
+  }
+
+  pub fn render_graph(&self) -> &render_graph::RenderGraph {
+    &self.render_graph
+  }
+
+  // Layout meshes need to be loaded with, e.g. through `load_model_with_format`
+  pub fn vertex_format(&self) -> model::VertexFormatOption {
+    self.renderer_config.vertex_format
+  }
+
+  // Layout that material bind groups (loaded or procedural) must be created with
+  pub fn texture_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+    &self.texture_bind_group_layout
+  }
+
+  // Layout matching a pipeline added with `sampler_binding` in its PipelineOptions.
+  // Materials for a NonFiltering pipeline need all-nearest samplers, e.g. from
+  // SamplerOptions::nearest; the default Filtering layout takes either kind
+  pub fn texture_bind_group_layout_for(&self, sampler_binding: wgpu::SamplerBindingType) -> &wgpu::BindGroupLayout {
+    match sampler_binding {
+      wgpu::SamplerBindingType::NonFiltering => &self.non_filtering_texture_bind_group_layout,
+      _ => &self.texture_bind_group_layout,
+    }
+  }
+
+  pub fn diagnostics(&self) -> diagnostics::RendererDiagnostics {
+    let adapter_info = self.adapter.get_info();
+    diagnostics::RendererDiagnostics {
+      adapter_name: adapter_info.name.clone(),
+      backend: adapter_info.backend,
+      device_type: adapter_info.device_type,
+      driver: adapter_info.driver.clone(),
+      driver_info: adapter_info.driver_info.clone(),
+      surface_format: self.config.format,
+      render_format: tonemap::HDR_FORMAT,
+      present_mode: self.config.present_mode,
+      alpha_mode: self.config.alpha_mode,
+      sample_count: self.pipeline_manager.sample_count(),
+      surface_size: (self.config.width, self.config.height),
+      features: self.device.features(),
+    }
+  }
+
+  // Features and limits the device was actually granted, which may be less than the adapter offers
+  pub fn features(&self) -> wgpu::Features {
+    self.device.features()
+  }
+
+  pub fn limits(&self) -> wgpu::Limits {
+    self.device.limits()
+  }
+
+  pub fn supports(&self, features: wgpu::Features) -> bool {
+    self.device.features().contains(features)
+  }
+
+  // Loads a model from the resource directory, reusing the GPU upload if it was loaded before
+  pub async fn load_model_cached(&mut self, file_name: &str) -> anyhow::Result<Arc<model::Model>> {
+    self.resource_cache
+      .load_model(file_name, &self.device, &self.queue, &self.texture_bind_group_layout)
+      .await
+  }
+
+  // Loads a texture from the resource directory, reusing the GPU upload if it was
+  // loaded before with the same sampler options, e.g. for `Material::new`
+  pub async fn load_texture_cached(
+    &mut self,
+    file_name: &str,
+    is_normal_map: bool,
+    sampler_options: texture::SamplerOptions,
+  ) -> anyhow::Result<Arc<texture::Texture>> {
+    self.resource_cache
+      .load_texture(file_name, is_normal_map, sampler_options, &self.device, &self.queue)
+      .await
+  }
+
+  pub fn clear_resource_cache(&mut self) {
+    self.resource_cache.clear();
+  }
+
+  // Objects whose model didn't come through the resource cache (procedural
+  // primitives) have no file to refer to and are left out
+  pub fn scene(&self) -> scene::Scene {
+    let objects = self.objects
+      .iter()
+      .filter_map(|obj| {
+        let Some(model) = self.resource_cache.model_name(&obj.model) else {
+          log::warn!("Leaving an object out of the scene, its model wasn't loaded from a file");
+          return None;
+        };
+        Some(scene::SceneObject {
+          model: model.to_string(),
+          pipeline: obj.pipeline_name.clone(),
+          draw_method: obj.draw_method,
+          instances: obj.instances.iter().map(scene::SceneInstance::from).collect(),
+        })
+      })
+      .collect();
+
+    scene::Scene {
+      objects,
+      camera: scene::SceneCamera {
+        position: self.camera.position.into(),
+        yaw: self.camera.yaw().0,
+        pitch: self.camera.pitch().0,
+        fovy: self.projection.fovy().0,
+        znear: self.projection.znear(),
+        zfar: self.projection.zfar(),
+      },
+      light: scene::SceneLight {
+        position: self.light.position(),
+        color: self.light.color(),
+        animated: self.light_animation,
+      },
+    }
+  }
+
+  // Writes `scene()` to `path` as JSON
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn save_scene(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+    self.scene().save(path)
+  }
+
+  // Reads a scene saved by `save_scene` and switches to it with `set_scene`
+  pub async fn load_scene(&mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+    let scene = scene::Scene::load(path).await?;
+    self.set_scene(&scene).await
+  }
+
+  // Replaces every object (and outline) with the scene's, loading models through the cache
+  pub async fn set_scene(&mut self, scene: &scene::Scene) -> anyhow::Result<()> {
+    let mut objects = Vec::with_capacity(scene.objects.len());
+    for object in &scene.objects {
+      let model = self.load_model_cached(&object.model).await?;
+      objects.push((
+        model,
+        object.instances.iter().map(instance::Instance::from).collect::<Vec<_>>(),
+        object.pipeline.clone(),
+        object.draw_method,
+      ));
+    }
+
+    self.objects.clear();
+    self.outlines.clear();
+    for (model, instances, pipeline_name, draw_method) in objects {
+      self.add_object(model, instances, pipeline_name, draw_method)?;
+    }
+
+    let (width, height) = self.main_viewport.pixel_size(self.config.width, self.config.height);
+    self.camera = camera::Camera::new(scene.camera.position, cgmath::Rad(scene.camera.yaw), cgmath::Rad(scene.camera.pitch));
+    self.projection = camera::Projection::new(width, height, cgmath::Rad(scene.camera.fovy), scene.camera.znear, scene.camera.zfar);
+    self.projection.set_reverse_z(self.pipeline_manager.depth_config().is_reversed());
+    self.write_camera_uniform();
+
+    self.light.set_position(&self.queue, scene.light.position);
+    self.light.set_color(&self.queue, scene.light.color);
+    self.light_animation = scene.light.animated;
+    Ok(())
+  }
+
+  pub fn set_object_push_constants<T: bytemuck::Pod>(&mut self, index: usize, data: &T) -> anyhow::Result<()> {
+    let Some(obj) = self.objects.get_mut(index) else {
+      anyhow::bail!("no object {index}");
+    };
+    obj.set_push_constants(data)?;
+    // Native push constants are recorded into the bundle
+    if obj.is_static {
+      self.static_bundles = None;
+    }
+    Ok(())
+  }
+
+  pub fn clear_object_push_constants(&mut self, index: usize) {
+    if let Some(obj) = self.objects.get_mut(index) {
+      obj.clear_push_constants();
+      if obj.is_static {
+        self.static_bundles = None;
+      }
+    }
+  }
+
+  // See RenderableObject::play_animation. Returns false for a missing object too
+  pub fn play_object_animation(&mut self, index: usize, name: &str) -> bool {
+    self.objects.get_mut(index).is_some_and(|obj| obj.play_animation(name))
+  }
+
+  pub fn stop_object_animation(&mut self, index: usize) {
+    if let Some(obj) = self.objects.get_mut(index) {
+      obj.stop_animation();
+    }
+  }
+
+  pub fn object_animation(&self, index: usize) -> Option<&str> {
+    self.objects.get(index)?.animation()
+  }
+
+  // Edit an instance in place, then upload it with `update_object_instance`
+  pub fn object_instance_mut(&mut self, object: usize, instance: usize) -> Option<&mut instance::Instance> {
+    self.objects.get_mut(object)?.instance_mut(instance)
+  }
+
+  // Uploads one instance changed through `object_instance_mut`
+  pub fn update_object_instance(&self, object: usize, instance: usize) -> bool {
+    self.objects
+      .get(object)
+      .is_some_and(|obj| obj.update_instance(&self.queue, instance))
+  }
+
+  // Static objects are drawn from a prerecorded render bundle; their instances
+  // can still be rewritten in place, but not resized. Blended objects are always
+  // drawn directly to keep them after everything opaque
+  pub fn set_object_static(&mut self, index: usize, is_static: bool) {
+    if let Some(obj) = self.objects.get_mut(index) {
+      if obj.is_static != is_static {
+        obj.is_static = is_static;
+        self.static_bundles = None;
+      }
+    }
+  }
+
+  pub fn set_object_layer(&mut self, index: usize, layer: u32) {
+    if let Some(obj) = self.objects.get_mut(index) {
+      if obj.layer != layer {
+        obj.layer = layer;
+        self.static_bundles = None;
+      }
+    }
+  }
+
+  // Lower orders draw first. Static objects all draw from their bundle ahead of
+  // the dynamic ones, so the order only holds among each of the two
+  pub fn set_object_render_order(&mut self, index: usize, render_order: i32) {
+    if let Some(obj) = self.objects.get_mut(index) {
+      if obj.render_order != render_order {
+        obj.render_order = render_order;
+        self.rebuild_draw_order();
+      }
+    }
+  }
+
+  pub fn visible_layers(&self) -> u32 {
+    self.visible_layers
+  }
+
+  // Only objects whose `layer` shares a bit with `mask` are drawn, cast shadows or
+  // can be picked; all layers are visible by default
+  pub fn set_visible_layers(&mut self, mask: u32) {
+    if self.visible_layers != mask {
+      self.visible_layers = mask;
+      self.static_bundles = None;
+    }
+  }
+
+  fn is_visible(&self, obj: &RenderableObject) -> bool {
+    obj.layer & self.visible_layers != 0
+  }
+
+  // Overlay objects draw in a pass of their own, which has no bundle
+  fn is_bundled(obj: &RenderableObject) -> bool {
+    obj.is_static && !obj.is_overlay() && obj.pipeline_name.as_deref() != Some(TRANSPARENT_PIPELINE)
+  }
+
+  // Records the static objects' draws for every view. `render` does this lazily
+  // whenever the bundles are stale, so calling it up front just avoids the hitch
+  pub fn bake_static_objects(&mut self) {
+    let depth_format = self.pipeline_manager.depth_config().format;
+    let use_depth = self.pipeline_manager.use_depth();
+    let views = iter::once(&self.camera_bind_group)
+      .chain(self.viewports.iter().map(|v| &v.camera_bind_group));
+
+    let bundles = views
+      .map(|camera_bind_group| {
+        let mut encoder = self.device.create_render_bundle_encoder(&wgpu::RenderBundleEncoderDescriptor {
+          label: Some("Static Objects Bundle"),
+          color_formats: &[Some(tonemap::HDR_FORMAT)],
+          depth_stencil: use_depth.then_some(wgpu::RenderBundleDepthStencil {
+            format: depth_format,
+            depth_read_only: false,
+            stencil_read_only: true,
+          }),
+          sample_count: self.pipeline_manager.sample_count(),
+          multiview: None,
+        });
+
+        for &i in &self.draw_order {
+          let obj = &self.objects[i];
+          if !Self::is_bundled(obj) || !self.is_visible(obj) {
+            continue;
+          }
+          let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+          let Some(pipeline) = self.pipeline_manager.get_by_name(pipeline_name) else { continue };
+          if !self.has_declared_bindings(obj, pipeline_name) {
+            continue;
+          }
+          encoder.set_pipeline(pipeline);
+          obj.bind_vertex_buffers(&mut encoder, self.pipeline_manager.instance_slot(pipeline_name), |slot| {
+            self.pipeline_manager.vertex_step_mode(pipeline_name, slot)
+          });
+          let push_constants = self.pipeline_manager.push_constant_binding(pipeline_name, i);
+          let transform = (pipeline_name == object_transforms::PIPELINE).then(|| self.object_transforms.binding(i));
+          obj.draw(&mut encoder, camera_bind_group, &self.light.bind_group, push_constants, transform);
+        }
+
+        encoder.finish(&wgpu::RenderBundleDescriptor {
+          label: Some("Static Objects Bundle"),
+        })
+      })
+      .collect();
+    self.static_bundles = Some(bundles);
+  }
+
+  // Registers a preset camera, replacing any inactive one with the same name
+  pub fn add_named_camera(&mut self, name: impl Into<String>, camera: camera::Camera, projection: camera::Projection) {
+    let name = name.into();
+    if name == self.active_camera {
+      self.camera = camera;
+      self.projection = projection;
+      self.fit_active_projection();
+      self.write_camera_uniform();
+    } else {
+      self.named_cameras.insert(name, (camera, projection));
+    }
+  }
+
+  // Swaps in the named camera, keeping the current one under its own name so it can
+  // be switched back to. Returns false if there's no camera with that name
+  pub fn activate_camera(&mut self, name: &str) -> bool {
+    if name == self.active_camera {
+      return true;
+    }
+    let Some((camera, projection)) = self.named_cameras.remove(name) else {
+      log::warn!("No camera named {:?}", name);
+      return false;
+    };
+
+    let previous_camera = std::mem::replace(&mut self.camera, camera);
+    let previous_projection = std::mem::replace(&mut self.projection, projection);
+    let previous_name = std::mem::replace(&mut self.active_camera, name.to_string());
+    self.named_cameras.insert(previous_name, (previous_camera, previous_projection));
+
+    self.fit_active_projection();
+    self.write_camera_uniform();
+    true
+  }
+
+  pub fn active_camera(&self) -> &str {
+    &self.active_camera
+  }
+
+  // Stored projections miss resizes and depth changes while inactive
+  fn fit_active_projection(&mut self) {
+    let (width, height) = self.main_viewport.pixel_size(self.config.width, self.config.height);
+    self.projection.resize(width, height);
+    self.projection.set_reverse_z(self.pipeline_manager.depth_config().is_reversed());
+  }
+
+  fn write_camera_uniform(&mut self) {
+    self.camera_uniform.update_view_proj(&self.camera, &self.projection);
+    self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+  }
+
+  // Applies to the active camera's projection; false if the planes were rejected
+  pub fn set_clip_planes(&mut self, znear: f32, zfar: f32) -> bool {
+    if !self.projection.set_clip_planes(znear, zfar) {
+      return false;
+    }
+    self.write_camera_uniform();
+    true
+  }
+
+  // Applies to the active camera's projection; `None` derives the aspect from the
+  // main viewport's size again. False if the aspect was rejected
+  pub fn set_aspect_override(&mut self, aspect: Option<f32>) -> bool {
+    if !self.projection.set_aspect_override(aspect) {
+      return false;
+    }
+    self.write_camera_uniform();
+    true
+  }
+
+  // Draws the scene again from `camera` over `rect`. DrawMethod::GpuCulled
+  // objects are culled against the main camera only, so the viewport shows
+  // just the instances the main view keeps
+  pub fn add_viewport(&mut self, rect: viewport::ViewportRect, camera: camera::Camera) -> usize {
+    let mut viewport = viewport::Viewport::new(
+      &self.device,
+      &self.camera_bind_group_layout,
+      rect,
+      camera,
+      self.projection.clone(),
+    );
+    viewport.resize(self.config.width, self.config.height);
+    self.viewports.push(viewport);
+    self.static_bundles = None;
+    self.viewports.len() - 1
+  }
+
+  pub fn viewport_camera_mut(&mut self, index: usize) -> Option<&mut camera::Camera> {
+    self.viewports.get_mut(index).map(|v| &mut v.camera)
+  }
+
+  pub fn set_main_viewport(&mut self, rect: viewport::ViewportRect) {
+    self.main_viewport = rect;
+    let (width, height) = rect.pixel_size(self.config.width, self.config.height);
+    self.projection.resize(width, height);
+  }
+
+  pub fn depth_config(&self) -> pipeline::DepthConfig {
+    self.pipeline_manager.depth_config()
+  }
+
+  // Rebuilds all pipelines and flips the projections when switching to/from reverse-Z
+  pub fn set_depth_config(&mut self, depth_config: pipeline::DepthConfig) {
+    let old_format = self.pipeline_manager.depth_config().format;
+    let format_changed = depth_config.format != old_format;
+    if format_changed {
+      // Settle on a count both formats can multisample before rebuilding for the new one
+      let sample_count = self.supported_sample_count(self.sample_count(), &[old_format, depth_config.format]);
+      self.apply_sample_count(sample_count);
+    }
+    self.pipeline_manager.set_depth_config(&self.device, depth_config);
+    self.static_bundles = None;
+    if format_changed {
+      self.recreate_depth_texture();
+    }
+    self.projection.set_reverse_z(depth_config.is_reversed());
+    for viewport in &mut self.viewports {
+      viewport.projection.set_reverse_z(depth_config.is_reversed());
+    }
+  }
+
+  // MSAA samples per pixel of the scene passes; 1 is off
+  pub fn sample_count(&self) -> u32 {
+    self.pipeline_manager.sample_count()
+  }
+
+  // Validated against what the adapter can multisample for the scene's color and
+  // depth formats: unsupported counts fall back to the nearest supported one, with
+  // a warning, and MSAA stays off where the HDR format can't be resolved. Returns
+  // the count actually used. Pipelines drawn from `render_with` callbacks have to
+  // be created with the same count
+  pub fn set_sample_count(&mut self, sample_count: u32) -> u32 {
+    let depth_format = self.pipeline_manager.depth_config().format;
+    let actual = self.supported_sample_count(sample_count, &[depth_format]);
+    if sample_count > 1 && !self.hdr_resolve_supported() {
+      log::warn!(
+        "Adapter can't resolve multisampled {:?} scene targets, so MSAA stays off",
+        tonemap::HDR_FORMAT,
+      );
+    } else if actual != sample_count {
+      log::warn!("Adapter can't render {} samples per pixel here, using {}", sample_count, actual);
+    }
+    self.apply_sample_count(actual);
+    actual
+  }
+
+  // Whether the scene passes resolve MSAA into the HDR target themselves (the
+  // default). Turned off, a custom pass between render_graph::SCENE_PASS and
+  // TONEMAP_PASS has to resolve FrameResources::msaa_view into `hdr_view`, e.g.
+  // for a TAA-style resolve; tone mapping otherwise shows stale contents. Has no
+  // effect without MSAA
+  pub fn msaa_resolve(&self) -> bool {
+    self.tonemap_pass.auto_resolve()
+  }
+
+  pub fn set_msaa_resolve(&mut self, enabled: bool) {
+    self.tonemap_pass.set_auto_resolve(enabled);
+  }
+
+  // Multisampled scene targets are resolved into the single-sampled HDR texture
+  // tone mapping reads, which some backends can't do for float formats
+  fn hdr_resolve_supported(&self) -> bool {
+    self
+      .adapter
+      .get_texture_format_features(tonemap::HDR_FORMAT)
+      .flags
+      .contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+  }
+
+  fn supported_sample_count(&self, requested: u32, depth_formats: &[wgpu::TextureFormat]) -> u32 {
+    let resolvable = self.hdr_resolve_supported();
+    let supported = |count: u32| {
+      (count == 1 || resolvable)
+        && iter::once(tonemap::HDR_FORMAT)
+          .chain(depth_formats.iter().copied().filter(|_| self.pipeline_manager.use_depth()))
+          .all(|format| self.adapter.get_texture_format_features(format).flags.sample_count_supported(count))
+    };
+    // Ties go to the lower count
+    SAMPLE_COUNTS
+      .into_iter()
+      .filter(|&count| supported(count))
+      .min_by_key(|&count| (count.abs_diff(requested), count))
+      .unwrap_or(1)
+  }
+
+  fn apply_sample_count(&mut self, sample_count: u32) {
+    if sample_count == self.sample_count() {
+      return;
+    }
+    self.pipeline_manager.set_sample_count(&self.device, sample_count);
+    self.tonemap_pass.set_sample_count(&self.device, sample_count);
+    self.background_pass.set_sample_count(&self.device, sample_count);
+    self.recreate_depth_texture();
+    self.static_bundles = None;
+  }
+
+  fn recreate_depth_texture(&mut self) {
+    self.depth_texture = self.pipeline_manager.use_depth().then(|| {
+      texture::Texture::create_depth_texture_with_format(
+        &self.device,
+        &self.config,
+        self.pipeline_manager.depth_config().format,
+        self.pipeline_manager.sample_count(),
+        "depth_texture",
+      )
+    });
+    // Recreated to match by the next frame that needs it
+    self.overlay_depth_texture = None;
+  }
+
+  fn has_overlay_objects(&self) -> bool {
+    self.objects.iter().any(|obj| obj.is_overlay() && self.is_visible(obj))
+  }
+
+  pub fn resize(&mut self, width: u32, height: u32) {
+    let current = self.is_surface_configured.then_some((self.config.width, self.config.height));
+    if !needs_resize(current, (width, height)) {
+      return;
+    }
+
+    self.config.width = width;
+    self.config.height = height;
+    self.is_surface_configured = true;
+    let (main_width, main_height) = self.main_viewport.pixel_size(width, height);
+    self.projection.resize(main_width, main_height);
+    for viewport in &mut self.viewports {
+      viewport.resize(width, height);
+    }
+    self.configure_surface();
+    self.recreate_depth_texture();
+    self.tonemap_pass.resize(&self.device, width, height);
+  }
+
+  pub fn view_matrix(&self) -> cgmath::Matrix4<f32> {
+    self.camera.calc_matrix()
+  }
+
+  pub fn projection_matrix(&self) -> cgmath::Matrix4<f32> {
+    self.projection.calc_matrix()
+  }
+
+  pub fn view_proj_matrix(&self) -> cgmath::Matrix4<f32> {
+    camera::view_proj_matrix(&self.camera, &self.projection)
+  }
+
+  // Pose of the active camera, e.g. for an audio listener or a light following it
+  pub fn camera_position(&self) -> cgmath::Point3<f32> {
+    self.camera.position
+  }
+
+  // Unit length, the direction the active camera looks in
+  pub fn camera_forward(&self) -> cgmath::Vector3<f32> {
+    self.camera.forward()
+  }
+
+  pub fn camera_yaw_pitch(&self) -> (cgmath::Rad<f32>, cgmath::Rad<f32>) {
+    (self.camera.yaw(), self.camera.pitch())
+  }
+
+  // Ray through window pixel (x, y) in the main view, as origin and direction; None
+  // if the view is empty or its matrices are degenerate
+  pub fn screen_to_world_ray(&self, x: f32, y: f32) -> Option<camera::Ray> {
+    // Pixel coordinates are relative to the window, the projection to the main viewport
+    let (vx, vy, _, _) = self.main_viewport.to_pixels(self.config.width, self.config.height);
+    let (width, height) = self.main_viewport.pixel_size(self.config.width, self.config.height);
+    camera::screen_to_world_ray(
+      self.view_proj_matrix(),
+      self.projection.ndc_depth_range(),
+      x - vx,
+      y - vy,
+      width,
+      height,
+    )
+  }
+
+  // Which object and instance is drawn at window pixel (x, y) in the main view,
+  // from a one-texel id render that blocks until the GPU reads it back. Objects
+  // positioned by their shader rather than an instance buffer can't be picked.
+  // Native only, since wasm can't block on the readback
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn pick(&mut self, x: f32, y: f32) -> Option<(usize, u32)> {
+    let (vx, vy, _, _) = self.main_viewport.to_pixels(self.config.width, self.config.height);
+    let (width, height) = self.main_viewport.pixel_size(self.config.width, self.config.height);
+    let (x, y) = (x - vx, y - vy);
+    if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+      return None;
+    }
+    let ndc = (
+      2.0 * (x.floor() + 0.5) / width as f32 - 1.0,
+      1.0 - 2.0 * (y.floor() + 0.5) / height as f32,
+    );
+
+    let view_proj = self.view_proj_matrix();
+    let reversed_depth = self.pipeline_manager.depth_config().is_reversed();
+    let pipeline_manager = &self.pipeline_manager;
+    let visible_layers = self.visible_layers;
+    let objects = self.objects.iter().enumerate().filter(|(_, obj)| {
+      let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+      obj.layer & visible_layers != 0 && pipeline_manager.uses_instances(pipeline_name)
+    });
+    self.picker
+      .get_or_insert_with(|| picking::Picker::new(&self.device, self.renderer_config.vertex_format))
+      .pick(&self.device, &self.queue, view_proj, ndc, (width, height), reversed_depth, objects)
+  }
+
+  pub fn max_frame_latency(&self) -> u32 {
+    self.config.desired_maximum_frame_latency
+  }
+
+  fn configure_surface(&self) {
+    if let Some(surface) = &self.surface {
+      surface.configure(&self.device, &self.config);
+    }
+  }
+
+  pub fn set_max_frame_latency(&mut self, frames: u32) {
+    self.config.desired_maximum_frame_latency = frames.max(1);
+    if self.is_surface_configured {
+      self.configure_surface();
+    }
+  }
+
+  pub fn alpha_mode(&self) -> wgpu::CompositeAlphaMode {
+    self.config.alpha_mode
+  }
+
+  pub fn supported_alpha_modes(&self) -> &[wgpu::CompositeAlphaMode] {
+    &self.supported_alpha_modes
+  }
+
+  // Returns false, leaving the current mode, if the surface doesn't support `mode`
+  pub fn set_alpha_mode(&mut self, mode: wgpu::CompositeAlphaMode) -> bool {
+    if !self.supported_alpha_modes.contains(&mode) {
+      log::warn!(
+        "Surface doesn't support alpha mode {:?}; supported: {:?}",
+        mode,
+        self.supported_alpha_modes
+      );
+      return false;
+    }
+    self.config.alpha_mode = mode;
+    if self.is_surface_configured {
+      self.configure_surface();
+    }
+    true
+  }
+
+  // Reconfigures even if the size is unchanged, e.g. after the surface was lost or outdated
+  pub fn reconfigure_surface(&mut self) {
+    let size = self.window.inner_size();
+    self.is_surface_configured = false;
+    self.resize(size.width, size.height);
+  }
+
+  pub fn scene_bounds(&self) -> Option<bounds::Aabb> {
+    self.objects
+      .iter()
+      .filter_map(RenderableObject::world_bounds)
+      .reduce(|a, b| a.union(&b))
+  }
+
+  // Backs the camera up along its current view direction until the whole scene fits
+  pub fn frame_all(&mut self) {
+    let Some(bounds) = self.scene_bounds() else {
+      return;
+    };
+    let distance = bounds.radius() / self.projection.min_half_fov().0.sin();
+    self.camera.position = bounds.center() - self.camera.forward() * distance;
+  }
+
+  // The input handlers never act on the app itself; it decides what to do with
+  // whatever comes back `Unhandled`
+  pub fn handle_key(&mut self, key: KeyCode, pressed: bool) -> input::InputAction {
+    if self.ui_wants_keyboard() {
+      return input::InputAction::Handled;
+    }
+    self.camera_controller.handle_key(key, pressed).into()
+  }
+
+  pub fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool) -> input::InputAction {
+    // Releases always go through, so a drag started over the scene still ends
+    if pressed && self.ui_wants_pointer() {
+      return input::InputAction::Handled;
+    }
+    if button == MouseButton::Left {
+      self.mouse_pressed = pressed;
+      return input::InputAction::Handled;
+    }
+    input::InputAction::Unhandled
+  }
+
+  pub fn handle_mouse_scroll(&mut self, delta: &MouseScrollDelta) -> input::InputAction {
+    if !self.ui_wants_pointer() {
+      self.camera_controller.handle_mouse_scroll(delta);
+    }
+    input::InputAction::Handled
+  }
+
+  // Passes a window event to the UI first. Returns true if the UI used it, in
+  // which case it shouldn't also reach the handlers above. Always false without
+  // the `egui` feature
+  #[allow(unused_variables)]
+  pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+    #[cfg(feature = "egui")]
+    if self.ui.is_some() {
+      let consumed = self.egui_layer.on_window_event(&self.window, event);
+      return consumed && !matches!(event, WindowEvent::MouseInput { state: ElementState::Released, .. });
+    }
+    false
+  }
+
+  fn ui_wants_pointer(&self) -> bool {
+    #[cfg(feature = "egui")]
+    if self.ui.is_some() {
+      return self.egui_layer.wants_pointer_input();
+    }
+    false
+  }
+
+  fn ui_wants_keyboard(&self) -> bool {
+    #[cfg(feature = "egui")]
+    if self.ui.is_some() {
+      return self.egui_layer.wants_keyboard_input();
+    }
+    false
+  }
+
+  // Called every rendered frame to build the UI, which is painted over the
+  // finished frame after tone mapping. Survives `try_recreate_device`, though
+  // egui's own state, like window positions, starts over
+  #[cfg(feature = "egui")]
+  pub fn set_ui<F>(&mut self, build_ui: F)
+  where
+    F: FnMut(&egui::Context) + 'static,
+  {
+    self.ui = Some(Box::new(build_ui));
+  }
+
+  #[cfg(feature = "egui")]
+  pub fn clear_ui(&mut self) {
+    self.ui = None;
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused
+  }
+
+  // While paused no redraws are requested, so the event loop can go idle
+  pub fn set_paused(&mut self, paused: bool) {
+    if self.paused && !paused {
+      self.skip_next_dt = true;
+      self.window.request_redraw();
+    }
+    self.paused = paused;
+  }
+
+  pub fn update(&mut self, dt: instant::Duration) {
+    if self.paused {
+      self.camera_controller.reset_mouse();
+      return;
+    }
+    let dt = if self.skip_next_dt {
+      self.skip_next_dt = false;
+      instant::Duration::ZERO
+    } else {
+      dt
+    };
+
+    match self.fixed_timestep {
+      Some(step) => {
+        self.accumulator += dt;
+        let mut steps = 0;
+        while self.accumulator >= step && steps < MAX_FIXED_STEPS_PER_FRAME {
+          self.step(step);
+          self.accumulator -= step;
+          steps += 1;
+        }
+        // Drop the backlog rather than spiral when frames take longer than the steps they owe
+        if steps == MAX_FIXED_STEPS_PER_FRAME {
+          self.accumulator = self.accumulator.min(step);
+        }
+        self.interpolation_alpha = self.accumulator.as_secs_f32() / step.as_secs_f32();
+      }
+      None => {
+        self.step(dt);
+        self.interpolation_alpha = 1.0;
+      }
+    }
+    // Frames without a fixed step drop their motion, as do frames on other cameras
+    self.camera_controller.reset_mouse();
+    for obj in &mut self.objects {
+      obj.upload_pose(&self.queue);
+    }
+
+    self.write_camera_uniform();
+    for viewport in &mut self.viewports {
+      viewport.update(&self.queue);
+    }
+  }
+
+  // Advances the simulation (camera movement, light animation) by `dt`
+  fn step(&mut self, dt: instant::Duration) {
+    if self.active_camera == FREE_CAMERA {
+      self.camera_controller.update_camera(&mut self.camera, dt);
+    }
+    for obj in &mut self.objects {
+      obj.advance_animation(dt.as_secs_f32());
+    }
+
+    if self.light_animation {
+      let old_position: cgmath::Vector3<_> = self.light.position().into();
+      let position =
+        cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(60.0 * dt.as_secs_f32()))
+          * old_position;
+      self.light.set_position(&self.queue, position.into());
+    }
+  }
+
+  // `None` goes back to stepping once per frame with the frame's dt
+  pub fn set_fixed_timestep(&mut self, step: Option<instant::Duration>) {
+    self.fixed_timestep = step.filter(|step| !step.is_zero());
+    self.accumulator = instant::Duration::ZERO;
+  }
+
+  // Fraction of a fixed step left in the accumulator, for blending the previous and
+  // current simulation states; always 1.0 without a fixed timestep
+  pub fn interpolation_alpha(&self) -> f32 {
+    self.interpolation_alpha
+  }
+
+  pub fn line_width(&self) -> f32 {
+    self.line_style.width()
+  }
+
+  // Width in pixels of debug lines, the ground grid and the wireframe overlay,
+  // 1.0 by default
+  pub fn set_line_width(&mut self, width: f32) {
+    if width.is_nan() || width <= 0.0 {
+      log::warn!("Line width must be positive, got {width}");
+      return;
+    }
+    self.line_style.set_width(width);
+  }
+
+  pub fn set_wireframe_overlay(&mut self, enabled: bool) {
+    let Some(wireframe) = &mut self.wireframe else {
+      if enabled {
+        log::warn!("Wireframe overlay needs storage buffers in vertex shaders, which this adapter doesn't support");
+      }
+      return;
+    };
+    if !enabled {
+      wireframe.clear();
+    }
+    self.wireframe_overlay = enabled;
+  }
+
+  pub fn tone_mapping(&self) -> tonemap::ToneMap {
+    self.tonemap_pass.tone_map()
+  }
+
+  pub fn set_tone_mapping(&mut self, tone_map: tonemap::ToneMap) {
+    self.tonemap_pass.set_tone_map(&self.queue, tone_map);
+  }
+
+  pub fn exposure(&self) -> f32 {
+    self.tonemap_pass.exposure()
+  }
+
+  // Scales the linear scene color before tone mapping
+  pub fn set_exposure(&mut self, exposure: f32) {
+    self.tonemap_pass.set_exposure(&self.queue, exposure);
+  }
+
+  pub fn render_stats(&self) -> render_stats::RenderStats {
+    self.render_stats
+  }
+
+  // GPU time of the built-in passes (shadow, scene and tone map) in a recent frame.
+  // Results arrive a few frames late, since reading them back never waits on the
+  // GPU; `None` until then, or without Features::TIMESTAMP_QUERY
+  pub fn last_gpu_frame_time_ms(&self) -> Option<f32> {
+    self.frame_timer.as_ref()?.last_frame_ms()
+  }
+
+  // Same, for one built-in pass by its render graph name; `None` for passes that
+  // aren't timed or didn't run in that frame
+  pub fn last_gpu_pass_time_ms(&self, name: &str) -> Option<f32> {
+    let span = gpu_timer::TIMED_PASSES.iter().position(|&pass| pass == name)?;
+    self.frame_timer.as_ref()?.last_pass_ms(span)
+  }
+
+  // Renders `frames` frames into an offscreen copy of the surface, so nothing waits
+  // on presentation or vsync, with the camera on a fixed orbit around the scene
+  // and each frame finished before the next starts. Objects, the light and the
+  // camera are left as they were; `update` isn't called in between. Native only,
+  // since wasm can't wait on the device and the timings would mean nothing
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn run_benchmark(&mut self, frames: u32) -> benchmark::BenchmarkReport {
+    if frames == 0 || !self.is_surface_configured {
+      log::warn!("Skipping the benchmark, there are no frames to render or no surface size yet");
+      return benchmark::BenchmarkReport::default();
+    }
+
+    let target = self.device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("Benchmark Target"),
+      size: wgpu::Extent3d {
+        width: self.config.width,
+        height: self.config.height,
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: self.config.format,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+      view_formats: &[],
+    });
+    let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let camera = self.camera.clone();
+    let bounds = self.scene_bounds();
+    if self.frame_timer.is_none() {
+      log::info!("Timestamp queries aren't supported, the benchmark only reports CPU frame times");
+    }
+
+    let mut cpu_samples = Vec::with_capacity(frames as usize);
+    let mut gpu_samples = Vec::with_capacity(frames as usize);
+    for frame in 0..frames {
+      if let Some(bounds) = &bounds {
+        self.camera = benchmark::orbit_camera(bounds, frame, frames, self.projection.min_half_fov());
+        self.write_camera_uniform();
+      }
+
+      let start = instant::Instant::now();
+      let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Benchmark Encoder"),
+      });
+      self.encode_frame(&mut encoder, &view, |_, _| {});
+      self.submit_frame(encoder);
+
+      match &mut self.frame_timer {
+        Some(timer) => {
+          timer.wait(&self.device);
+          gpu_samples.extend(timer.last_frame_ms());
+        }
+        None => {
+          if let Err(e) = self.device.poll(wgpu::PollType::wait_indefinitely()) {
+            log::warn!("Waiting for a benchmark frame failed: {}", e);
+          }
+        }
+      }
+      cpu_samples.push(start.elapsed().as_secs_f32() * 1000.0);
+    }
+
+    self.camera = camera;
+    self.write_camera_uniform();
+    benchmark::BenchmarkReport::new(frames, cpu_samples, gpu_samples)
+  }
+
+  // Lines queued here are drawn by the next `render` and then cleared
+  pub fn debug_lines_mut(&mut self) -> &mut debug_lines::DebugLines {
+    &mut self.debug_lines
+  }
+
+  // Replaces any existing grid; `size` is the full width, split into `divisions` cells per side
+  pub fn add_ground_grid(&mut self, size: f32, divisions: u32, color: [f32; 3]) {
+    self.ground_grid = Some(grid::Grid::new(&self.device, size, divisions, color));
+  }
+
+  pub fn set_ground_grid_visible(&mut self, visible: bool) {
+    if let Some(grid) = &mut self.ground_grid {
+      grid.visible = visible;
+    }
+  }
+
+  pub fn debug_view(&self) -> debug_view::DebugView {
+    self.debug_view
+  }
+
+  pub fn set_debug_view(&mut self, view: debug_view::DebugView) {
+    if view == debug_view::DebugView::Depth && !self.pipeline_manager.use_depth() {
+      log::warn!("The depth debug view shows nothing without a depth buffer");
+    }
+    self.debug_view = view;
+  }
+
+  // Highlights an object (by the index `add_object` returned) with a flat-colored
+  // outline; switches the depth buffer to a stencil format if needed
+  pub fn set_outline(&mut self, object: usize, color: [f32; 4]) {
+    if object >= self.objects.len() {
+      log::warn!("Can't outline object {}, there are only {}", object, self.objects.len());
+      return;
+    }
+    if !self.pipeline_manager.use_depth() {
+      log::warn!("Outlines need the depth buffer's stencil, which RendererConfig::use_depth turned off");
+      return;
+    }
+
+    let depth_config = self.pipeline_manager.depth_config();
+    if !depth_config.has_stencil() {
+      self.set_depth_config(pipeline::DepthConfig {
+        format: texture::Texture::DEPTH_STENCIL_FORMAT,
+        ..depth_config
+      });
+    }
+
+    match self.outlines.iter_mut().find(|o| o.object == object) {
+      Some(outline) => outline.set_color(&self.queue, color),
+      None => self.outlines.push(outline::Outline::new(
+        &self.device,
+        &self.outline_bind_group_layout,
+        object,
+        color,
+      )),
+    }
+  }
+
+  pub fn clear_outline(&mut self, object: usize) {
+    self.outlines.retain(|o| o.object != object);
+  }
+
+  pub fn shadow_config(&self) -> shadow::ShadowConfig {
+    self.shadow_map.config()
+  }
+
+  // Recreates the shadow map if the resolution changed, and its sampler if
+  // `hardware_filtering` did
+  pub fn set_shadow_config(&mut self, config: shadow::ShadowConfig) {
+    if self.shadow_map.set_config(&self.device, config) {
+      self.light.rebind(&self.device, &self.light_bind_group_layout, &self.shadow_map, &self.fog.buffer);
+      self.shadow_map_overlay.rebind(&self.device, self.shadow_map.view());
+    }
+  }
+
+  pub fn set_light_casts_shadows(&mut self, casts_shadows: bool) {
+    self.light.casts_shadows = casts_shadows;
+  }
+
+  pub fn light_debug_visible(&self) -> bool {
+    self.light.debug_mesh_visible
+  }
+
+  // Shows or hides the cube marking the light's position; the light itself keeps
+  // shining either way
+  pub fn set_light_debug_visible(&mut self, visible: bool) {
+    self.light.debug_mesh_visible = visible;
+  }
+
+  pub fn background(&self) -> background::Background {
+    self.background_pass.background()
+  }
+
+  pub fn set_background(&mut self, background: background::Background) {
+    self.background_pass.set_background(&self.queue, background);
+  }
+
+  pub fn clear_scene(&self) -> bool {
+    self.clear_scene
+  }
+
+  // On (the default), every frame starts from the background. Off, the scene pass
+  // loads the HDR target as it is and the background isn't drawn: the previous
+  // frame stays for accumulation effects, or a custom pass before
+  // render_graph::SCENE_PASS fills it
+  pub fn set_clear_scene(&mut self, clear: bool) {
+    self.clear_scene = clear;
+  }
+
+  pub fn fog(&self) -> Option<fog::Fog> {
+    self.fog.fog()
+  }
+
+  // Linear fog between `start` and `end` from the camera, in world units
+  pub fn set_fog(&mut self, color: [f32; 3], start: f32, end: f32) {
+    if !(start >= 0.0 && end > start) {
+      log::warn!("Fog range {}..{} is invalid; it needs 0 <= start < end", start, end);
+      return;
+    }
+    self.fog.set_fog(&self.queue, Some(fog::Fog::Linear { color, start, end }));
+  }
+
+  pub fn set_exponential_fog(&mut self, color: [f32; 3], density: f32) {
+    if density.is_nan() || density <= 0.0 {
+      log::warn!("Fog density {} is invalid; it needs to be positive", density);
+      return;
+    }
+    self.fog.set_fog(&self.queue, Some(fog::Fog::Exponential { color, density }));
+  }
+
+  pub fn disable_fog(&mut self) {
+    self.fog.set_fog(&self.queue, None);
+  }
+
+  pub fn set_light_animation(&mut self, enabled: bool) {
+    self.light_animation = enabled;
+  }
+
+  pub fn set_light_position<P: Into<[f32; 3]>>(&mut self, position: P) {
+    self.light.set_position(&self.queue, position.into());
+  }
+
+  // Asks the window for another RedrawRequested event. `render` only does the
+  // frame's GPU work, so a host that redraws continuously calls this after each
+  // frame, and one driving its own loop (or rendering offscreen) never has to
+  pub fn request_redraw(&self) {
+    self.window.request_redraw();
+  }
+
+  pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+    self.render_with(|_, _| {})
+  }
+
+  // Like `render`, but calls `f` inside the main view's pass after the built-in
+  // objects are drawn, so custom draws share its color and depth targets
+  pub fn render_with<F>(&mut self, f: F) -> Result<(), wgpu::SurfaceError>
+  where
+    F: FnOnce(&mut wgpu::RenderPass<'_>, &render_context::RenderContext<'_>),
+  {
+    let result = self.render_frame(f);
+    self.debug_lines.clear();
+    result
+  }
+
+  fn render_frame<F>(&mut self, f: F) -> Result<(), wgpu::SurfaceError>
+  where
+    F: FnOnce(&mut wgpu::RenderPass<'_>, &render_context::RenderContext<'_>),
+  {
+    if self.paused {
+      return Ok(());
+    }
+
+    let Some(surface) = self.surface.as_ref().filter(|_| self.is_surface_configured) else {
+      return Ok(());
+    };
+
+    let output = surface.get_current_texture()?;
+    let view = output
+      .texture
+      .create_view(&wgpu::TextureViewDescriptor::default());
+
+    let mut encoder = self
+      .device
+      .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Render Encoder"),
+      });
+    self.encode_frame(&mut encoder, &view, f);
+    #[cfg(feature = "egui")]
+    if let Some(build_ui) = &mut self.ui {
+      let callbacks = self.egui_layer.paint(
+        &self.device,
+        &self.queue,
+        &mut encoder,
+        &view,
+        &self.window,
+        (self.config.width, self.config.height),
+        build_ui,
+      );
+      if !callbacks.is_empty() {
+        self.queue.submit(callbacks);
+      }
+    }
+    self.submit_frame(encoder);
+    output.present();
+
+    Ok(())
+  }
+
+  // Submits an encoder filled by `encode_frame`, resolving its timestamps with it
+  fn submit_frame(&mut self, mut encoder: wgpu::CommandEncoder) {
+    if let Some(timer) = &mut self.frame_timer {
+      timer.resolve(&mut encoder);
+    }
+    self.queue.submit(iter::once(encoder.finish()));
+    if let Some(timer) = &mut self.frame_timer {
+      timer.after_submit();
+    }
+  }
+
+  // Everything `render` does short of acquiring and presenting the surface, with
+  // `view` standing in for it
+  fn encode_frame<F>(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, f: F)
+  where
+    F: FnOnce(&mut wgpu::RenderPass<'_>, &render_context::RenderContext<'_>),
+  {
+    if let Some(timer) = &mut self.frame_timer {
+      timer.begin_frame(&self.device);
+    }
+
+    if let Some(fallback) = self.pipeline_manager.push_constant_fallback_mut() {
+      fallback.write(
+        &self.device,
+        &self.queue,
+        self.objects.iter().map(|obj| obj.push_constants.as_deref().unwrap_or(&[])),
+      );
+    }
+
+    if self.objects.iter().any(|obj| obj.pipeline_name.as_deref() == Some(object_transforms::PIPELINE)) {
+      self.object_transforms.write(
+        &self.device,
+        &self.queue,
+        self.objects.iter().map(|obj| obj.instances.first()),
+      );
+    }
+
+    self.debug_lines.upload(&self.device, &self.queue);
+    let (width, height) = (self.config.width, self.config.height);
+    let view_sizes = iter::once(self.main_viewport)
+      .chain(self.viewports.iter().map(|v| v.rect))
+      .map(|rect| {
+        let (_, _, w, h) = rect.to_pixels(width, height);
+        (w, h)
+      })
+      .collect::<Vec<_>>();
+    self.line_style.write(&self.device, &self.queue, view_sizes.into_iter());
+    if let Some(wireframe) = self.wireframe.as_mut().filter(|_| self.wireframe_overlay) {
+      wireframe.prepare(&self.device, self.objects.iter().map(|obj| &obj.model));
+    }
+
+    if self.overlay_depth_texture.is_none() && self.pipeline_manager.use_depth() && self.has_overlay_objects() {
+      self.overlay_depth_texture = Some(texture::Texture::create_depth_texture_with_format(
+        &self.device,
+        &self.config,
+        self.pipeline_manager.depth_config().format,
+        self.pipeline_manager.sample_count(),
+        "overlay_depth_texture",
+      ));
+    }
+
+    // After the uploads above, so buffers they regrow are picked up
+    if self.static_bundles.is_none() && self.objects.iter().any(Self::is_bundled) {
+      self.bake_static_objects();
+    }
+
+    let mut custom_draw = Some(f);
+    let mut stats = render_stats::RenderStats::default();
+
+    // Taken out for the frame so custom passes can borrow the rest of the state
+    let mut graph = std::mem::take(&mut self.render_graph);
+    for pass in graph.passes_mut() {
+      match &mut pass.kind {
+        PassKind::Builtin(BuiltinPass::Compute) => {
+          self.encode_culling(encoder);
+          self.encode_dispatches(encoder);
+        }
+        PassKind::Builtin(BuiltinPass::Shadow) => self.encode_shadow_pass(encoder, &mut stats),
+        PassKind::Builtin(BuiltinPass::Background) if self.clear_scene => {
+          self.background_pass.run(encoder, self.tonemap_pass.scene_target().0)
+        }
+        PassKind::Builtin(BuiltinPass::Background) => {}
+        PassKind::Builtin(BuiltinPass::Scene) => self.encode_scene_passes(encoder, &mut custom_draw, &mut stats),
+        PassKind::Builtin(BuiltinPass::ToneMap) => {
+          let timestamp_writes = self
+            .frame_timer
+            .as_ref()
+            .and_then(|timer| timer.render_pass_writes(gpu_timer::TONEMAP_SPAN, true, true));
+          self.tonemap_pass.run(encoder, view, timestamp_writes);
+          match (self.debug_view, &self.depth_texture) {
+            (debug_view::DebugView::ShadowMap, _) => {
+              self.shadow_map_overlay.run(encoder, view, (self.config.width, self.config.height))
+            }
+            (debug_view::DebugView::Depth, Some(depth)) => {
+              // Every view's pass clears the depth buffer, so only the last one's is left
+              let (rect, projection) = match self.viewports.last() {
+                Some(viewport) => (viewport.rect, &viewport.projection),
+                None => (self.main_viewport, &self.projection),
+              };
+              let rect = rect.to_pixels(self.config.width, self.config.height);
+              self.depth_overlay.run(&self.device, &self.queue, encoder, view, &depth.texture, rect, projection)
+            }
+            _ => {}
+          }
+        }
+        PassKind::Custom(pass_fn) => {
+          let resources = render_graph::FrameResources {
+            device: &self.device,
+            queue: &self.queue,
+            surface_view: view,
+            surface_format: self.config.format,
+            hdr_view: self.tonemap_pass.hdr_view(),
+            msaa_view: self.tonemap_pass.msaa_view(),
+            sample_count: self.pipeline_manager.sample_count(),
+            depth_view: self.depth_texture.as_ref().map(|depth| &depth.view),
+            shadow_map_view: self.shadow_map.view(),
+            camera_bind_group: &self.camera_bind_group,
+            light_bind_group: &self.light.bind_group,
+            pipeline_manager: &self.pipeline_manager,
+            width: self.config.width,
+            height: self.config.height,
+          };
+          pass_fn(encoder, &resources);
+        }
+      }
+    }
+    self.render_graph = graph;
+    self.render_stats = stats;
+  }
+
+  // Culls DrawMethod::GpuCulled objects against the main camera, ahead of every
+  // pass that draws them; secondary viewports reuse the result
+  fn encode_culling(&mut self, encoder: &mut wgpu::CommandEncoder) {
+    let Some(culler) = &self.gpu_culler else { return };
+    let view_proj = camera::view_proj_matrix(&self.camera, &self.projection);
+    let visible_layers = self.visible_layers;
+    let objects = self
+      .objects
+      .iter_mut()
+      .filter(|obj| obj.layer & visible_layers != 0)
+      .filter_map(|obj| {
+        let RenderableObject { instance_buffer, indirect_buffer, model, cull_target, .. } = obj;
+        Some((&*instance_buffer, indirect_buffer.as_ref()?, model.meshes.len(), cull_target.as_mut()?))
+      });
+    culler.encode(&self.device, &self.queue, encoder, view_proj, objects);
+  }
+
+  fn encode_dispatches(&mut self, encoder: &mut wgpu::CommandEncoder) {
+    if self.pending_dispatches.is_empty() {
+      return;
+    }
+    let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+      label: Some("Compute Pass"),
+      timestamp_writes: None,
+    });
+    for dispatch in self.pending_dispatches.drain(..) {
+      if let Some(pipeline) = self.compute_pipeline_manager.get_by_name(&dispatch.pipeline) {
+        dispatch.encode(&mut compute_pass, pipeline);
+      }
+    }
+  }
+
+  fn encode_shadow_pass(&mut self, encoder: &mut wgpu::CommandEncoder, stats: &mut render_stats::RenderStats) {
+    let casts_shadows = self.light.casts_shadows;
+    let shadow_bounds = if casts_shadows { self.scene_bounds() } else { None };
+    self.shadow_map.update(&self.queue, self.light.position(), shadow_bounds, casts_shadows);
+    if !casts_shadows {
+      return;
+    }
+    // Blended objects and ones without instance buffers don't cast shadows
+    let casters = self.objects.iter().filter(|obj| {
+      if !self.is_visible(obj) {
+        return false;
+      }
+      let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+      pipeline_name != TRANSPARENT_PIPELINE && self.pipeline_manager.uses_instances(pipeline_name)
+    });
+    let timestamp_writes = self
+      .frame_timer
+      .as_ref()
+      .and_then(|timer| timer.render_pass_writes(gpu_timer::SHADOW_SPAN, true, true));
+    self.shadow_map.render(encoder, casters, stats, timestamp_writes);
+  }
+
+  fn encode_scene_passes<F>(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    custom_draw: &mut Option<F>,
+    stats: &mut render_stats::RenderStats,
+  ) where
+    F: FnOnce(&mut wgpu::RenderPass<'_>, &render_context::RenderContext<'_>),
+  {
+    let depth_config = self.pipeline_manager.depth_config();
+
+    // Each view gets its own pass so overlapping viewports start from a cleared depth buffer
+    let views = iter::once((self.main_viewport, &self.camera_bind_group))
+      .chain(self.viewports.iter().map(|v| (v.rect, &v.camera_bind_group)));
+
+    let view_count = 1 + self.viewports.len();
+    let (color_view, resolve_target) = self.tonemap_pass.scene_target();
+    let has_overlay = self.has_overlay_objects();
+    for (i, (rect, camera_bind_group)) in views.enumerate() {
+      // Gradient backgrounds were already drawn by their own pass
+      let color_load = match self.background_pass.clear_color() {
+        Some(color) if i == 0 && self.clear_scene => wgpu::LoadOp::Clear(color),
+        _ => wgpu::LoadOp::Load,
+      };
+      let last_view = i == view_count - 1;
+
+      // Timed from the start of the first view to the end of the last, overlay included
+      let mut render_pass = self.begin_scene_pass(
+        encoder,
+        "Render Pass",
+        color_view,
+        resolve_target,
+        color_load,
+        self.depth_texture.as_ref().map(|depth| &depth.view),
+        (i == 0, last_view && !has_overlay),
+      );
+
+      let (x, y, width, height) = rect.to_pixels(self.config.width, self.config.height);
+      if width <= 0.0 || height <= 0.0 {
+        continue;
+      }
+      render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+
+      let static_bundle = self.static_bundles.as_ref().and_then(|bundles| bundles.get(i));
+      self.draw_objects(&mut render_pass, camera_bind_group, static_bundle, false, self.line_style.binding(i), stats);
+      if self.pipeline_manager.use_depth() && depth_config.has_stencil() {
+        self.draw_outlines(&mut render_pass, camera_bind_group, stats);
+      }
+      if let Some(pipeline) = self.pipeline_manager.get_by_name(debug_lines::PIPELINE) {
+        if let Some(grid) = &self.ground_grid {
+          if grid.draw(&mut render_pass, pipeline, camera_bind_group, self.line_style.binding(i)) {
+            stats.draw_calls += 1;
+          }
+        }
+        if self.debug_lines.draw(&mut render_pass, pipeline, camera_bind_group, self.line_style.binding(i)) {
+          stats.draw_calls += 1;
+        }
+      }
+
+      if let Some(f) = custom_draw.take() {
+        let context = render_context::RenderContext {
+          camera_bind_group,
+          light_bind_group: &self.light.bind_group,
+          pipeline_manager: &self.pipeline_manager,
+        };
+        f(&mut render_pass, &context);
+      }
+      drop(render_pass);
+
+      // Without a depth buffer the overlay only relies on being drawn last
+      if has_overlay {
+        let mut render_pass = self.begin_scene_pass(
+          encoder,
+          "Overlay Pass",
+          color_view,
+          resolve_target,
+          wgpu::LoadOp::Load,
+          self.overlay_depth_texture.as_ref().map(|depth| &depth.view),
+          (false, last_view),
+        );
+        render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        self.draw_objects(&mut render_pass, camera_bind_group, None, true, self.line_style.binding(i), stats);
+      }
+    }
+  }
+
+  // A scene or overlay pass drawing into the HDR target with `depth_view` (if
+  // there's a depth buffer) cleared.
+  // `timestamps` says whether it begins and/or ends the timed scene span
+  #[allow(clippy::too_many_arguments)]
+  fn begin_scene_pass<'a>(
+    &self,
+    encoder: &'a mut wgpu::CommandEncoder,
+    label: &str,
+    color_view: &wgpu::TextureView,
+    resolve_target: Option<&wgpu::TextureView>,
+    color_load: wgpu::LoadOp<wgpu::Color>,
+    depth_view: Option<&wgpu::TextureView>,
+    (begin, end): (bool, bool),
+  ) -> wgpu::RenderPass<'a> {
+    let depth_config = self.pipeline_manager.depth_config();
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some(label),
+      color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+        view: color_view,
+        resolve_target,
+        ops: wgpu::Operations {
+          load: color_load,
+          store: wgpu::StoreOp::Store,
+        },
+        depth_slice: None,
+      })],
+      depth_stencil_attachment: depth_view.map(|view| wgpu::RenderPassDepthStencilAttachment {
+        view,
+        depth_ops: Some(wgpu::Operations {
+          load: wgpu::LoadOp::Clear(depth_config.clear_value),
+          store: wgpu::StoreOp::Store,
+        }),
+        stencil_ops: depth_config.has_stencil().then_some(wgpu::Operations {
+          load: wgpu::LoadOp::Clear(0),
+          store: wgpu::StoreOp::Store,
+        }),
+      }),
+      occlusion_query_set: None,
+      timestamp_writes: self
+        .frame_timer
+        .as_ref()
+        .and_then(|timer| timer.render_pass_writes(gpu_timer::SCENE_SPAN, begin, end)),
+    })
+  }
+
+  fn draw_objects<'a>(
+    &'a self,
+    render_pass: &mut wgpu::RenderPass<'a>,
+    camera_bind_group: &'a wgpu::BindGroup,
+    static_bundle: Option<&'a wgpu::RenderBundle>,
+    // Draws the overlay layer's objects instead of everything else
+    overlay: bool,
+    // Of the view being drawn, for the wireframe overlay
+    line_style: (&'a wgpu::BindGroup, u32),
+    stats: &mut render_stats::RenderStats,
+  ) {
+    let debug_pipeline = self.debug_view.pipeline_name();
+    // Debug views swap every object's pipeline, which the bundle can't follow
+    let static_bundle = static_bundle.filter(|_| debug_pipeline.is_none());
+    if let Some(bundle) = static_bundle {
+      render_pass.execute_bundles(iter::once(bundle));
+    }
+
+    // Render all objects - draw method is encapsulated in the object
+    let mut bound_pipeline = None;
+    for &i in &self.draw_order {
+      let obj = &self.objects[i];
+      if !self.is_visible(obj) || obj.is_overlay() != overlay {
+        continue;
+      }
+      let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+      if static_bundle.is_some() && Self::is_bundled(obj) {
+        stats.record_model(&obj.model, obj.instances.len() as u32);
+        continue;
+      }
+
+      if let Some(debug_pipeline) = debug_pipeline.filter(|_| self.pipeline_manager.uses_instances(pipeline_name)) {
+        let Some(pipeline) = self.pipeline_manager.get_by_name(debug_pipeline) else { continue };
+        if bound_pipeline != Some(debug_pipeline) {
+          render_pass.set_pipeline(pipeline);
+          bound_pipeline = Some(debug_pipeline);
+        }
+        render_pass.set_vertex_buffer(1, obj.instance_buffer.slice(..));
+        DrawWithMaterial::draw_model_instanced(
+          render_pass,
+          &obj.model,
+          0..obj.instances.len() as u32,
+          camera_bind_group,
+          &self.light.bind_group,
+          &[],
+        );
+        stats.record_model(&obj.model, obj.instances.len() as u32);
+        continue;
+      }
+
+      if !self.has_declared_bindings(obj, pipeline_name) {
+        continue;
+      }
+      if let Some(pipeline) = self.pipeline_manager.get_by_name(pipeline_name) {
+        if bound_pipeline != Some(pipeline_name) {
+          render_pass.set_pipeline(pipeline);
+          bound_pipeline = Some(pipeline_name);
+        }
+        // Pipelines without an instance layout (shader-positioned meshes) get no instance buffer
+        obj.bind_vertex_buffers(render_pass, self.pipeline_manager.instance_slot(pipeline_name), |slot| {
+          self.pipeline_manager.vertex_step_mode(pipeline_name, slot)
+        });
+        let push_constants = self.pipeline_manager.push_constant_binding(pipeline_name, i);
+        let transform = (pipeline_name == object_transforms::PIPELINE).then(|| self.object_transforms.binding(i));
+        obj.draw(render_pass, camera_bind_group, &self.light.bind_group, push_constants, transform);
+        stats.record_model(&obj.model, obj.instances.len() as u32);
+      }
+    }
+    if overlay {
+      return;
+    }
+
+    let light_mesh = self.light.debug_mesh.as_ref().filter(|_| self.light.debug_mesh_visible);
+    if let (Some(mesh), Some(pipeline)) = (light_mesh, self.pipeline_manager.get_by_name(light::PIPELINE)) {
+      render_pass.set_pipeline(pipeline);
+      render_pass.set_vertex_buffer(1, mesh.instance_buffer.slice(..));
+      mesh.draw(render_pass, camera_bind_group, &self.light.bind_group, None, None);
+      stats.record_model(&mesh.model, mesh.instances.len() as u32);
+    }
+
+    if !self.wireframe_overlay {
+      return;
+    }
+    let (Some(wireframe), Some(pipeline)) = (&self.wireframe, self.pipeline_manager.get_by_name(wireframe::PIPELINE))
+    else {
+      return;
+    };
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, camera_bind_group, &[]);
+    render_pass.set_bind_group(1, line_style.0, &[line_style.1]);
+    for obj in self.objects.iter().filter(|obj| self.is_visible(obj) && !obj.is_overlay()) {
+      // Objects positioned by their shader rather than instances are skipped
+      let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+      if !self.pipeline_manager.uses_instances(pipeline_name) {
+        continue;
+      }
+      render_pass.set_vertex_buffer(0, obj.instance_buffer.slice(..));
+      let instances = 0..obj.instances.len() as u32;
+      for (mesh, bind_group) in obj.model.meshes.iter().zip(wireframe.bind_groups(&obj.model)) {
+        let Some(bind_group) = bind_group else { continue };
+        render_pass.set_bind_group(2, bind_group, &[]);
+        render_pass.draw(0..mesh.triangle_count() * wireframe::VERTICES_PER_TRIANGLE, instances.clone());
+      }
+      stats.record_model(&obj.model, obj.instances.len() as u32);
+    }
+  }
+
+  fn draw_outlines<'a>(
+    &'a self,
+    render_pass: &mut wgpu::RenderPass<'a>,
+    camera_bind_group: &'a wgpu::BindGroup,
+    stats: &mut render_stats::RenderStats,
+  ) {
+    let (Some(mask_pipeline), Some(shell_pipeline)) = (
+      self.pipeline_manager.get_by_name(outline::MASK_PIPELINE),
+      self.pipeline_manager.get_by_name(outline::SHELL_PIPELINE),
+    ) else {
+      return;
+    };
+
+    for (i, outline) in self.outlines.iter().enumerate() {
+      let Some(obj) = self.objects.get(outline.object).filter(|obj| self.is_visible(obj) && !obj.is_overlay()) else { continue };
+      let pipeline_name = obj.pipeline_name.as_deref().unwrap_or("main_pipeline");
+      if !self.pipeline_manager.uses_instances(pipeline_name) {
+        continue;
+      }
+
+      // A separate reference per outline keeps earlier stamps from masking later shells
+      render_pass.set_stencil_reference(i as u32 % 255 + 1);
+      render_pass.set_vertex_buffer(1, obj.instance_buffer.slice(..));
+      let instances = 0..obj.instances.len() as u32;
+
+      render_pass.set_pipeline(mask_pipeline);
+      DrawWithoutMaterial::draw_model_instanced(
+        render_pass,
+        &obj.model,
+        instances.clone(),
+        camera_bind_group,
+        &self.light.bind_group,
+      );
+      stats.record_model(&obj.model, obj.instances.len() as u32);
+
+      render_pass.set_pipeline(shell_pipeline);
+      render_pass.set_bind_group(2, &outline.bind_group, &[]);
+      DrawWithoutMaterial::draw_model_instanced(
+        render_pass,
+        &obj.model,
+        instances,
+        camera_bind_group,
+        &self.light.bind_group,
+      );
+      stats.record_model(&obj.model, obj.instances.len() as u32);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn zero_sizes_never_resize() {
+    assert!(!needs_resize(Some((800, 600)), (0, 0)));
+    assert!(!needs_resize(Some((800, 600)), (0, 600)));
+    assert!(!needs_resize(None, (800, 0)));
+  }
+
+  #[test]
+  fn only_new_sizes_resize() {
+    assert!(!needs_resize(Some((800, 600)), (800, 600)));
+    assert!(needs_resize(Some((800, 600)), (1024, 600)));
+    assert!(needs_resize(None, (800, 600)));
   }
 }
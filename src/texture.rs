@@ -1,6 +1,182 @@
 use image::GenericImageView;
 use anyhow::*;
 
+use crate::ktx2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerOptions {
+  // 1 disables anisotropic filtering; otherwise 2, 4, 8 or 16
+  pub anisotropy: u16,
+  // Repeat/MirrorRepeat let UVs outside 0..1 tile instead of smearing the edge texels
+  pub address_mode_u: wgpu::AddressMode,
+  pub address_mode_v: wgpu::AddressMode,
+  pub mag_filter: wgpu::FilterMode,
+  pub min_filter: wgpu::FilterMode,
+}
+
+impl SamplerOptions {
+  // Point sampling for both magnification and minification, for pixel art and
+  // other textures whose texels should stay hard-edged
+  pub fn nearest() -> Self {
+    Self {
+      mag_filter: wgpu::FilterMode::Nearest,
+      min_filter: wgpu::FilterMode::Nearest,
+      ..Default::default()
+    }
+  }
+
+  // Binding type a layout entry needs to accept samplers made from these options.
+  // Filtering entries take nearest samplers as well, so materials mixing the two
+  // modes can share one layout; NonFiltering entries only take all-nearest ones
+  pub fn binding_type(&self) -> wgpu::SamplerBindingType {
+    let filtering = self.anisotropy > 1
+      || self.mag_filter == wgpu::FilterMode::Linear
+      || self.min_filter == wgpu::FilterMode::Linear;
+    if filtering {
+      wgpu::SamplerBindingType::Filtering
+    } else {
+      wgpu::SamplerBindingType::NonFiltering
+    }
+  }
+
+  pub fn with_address_mode(mut self, address_mode: wgpu::AddressMode) -> Self {
+    self.address_mode_u = address_mode;
+    self.address_mode_v = address_mode;
+    self
+  }
+
+  pub fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+    let anisotropy = self.anisotropy.clamp(1, 16);
+    // wgpu rejects anisotropic samplers unless every filter is linear
+    let (mag_filter, min_filter, mipmap_filter) = if anisotropy > 1 {
+      (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear, wgpu::FilterMode::Linear)
+    } else {
+      (self.mag_filter, self.min_filter, wgpu::FilterMode::Nearest)
+    };
+
+    device.create_sampler(
+      &wgpu::SamplerDescriptor {
+        address_mode_u: self.address_mode_u,
+        address_mode_v: self.address_mode_v,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter,
+        min_filter,
+        mipmap_filter,
+        anisotropy_clamp: anisotropy,
+        ..Default::default()
+      }
+    )
+  }
+}
+
+impl Default for SamplerOptions {
+  fn default() -> Self {
+    Self {
+      anisotropy: 1,
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Nearest,
+    }
+  }
+}
+
+// Big enough for a 2048x2048 RGBA8 level in one staging chunk
+const UPLOAD_CHUNK_SIZE: wgpu::BufferAddress = 16 << 20;
+
+// Texture uploads for a whole model load, staged through one belt and
+// submitted together instead of as many small queue writes
+pub(crate) struct UploadBatch {
+  belt: wgpu::util::StagingBelt,
+  encoder: wgpu::CommandEncoder,
+}
+
+impl UploadBatch {
+  pub fn new(device: &wgpu::Device) -> Self {
+    Self {
+      belt: wgpu::util::StagingBelt::new(UPLOAD_CHUNK_SIZE),
+      encoder: device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Texture Upload Encoder"),
+      }),
+    }
+  }
+
+  pub fn submit(mut self, queue: &wgpu::Queue) {
+    self.belt.finish();
+    queue.submit(std::iter::once(self.encoder.finish()));
+    self.belt.recall();
+  }
+}
+
+// Where texel data goes: straight to the queue, or into an `UploadBatch`
+pub(crate) enum Uploader<'a> {
+  Queue(&'a wgpu::Queue),
+  Batch(&'a mut UploadBatch),
+}
+
+impl Uploader<'_> {
+  // `data` is tightly packed: `rows` rows of `bytes_per_row` bytes (block rows for
+  // compressed formats)
+  #[allow(clippy::too_many_arguments)]
+  fn write_texture(
+    &mut self,
+    device: &wgpu::Device,
+    texture: &wgpu::Texture,
+    mip_level: u32,
+    data: &[u8],
+    bytes_per_row: u32,
+    rows: u32,
+    size: wgpu::Extent3d,
+  ) {
+    let destination = wgpu::TexelCopyTextureInfo {
+      texture,
+      mip_level,
+      origin: wgpu::Origin3d::ZERO,
+      aspect: wgpu::TextureAspect::All,
+    };
+    match self {
+      Uploader::Queue(queue) => queue.write_texture(
+        destination,
+        data,
+        wgpu::TexelCopyBufferLayout {
+          offset: 0,
+          bytes_per_row: Some(bytes_per_row),
+          rows_per_image: Some(rows),
+        },
+        size,
+      ),
+      Uploader::Batch(batch) => {
+        // Buffer-to-texture copies need rows padded to the copy alignment
+        let padded_bytes_per_row = bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let staged_size = padded_bytes_per_row as wgpu::BufferAddress * rows as wgpu::BufferAddress;
+        let Some(staged_size) = wgpu::BufferSize::new(staged_size) else { return };
+        let alignment = wgpu::BufferSize::new(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress)
+          .expect("the copy alignment is nonzero");
+        let slice = batch.belt.allocate(staged_size, alignment, device);
+        {
+          let mut view = slice.get_mapped_range_mut();
+          for (row, src) in data.chunks_exact(bytes_per_row as usize).take(rows as usize).enumerate() {
+            let start = row * padded_bytes_per_row as usize;
+            view[start..start + src.len()].copy_from_slice(src);
+          }
+        }
+        batch.encoder.copy_buffer_to_texture(
+          wgpu::TexelCopyBufferInfo {
+            buffer: slice.buffer(),
+            layout: wgpu::TexelCopyBufferLayout {
+              offset: slice.offset(),
+              bytes_per_row: Some(padded_bytes_per_row),
+              rows_per_image: Some(rows),
+            },
+          },
+          destination,
+          size,
+        );
+      }
+    }
+  }
+}
+
 pub struct Texture {
   #[allow(unused)]
   pub texture: wgpu::Texture,
@@ -10,8 +186,19 @@ pub struct Texture {
 
 impl Texture {
   pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+  pub const DEPTH_STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
 
   pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    Self::create_depth_texture_with_format(device, config, Self::DEPTH_FORMAT, 1, label)
+  }
+
+  pub fn create_depth_texture_with_format(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    label: &str,
+  ) -> Self {
     let size = wgpu::Extent3d {
       width: config.width,
       height: config.height,
@@ -21,9 +208,9 @@ impl Texture {
       label: Some(label),
       size,
       mip_level_count: 1,
-      sample_count: 1,
+      sample_count,
       dimension: wgpu::TextureDimension::D2,
-      format: Self::DEPTH_FORMAT,
+      format,
       usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
       view_formats: &[],
     };
@@ -53,9 +240,43 @@ impl Texture {
     bytes: &[u8],
     label: &str,
     is_normal_map: bool,
+  ) -> Result<Self> {
+    Self::from_bytes_with_options(device, queue, bytes, label, is_normal_map, SamplerOptions::default())
+  }
+
+  pub fn from_bytes_with_options(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    bytes: &[u8],
+    label: &str,
+    is_normal_map: bool,
+    sampler_options: SamplerOptions,
+  ) -> Result<Self> {
+    Self::from_bytes_with_uploader(device, &mut Uploader::Queue(queue), bytes, label, is_normal_map, sampler_options)
+  }
+
+  pub(crate) fn from_bytes_with_uploader(
+    device: &wgpu::Device,
+    uploader: &mut Uploader<'_>,
+    bytes: &[u8],
+    label: &str,
+    is_normal_map: bool,
+    sampler_options: SamplerOptions,
   ) -> Result<Self> {
     let img = image::load_from_memory(bytes)?;
+    Self::from_image_with_uploader(device, uploader, &img, Some(label), is_normal_map, sampler_options)
+  }
+
+  pub fn from_color(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    color: [u8; 4],
+    label: &str,
+    is_normal_map: bool,
+  ) -> Self {
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
     Self::from_image(device, queue, &img, Some(label), is_normal_map)
+      .expect("a 1x1 RGBA image is always a valid texture")
   }
 
   pub fn from_image(
@@ -64,6 +285,28 @@ impl Texture {
     img: &image::DynamicImage,
     label: Option<&str>,
     is_normal_map: bool,
+  ) -> Result<Self> {
+    Self::from_image_with_options(device, queue, img, label, is_normal_map, SamplerOptions::default())
+  }
+
+  pub fn from_image_with_options(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    img: &image::DynamicImage,
+    label: Option<&str>,
+    is_normal_map: bool,
+    sampler_options: SamplerOptions,
+  ) -> Result<Self> {
+    Self::from_image_with_uploader(device, &mut Uploader::Queue(queue), img, label, is_normal_map, sampler_options)
+  }
+
+  fn from_image_with_uploader(
+    device: &wgpu::Device,
+    uploader: &mut Uploader<'_>,
+    img: &image::DynamicImage,
+    label: Option<&str>,
+    is_normal_map: bool,
+    sampler_options: SamplerOptions,
   ) -> Result<Self> {
     let rgba = img.to_rgba8();
     let dimensions = img.dimensions();
@@ -85,40 +328,154 @@ impl Texture {
         mip_level_count: 1,
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
-        format: format,
+        format,
         usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         view_formats: &[],
       });
 
-    queue.write_texture(
-      wgpu::TexelCopyTextureInfo {
-        texture: &texture,
-        mip_level: 0,
-        origin: wgpu::Origin3d::ZERO,
-        aspect: wgpu::TextureAspect::All,
-      },
-      &rgba,
-      wgpu::TexelCopyBufferLayout {
-        offset: 0,
-        bytes_per_row: Some(4 * dimensions.0),
-        rows_per_image: Some(dimensions.1),
-      },
-      size,
-    );
+    uploader.write_texture(device, &texture, 0, &rgba, 4 * dimensions.0, dimensions.1, size);
 
     let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-    let sampler = device.create_sampler(
-      &wgpu::SamplerDescriptor {
-        address_mode_u: wgpu::AddressMode::ClampToEdge,
-        address_mode_v: wgpu::AddressMode::ClampToEdge,
-        address_mode_w: wgpu::AddressMode::ClampToEdge,
-        mag_filter: wgpu::FilterMode::Linear,
-        min_filter: wgpu::FilterMode::Nearest,
-        mipmap_filter: wgpu::FilterMode::Nearest,
-        ..Default::default()
+    let sampler = sampler_options.create_sampler(device);
+
+    Ok(Self { texture, view, sampler })
+  }
+
+  // Raw texels in any uncompressed float `format`, tightly packed row by row, with
+  // a nearest sampler: data textures such as heightmaps or lookup tables. Formats
+  // that can't be filtered (R32Float and the like) bind to the layout from
+  // State::texture_bind_group_layout_for(SamplerBindingType::NonFiltering).
+  // Integer and depth formats are refused, since every material layout binds
+  // float textures; store integer data as unfilterable float texels instead
+  pub fn from_data(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    data: &[u8],
+    (width, height): (u32, u32),
+    format: wgpu::TextureFormat,
+    label: &str,
+  ) -> Result<Self> {
+    let Some(texel_size) = format.block_copy_size(None).filter(|_| format.block_dimensions() == (1, 1)) else {
+      bail!("{}: {:?} isn't an uncompressed color format", label, format);
+    };
+    // Material layouts only take float textures, and the nearest sampler only works on those
+    if !matches!(format.sample_type(None, None), Some(wgpu::TextureSampleType::Float { .. })) {
+      bail!("{}: {:?} isn't sampled as float", label, format);
+    }
+    if width == 0 || height == 0 {
+      bail!("{}: {}x{} texture has no texels", label, width, height);
+    }
+    let Some(bytes_per_row) = width.checked_mul(texel_size) else {
+      bail!("{}: {} {:?} texels don't fit in a row", label, width, format);
+    };
+    let expected = bytes_per_row as u64 * height as u64;
+    if data.len() as u64 != expected {
+      bail!("{}: {}x{} {:?} texels take {} bytes, got {}", label, width, height, format, expected, data.len());
+    }
+
+    let size = wgpu::Extent3d {
+      width,
+      height,
+      depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(
+      &wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+      });
+
+    Uploader::Queue(queue).write_texture(device, &texture, 0, data, bytes_per_row, height, size);
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = SamplerOptions::nearest().create_sampler(device);
+
+    Ok(Self { texture, view, sampler })
+  }
+
+  // Uploads the file's blocks (and mip chain) directly, so BCn/ETC2/ASTC data stays
+  // compressed on the GPU. Fails if the device lacks the format's compression feature
+  pub fn from_ktx2_bytes(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    bytes: &[u8],
+    label: &str,
+    sampler_options: SamplerOptions,
+  ) -> Result<Self> {
+    Self::from_ktx2_with_uploader(device, &mut Uploader::Queue(queue), bytes, label, sampler_options)
+  }
+
+  pub(crate) fn from_ktx2_with_uploader(
+    device: &wgpu::Device,
+    uploader: &mut Uploader<'_>,
+    bytes: &[u8],
+    label: &str,
+    sampler_options: SamplerOptions,
+  ) -> Result<Self> {
+    let ktx2 = ktx2::Ktx2::parse(bytes).with_context(|| format!("failed to load {}", label))?;
+    let format = ktx2.format;
+
+    let missing_features = format.required_features() - device.features();
+    if !missing_features.is_empty() {
+      bail!("{}: {:?} textures need {:?}, which this device doesn't support", label, format, missing_features);
+    }
+    let (block_width, block_height) = format.block_dimensions();
+    if ktx2.width % block_width != 0 || ktx2.height % block_height != 0 {
+      bail!(
+        "{}: {}x{} isn't a multiple of the {}x{} block size",
+        label, ktx2.width, ktx2.height, block_width, block_height,
+      );
+    }
+
+    let size = wgpu::Extent3d {
+      width: ktx2.width,
+      height: ktx2.height,
+      depth_or_array_layers: 1,
+    };
+    let mip_level_count = (ktx2.levels.len() as u32).min(size.max_mips(wgpu::TextureDimension::D2));
+    let texture = device.create_texture(
+      &wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+      });
+
+    let block_size = format.block_copy_size(None).expect("sampled formats have a block size");
+    for (level, data) in ktx2.levels.iter().take(mip_level_count as usize).enumerate() {
+      let level_size = size
+        .mip_level_size(level as u32, wgpu::TextureDimension::D2)
+        .physical_size(format);
+      let blocks_wide = level_size.width / block_width;
+      let blocks_high = level_size.height / block_height;
+      let expected = (blocks_wide * blocks_high * block_size) as usize;
+      if data.len() < expected {
+        bail!("{}: mip level {} has {} bytes, expected {}", label, level, data.len(), expected);
       }
-    );
+
+      uploader.write_texture(
+        device,
+        &texture,
+        level as u32,
+        &data[..expected],
+        blocks_wide * block_size,
+        blocks_high,
+        level_size,
+      );
+    }
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = sampler_options.create_sampler(device);
 
     Ok(Self { texture, view, sampler })
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,324 @@
+use wgpu::util::DeviceExt;
+
+// The scene is rendered into this linear target, then tone mapped onto the swapchain
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMap {
+  // Clamps to 0..1, which matches rendering straight to the swapchain
+  #[default]
+  None,
+  Reinhard,
+  Aces,
+}
+
+impl ToneMap {
+  fn shader_mode(self) -> u32 {
+    match self {
+      ToneMap::None => 0,
+      ToneMap::Reinhard => 1,
+      ToneMap::Aces => 2,
+    }
+  }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMapUniform {
+  exposure: f32,
+  mode: u32,
+  _padding: [u32; 2],
+}
+
+pub struct ToneMapPass {
+  pipeline: wgpu::RenderPipeline,
+  bind_group_layout: wgpu::BindGroupLayout,
+  sampler: wgpu::Sampler,
+  tone_map: ToneMap,
+  uniform: ToneMapUniform,
+  uniform_buffer: wgpu::Buffer,
+  hdr_texture: wgpu::Texture,
+  hdr_view: wgpu::TextureView,
+  // With MSAA the scene renders here and resolves into `hdr_view`
+  msaa_view: Option<wgpu::TextureView>,
+  sample_count: u32,
+  // Off leaves the resolve into `hdr_view` to a custom pass
+  auto_resolve: bool,
+  bind_group: wgpu::BindGroup,
+}
+
+impl ToneMapPass {
+  pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat, width: u32, height: u32) -> Self {
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      entries: &[
+        wgpu::BindGroupLayoutEntry {
+          binding: 0,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+          },
+          count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+          binding: 1,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+          count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+          binding: 2,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+      ],
+      label: Some("tonemap_bind_group_layout"),
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("Tone Map Pipeline Layout"),
+      bind_group_layouts: &[&bind_group_layout],
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some("Tone Map Shader"),
+      source: wgpu::ShaderSource::Wgsl(
+        concat!(include_str!("../shaders/fullscreen.wgsl"), include_str!("../shaders/tonemap.wgsl")).into(),
+      ),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Tone Map Pipeline"),
+      layout: Some(&layout),
+      vertex: wgpu::VertexState {
+        module: &shader,
+        entry_point: Some("vs_main"),
+        buffers: &[],
+        compilation_options: Default::default(),
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: &shader,
+        entry_point: Some("fs_main"),
+        targets: &[Some(wgpu::ColorTargetState {
+          format: output_format,
+          blend: Some(wgpu::BlendState::REPLACE),
+          write_mask: wgpu::ColorWrites::ALL,
+        })],
+        compilation_options: Default::default(),
+      }),
+      primitive: wgpu::PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: wgpu::MultisampleState::default(),
+      multiview: None,
+      cache: None,
+    });
+
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      label: Some("Tone Map Sampler"),
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Linear,
+      ..Default::default()
+    });
+
+    let uniform = ToneMapUniform {
+      exposure: 1.0,
+      mode: ToneMap::default().shader_mode(),
+      _padding: [0; 2],
+    };
+    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Tone Map Buffer"),
+      contents: bytemuck::cast_slice(&[uniform]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let (hdr_texture, hdr_view, bind_group) =
+      Self::create_target(device, &bind_group_layout, &sampler, &uniform_buffer, width, height);
+
+    Self {
+      pipeline,
+      bind_group_layout,
+      sampler,
+      tone_map: ToneMap::default(),
+      uniform,
+      uniform_buffer,
+      hdr_texture,
+      hdr_view,
+      msaa_view: None,
+      sample_count: 1,
+      auto_resolve: true,
+      bind_group,
+    }
+  }
+
+  fn create_msaa_view(device: &wgpu::Device, width: u32, height: u32, sample_count: u32) -> Option<wgpu::TextureView> {
+    if sample_count <= 1 {
+      return None;
+    }
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("hdr_msaa_texture"),
+      size: wgpu::Extent3d {
+        width: width.max(1),
+        height: height.max(1),
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count,
+      dimension: wgpu::TextureDimension::D2,
+      format: HDR_FORMAT,
+      // Bindable as texture_multisampled_2d for passes that resolve it themselves
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+      view_formats: &[],
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+  }
+
+  fn create_target(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    uniform_buffer: &wgpu::Buffer,
+    width: u32,
+    height: u32,
+  ) -> (wgpu::Texture, wgpu::TextureView, wgpu::BindGroup) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some("hdr_texture"),
+      size: wgpu::Extent3d {
+        width: width.max(1),
+        height: height.max(1),
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: HDR_FORMAT,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+      view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout,
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::TextureView(&view),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: wgpu::BindingResource::Sampler(sampler),
+        },
+        wgpu::BindGroupEntry {
+          binding: 2,
+          resource: uniform_buffer.as_entire_binding(),
+        },
+      ],
+      label: Some("tonemap_bind_group"),
+    });
+
+    (texture, view, bind_group)
+  }
+
+  pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+    let size = self.hdr_texture.size();
+    if size.width == width && size.height == height {
+      return;
+    }
+    let (hdr_texture, hdr_view, bind_group) = Self::create_target(
+      device,
+      &self.bind_group_layout,
+      &self.sampler,
+      &self.uniform_buffer,
+      width,
+      height,
+    );
+    self.hdr_texture = hdr_texture;
+    self.hdr_view = hdr_view;
+    self.bind_group = bind_group;
+    self.msaa_view = Self::create_msaa_view(device, width, height, self.sample_count);
+  }
+
+  pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+    if self.sample_count == sample_count {
+      return;
+    }
+    self.sample_count = sample_count;
+    let size = self.hdr_texture.size();
+    self.msaa_view = Self::create_msaa_view(device, size.width, size.height, sample_count);
+  }
+
+  // Where the scene passes draw, and what they resolve into with MSAA
+  pub fn scene_target(&self) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+    match &self.msaa_view {
+      Some(msaa_view) => (msaa_view, self.auto_resolve.then_some(&self.hdr_view)),
+      None => (&self.hdr_view, None),
+    }
+  }
+
+  // Multisampled scene color, `None` without MSAA
+  pub fn msaa_view(&self) -> Option<&wgpu::TextureView> {
+    self.msaa_view.as_ref()
+  }
+
+  pub fn auto_resolve(&self) -> bool {
+    self.auto_resolve
+  }
+
+  pub fn set_auto_resolve(&mut self, auto_resolve: bool) {
+    self.auto_resolve = auto_resolve;
+  }
+
+  // Single-sampled scene color, resolved when MSAA is on
+  pub fn hdr_view(&self) -> &wgpu::TextureView {
+    &self.hdr_view
+  }
+
+  pub fn tone_map(&self) -> ToneMap {
+    self.tone_map
+  }
+
+  pub fn set_tone_map(&mut self, queue: &wgpu::Queue, tone_map: ToneMap) {
+    self.tone_map = tone_map;
+    self.uniform.mode = tone_map.shader_mode();
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+  }
+
+  pub fn exposure(&self) -> f32 {
+    self.uniform.exposure
+  }
+
+  pub fn set_exposure(&mut self, queue: &wgpu::Queue, exposure: f32) {
+    self.uniform.exposure = exposure.max(0.0);
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+  }
+
+  pub fn run(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    output: &wgpu::TextureView,
+    timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+  ) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("Tone Map Pass"),
+      color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+        view: output,
+        resolve_target: None,
+        ops: wgpu::Operations {
+          load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+          store: wgpu::StoreOp::Store,
+        },
+        depth_slice: None,
+      })],
+      depth_stencil_attachment: None,
+      occlusion_query_set: None,
+      timestamp_writes,
+    });
+    render_pass.set_pipeline(&self.pipeline);
+    render_pass.set_bind_group(0, &self.bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+  }
+}
@@ -1,3 +1,6 @@
+use std::marker::PhantomData;
+use std::num::NonZeroU64;
+
 use cgmath::prelude::*;
 
 use crate::camera;
@@ -18,7 +21,7 @@ impl CameraUniform {
 
   pub fn update_view_proj(&mut self, camera: &camera::Camera, projection: &camera::Projection) {
     self.view_position = camera.position.to_homogeneous().into();
-    self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into();
+    self.view_proj = camera::view_proj_matrix(camera, projection).into();
   }
 }
 
@@ -26,4 +29,113 @@ impl Default for CameraUniform {
   fn default() -> Self {
     Self::new()
   }
-}
\ No newline at end of file
+}
+// A uniform buffer of `T` slots, one bound at a time through a dynamic offset, for
+// per-object or per-view values drawn with a shared bind group. Slots are padded
+// to the device's offset alignment, and the buffer grows as more are written
+pub(crate) struct DynamicUniformSlots<T> {
+  pub layout: wgpu::BindGroupLayout,
+  buffer: wgpu::Buffer,
+  bind_group: wgpu::BindGroup,
+  stride: wgpu::BufferAddress,
+  capacity: usize,
+  label: &'static str,
+  _slot: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> DynamicUniformSlots<T> {
+  const SIZE: wgpu::BufferAddress = std::mem::size_of::<T>() as wgpu::BufferAddress;
+
+  // `label` names the layout, buffer and bind group
+  pub fn new(device: &wgpu::Device, visibility: wgpu::ShaderStages, capacity: usize, label: &'static str) -> Self {
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      entries: &[wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: true,
+          min_binding_size: NonZeroU64::new(Self::SIZE),
+        },
+        count: None,
+      }],
+      label: Some(&format!("{label}_layout")),
+    });
+
+    let alignment = device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress;
+    let stride = Self::SIZE.div_ceil(alignment) * alignment;
+    let (buffer, bind_group) = Self::create_buffer(device, &layout, stride, capacity, label);
+
+    Self {
+      layout,
+      buffer,
+      bind_group,
+      stride,
+      capacity,
+      label,
+      _slot: PhantomData,
+    }
+  }
+
+  fn create_buffer(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    stride: wgpu::BufferAddress,
+    capacity: usize,
+    label: &str,
+  ) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some(&format!("{label}_buffer")),
+      size: stride * capacity as wgpu::BufferAddress,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+          buffer: &buffer,
+          offset: 0,
+          size: NonZeroU64::new(Self::SIZE),
+        }),
+      }],
+      label: Some(&format!("{label}_bind_group")),
+    });
+
+    (buffer, bind_group)
+  }
+
+  // Uploads the slots from the start of the buffer, growing it if there are more
+  // than fit
+  pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, slots: impl ExactSizeIterator<Item = T>) {
+    let count = slots.len();
+    if count == 0 {
+      return;
+    }
+
+    if count > self.capacity {
+      let capacity = count.next_power_of_two();
+      let (buffer, bind_group) = Self::create_buffer(device, &self.layout, self.stride, capacity, self.label);
+      self.buffer = buffer;
+      self.bind_group = bind_group;
+      self.capacity = capacity;
+    }
+
+    let mut data = vec![0u8; self.stride as usize * count];
+    for (i, slot) in slots.enumerate() {
+      let start = i * self.stride as usize;
+      data[start..start + Self::SIZE as usize].copy_from_slice(bytemuck::bytes_of(&slot));
+    }
+    queue.write_buffer(&self.buffer, 0, &data);
+  }
+
+  pub fn bind_group(&self) -> &wgpu::BindGroup {
+    &self.bind_group
+  }
+
+  pub fn offset(&self, slot: usize) -> u32 {
+    (slot as wgpu::BufferAddress * self.stride) as u32
+  }
+}
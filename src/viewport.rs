@@ -0,0 +1,98 @@
+use wgpu::util::DeviceExt;
+
+use crate::{camera, uniforms};
+
+// Rectangle in normalized window coordinates (0..1, origin top-left) so it
+// follows the window through resizes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewportRect {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32,
+}
+
+impl ViewportRect {
+  pub const FULL: Self = Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+
+  pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+    Self { x, y, width, height }
+  }
+
+  // Returns (x, y, width, height) in pixels, clamped to a target of the given size
+  pub fn to_pixels(&self, target_width: u32, target_height: u32) -> (f32, f32, f32, f32) {
+    let (w, h) = (target_width as f32, target_height as f32);
+    let x = (self.x * w).clamp(0.0, w);
+    let y = (self.y * h).clamp(0.0, h);
+    let width = (self.width * w).clamp(0.0, w - x);
+    let height = (self.height * h).clamp(0.0, h - y);
+    (x, y, width, height)
+  }
+
+  pub fn pixel_size(&self, target_width: u32, target_height: u32) -> (u32, u32) {
+    let (_, _, width, height) = self.to_pixels(target_width, target_height);
+    ((width as u32).max(1), (height as u32).max(1))
+  }
+}
+
+impl Default for ViewportRect {
+  fn default() -> Self {
+    Self::FULL
+  }
+}
+
+pub struct Viewport {
+  pub rect: ViewportRect,
+  pub camera: camera::Camera,
+  pub projection: camera::Projection,
+  camera_uniform: uniforms::CameraUniform,
+  camera_buffer: wgpu::Buffer,
+  pub camera_bind_group: wgpu::BindGroup,
+}
+
+impl Viewport {
+  pub fn new(
+    device: &wgpu::Device,
+    camera_bind_group_layout: &wgpu::BindGroupLayout,
+    rect: ViewportRect,
+    camera: camera::Camera,
+    projection: camera::Projection,
+  ) -> Self {
+    let mut camera_uniform = uniforms::CameraUniform::new();
+    camera_uniform.update_view_proj(&camera, &projection);
+
+    let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Viewport Camera Buffer"),
+      contents: bytemuck::cast_slice(&[camera_uniform]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: camera_bind_group_layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: camera_buffer.as_entire_binding(),
+      }],
+      label: Some("viewport_camera_bind_group"),
+    });
+
+    Self {
+      rect,
+      camera,
+      projection,
+      camera_uniform,
+      camera_buffer,
+      camera_bind_group,
+    }
+  }
+
+  pub fn resize(&mut self, width: u32, height: u32) {
+    let (width, height) = self.rect.pixel_size(width, height);
+    self.projection.resize(width, height);
+  }
+
+  pub fn update(&mut self, queue: &wgpu::Queue) {
+    self.camera_uniform.update_view_proj(&self.camera, &self.projection);
+    queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+  }
+}
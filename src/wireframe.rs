@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+use crate::model;
+
+pub const PIPELINE: &str = "wireframe_pipeline";
+
+// Each triangle's three edges are drawn as quads of six vertices
+pub const VERTICES_PER_TRIANGLE: u32 = 18;
+
+// The wireframe shader pulls positions and indices from storage buffers, which
+// WebGL2 and some downlevel backends can't read in a vertex shader
+pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+  adapter
+    .get_downlevel_capabilities()
+    .flags
+    .contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+    && device.limits().max_storage_buffers_per_shader_stage >= 2
+}
+
+fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+  let storage = |binding| wgpu::BindGroupLayoutEntry {
+    binding,
+    visibility: wgpu::ShaderStages::VERTEX,
+    ty: wgpu::BindingType::Buffer {
+      ty: wgpu::BufferBindingType::Storage { read_only: true },
+      has_dynamic_offset: false,
+      min_binding_size: None,
+    },
+    count: None,
+  };
+  device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+    entries: &[
+      wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      },
+      storage(1),
+      storage(2),
+    ],
+    label: Some("wireframe_bind_group_layout"),
+  })
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MeshLayoutUniform {
+  vertex_stride: u32,
+  compact: u32,
+  index_u16: u32,
+  _padding: u32,
+}
+
+// Per-mesh bind groups exposing the vertex and index buffers to the wireframe
+// shader, built the first time a model is drawn and dropped with it
+pub(crate) struct WireframeBindings {
+  pub layout: wgpu::BindGroupLayout,
+  // Keyed by the model's address; the Arc keeps that address from being reused.
+  // Meshes too big to bind as storage have no entry and are skipped
+  models: HashMap<usize, (Arc<model::Model>, Vec<Option<wgpu::BindGroup>>)>,
+}
+
+impl WireframeBindings {
+  pub fn new(device: &wgpu::Device) -> Self {
+    Self {
+      layout: create_bind_group_layout(device),
+      models: HashMap::new(),
+    }
+  }
+
+  // Builds bind groups for models not seen before and forgets the ones no
+  // longer in `models`
+  pub fn prepare<'a>(&mut self, device: &wgpu::Device, models: impl Iterator<Item = &'a Arc<model::Model>>) {
+    let mut live = HashMap::new();
+    for model in models {
+      let key = Arc::as_ptr(model) as usize;
+      let entry = self
+        .models
+        .remove(&key)
+        .unwrap_or_else(|| (model.clone(), model.meshes.iter().map(|mesh| self.create_bind_group(device, mesh)).collect()));
+      live.insert(key, entry);
+    }
+    self.models = live;
+  }
+
+  pub fn clear(&mut self) {
+    self.models.clear();
+  }
+
+  fn create_bind_group(&self, device: &wgpu::Device, mesh: &model::Mesh) -> Option<wgpu::BindGroup> {
+    let max_size = device.limits().max_storage_buffer_binding_size as wgpu::BufferAddress;
+    let storage = wgpu::BufferUsages::STORAGE;
+    let buffers = [&mesh.vertex_buffer, &mesh.index_buffer];
+    if buffers.iter().any(|buffer| !buffer.usage().contains(storage) || buffer.size() > max_size) {
+      return None;
+    }
+
+    let uniform = MeshLayoutUniform {
+      vertex_stride: (mesh.vertex_format.layout().array_stride / mem::size_of::<u32>() as wgpu::BufferAddress) as u32,
+      compact: (mesh.vertex_format == model::VertexFormatOption::Compact) as u32,
+      index_u16: (mesh.index_format == wgpu::IndexFormat::Uint16) as u32,
+      _padding: 0,
+    };
+    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Wireframe Mesh Layout Buffer"),
+      contents: bytemuck::bytes_of(&uniform),
+      usage: wgpu::BufferUsages::UNIFORM,
+    });
+    Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &self.layout,
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: uniform_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: mesh.vertex_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 2,
+          resource: mesh.index_buffer.as_entire_binding(),
+        },
+      ],
+      label: Some("wireframe_bind_group"),
+    }))
+  }
+
+  // One entry per mesh of `model`, None for meshes that can't be drawn;
+  // empty for models not passed to `prepare`
+  pub fn bind_groups(&self, model: &Arc<model::Model>) -> &[Option<wgpu::BindGroup>] {
+    self
+      .models
+      .get(&(Arc::as_ptr(model) as usize))
+      .map_or(&[], |(_, bind_groups)| bind_groups.as_slice())
+  }
+}